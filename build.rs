@@ -32,6 +32,10 @@ fn main() {
     }
 
     println!("cargo:rustc-env=VERSION={}", env);
+    println!(
+        "cargo:rustc-env=AUTOBUILD_BUILD_TARGET={}",
+        std::env::var("TARGET").unwrap()
+    );
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=src/targ/guess.data");
 }
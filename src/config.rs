@@ -6,6 +6,9 @@ use std::env::VarError;
 use std::ffi::OsStr;
 use std::fs::File;
 use std::path::Path;
+use std::process::Command;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime};
 use std::{path::PathBuf, str::FromStr};
 
 use io::Read as _;
@@ -15,20 +18,24 @@ use serde_derive::{Deserialize, Serialize};
 
 use target_tuples::Target;
 
-use crate::hash::sha::Sha64State;
-use crate::hash::{self, FileHash};
-use crate::helpers::{which, FormatString};
+use crate::fs::FileLock;
+use crate::hash::{FileHash, HashAlgorithm};
+use crate::helpers::{which, FormatArg, FormatSpec, FormatString};
 use crate::install::InstallDirs;
 use crate::log::trace;
 use crate::map::OrderedMap;
 use crate::programs::rustc;
 use crate::rand::Rand;
+use crate::set::OrderedSet;
+
+use script::BuildScriptTaskTiming;
 
 pub mod script;
+pub mod site;
 
 mod store;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ConfigVarValue {
     Set,
     Unset,
@@ -113,7 +120,7 @@ impl<'de> serde::Deserialize<'de> for ConfigVarValue {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
 pub struct ConfigInstallDirs {
     #[serde(flatten)]
     pub install_dirs: InstallDirs,
@@ -129,13 +136,127 @@ pub struct ConfigTargets {
     pub others: OrderedMap<String, Target>,
 }
 
+impl ConfigTargets {
+    /// The names that can't be used for targets in `others`
+    pub const RESERVED_NAMES: [&'static str; 3] = ["build", "host", "target"];
+
+    /// The keys available to a [`FormatString`] naming a target: `build`, `host`, `target`, and each named target
+    pub fn format_keys(&self) -> HashMap<&str, &str> {
+        let mut keys = HashMap::new();
+        keys.insert("build", self.build.get_name());
+        keys.insert("host", self.host.get_name());
+        keys.insert("target", self.target.get_name());
+        for (name, targ) in &self.others {
+            keys.insert(&**name, targ.get_name());
+        }
+        keys
+    }
+
+    /// The target named `name`: `build`, `host`, `target`, or a named target
+    pub fn get(&self, name: &str) -> Option<&Target> {
+        match name {
+            "build" => Some(&self.build),
+            "host" => Some(&self.host),
+            "target" => Some(&self.target),
+            name => self.others.get(name),
+        }
+    }
+}
+
+/// Whether programs built for `targ` run directly on `build`, such as i686 programs on x86_64
+fn runs_natively(build: &Target, targ: &Target) -> bool {
+    use target_tuples::Architecture;
+
+    build.operating_system() == targ.operating_system()
+        && (build.arch() == targ.arch()
+            || (build.arch() == Architecture::X86_64 && targ.arch().is_x86()))
+}
+
+/// The programs searched for to run binaries built for `targ` on `build`, such as `qemu-aarch64` or `wine`.
+///
+/// There are none if `build` runs them natively
+fn runner_names(build: &Target, targ: &Target) -> Vec<String> {
+    use target_tuples::{Architecture, OS};
+
+    if runs_natively(build, targ) {
+        return Vec::new();
+    }
+
+    match targ.operating_system() {
+        Some(OS::Win32) if targ.arch() == Architecture::X86_64 => {
+            return vec!["wine64".to_string(), "wine".to_string()]
+        }
+        Some(OS::Win32) => return vec!["wine".to_string()],
+        Some(OS::WASI) => return vec!["wasmtime".to_string()],
+        Some(OS::Linux) => {}
+        _ => return Vec::new(),
+    }
+
+    // qemu's user-mode emulators don't all use the canonical name of the architecture
+    let arch = match targ.arch() {
+        arch if arch.is_x86() => "i386",
+        Architecture::PowerPC32 => "ppc",
+        Architecture::PowerPC64 => "ppc64",
+        Architecture::PowerPC64le => "ppc64le",
+        Architecture::SparcV9 => "sparc64",
+        arch => arch.canonical_name(),
+    };
+
+    vec![format!("qemu-{}", arch), format!("qemu-{}-static", arch)]
+}
+
+/// An extra named target declared by a manifest, which is set with `--target-alias NAME=TUPLE`
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct NamedTargetSpec {
+    /// The target used if none is given on the command line. May refer to `{build}`, `{host}`, `{target}`, or other named targets
+    pub default: Option<FormatString>,
+    pub help: Option<String>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ConfigFoundProgram {
     pub location: PathBuf,
+    /// A launcher (such as sccache) that compiles are run through
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wrapper: Option<ProgramWrapper>,
     #[serde(flatten)]
     pub info: Option<ConfigProgramInfo>,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProgramWrapper {
+    pub location: PathBuf,
+    pub hash: FileHash,
+}
+
+impl ConfigFoundProgram {
+    /// A command that runs the program, through its wrapper if it has one
+    pub fn command(&self) -> Command {
+        match &self.wrapper {
+            Some(wrapper) => {
+                let mut cmd = Command::new(&wrapper.location);
+                cmd.arg(&self.location);
+                cmd
+            }
+            None => Command::new(&self.location),
+        }
+    }
+
+    /// Computes a key identifying the program and its wrapper, so that outputs are rebuilt when either changes
+    pub fn rebuild_key(&self, alg: HashAlgorithm, key: FileHash) -> io::Result<FileHash> {
+        let mut id = self.location.as_os_str().as_encoded_bytes().to_vec();
+        if let Some(wrapper) = &self.wrapper {
+            id.push(0);
+            id.extend_from_slice(wrapper.location.as_os_str().as_encoded_bytes());
+            id.push(0);
+            id.extend_from_slice(wrapper.hash.as_ref());
+        }
+
+        alg.hash_reader(&*id, key)
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum ConfigProgramInfo {
     Rustc(rustc::RustcVersion),
@@ -150,6 +271,25 @@ pub struct TargetName {
     pub name: String,
 }
 
+impl TargetName {
+    /// Parses a target named on the command line, as `path:name`, or `name` for a target of the root manifest
+    pub fn from_arg(name: &str) -> io::Result<Self> {
+        if name.contains(':') {
+            name.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid target name {}", name),
+                )
+            })
+        } else {
+            Ok(Self {
+                base_path: PathBuf::new(),
+                name: name.to_string(),
+            })
+        }
+    }
+}
+
 impl core::fmt::Display for TargetName {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.base_path.display().fmt(f)?;
@@ -213,10 +353,18 @@ pub struct Artifact {
     pub path: PathBuf,
     pub deps: Vec<PathBuf>,
     pub target: TargetName,
+    /// For an artifact of a multi-target build, the name of the target it was built for.
+    /// Such artifacts are installed into the directories for that target (see [`InstallDirs::set_target`]).
+    #[serde(default)]
+    pub tuple: Option<String>,
     #[serde(default)]
     pub aliases: Vec<PathBuf>,
     #[serde(default)]
     pub install_base: Option<FormatString>,
+    /// The rpath relative to the build directory that the artifact was linked with (see [`set_local_rpath`](crate::programs::rpath::set_local_rpath)),
+    /// which is rewritten when it is installed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_rpath: Option<String>,
 }
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct SubdirInfo {}
@@ -225,8 +373,29 @@ pub struct SubdirInfo {}
 #[serde(rename_all = "kebab-case")]
 pub struct BuildInfo {
     pub compiler_name: String,
+    /// The main source file (for rustc, the crate root), relative to the source directory of the target
+    #[serde(default)]
+    pub src: PathBuf,
+    /// The kind of output, such as `rlib` or `bin`, given to the compiler as [`CompileTaskType::raw_build_type`](crate::programs::CompileTaskType::raw_build_type)
+    #[serde(default)]
+    pub build_type: String,
     pub primary_artifacts: Vec<PathBuf>,
     pub secondary_artifacts: Vec<PathBuf>,
+    /// Set if the primary artifact is a test harness, run by `autobuild test`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub test: Option<BuildTestInfo>,
+    /// Set for a binary with `set-local-rpath`, which is linked with a rpath that finds the dynamic libraries it depends on in the
+    ///  build directory
+    #[serde(default)]
+    pub local_rpath: bool,
+    /// Set if the primary artifact is run by `autobuild build` once it is built: a build script, or a binary with `run-target`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<BuildScriptTaskTiming>,
+    /// Identifies the compiler, its wrapper, and the inputs that the outputs were last built from by `autobuild build`.
+    ///
+    /// The target is rebuilt when any of them changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rebuild_key: Option<FileHash>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -243,9 +412,109 @@ pub struct BuildTargetInfo {
     pub deps: Vec<TargetName>,
     #[serde(flatten)]
     pub step: BuildTargetStep,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<TargetInstance>,
 }
 
+/// The target tuple that an instance of a multi-target build (a target with `tuples`) is built for
 #[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TargetInstance {
+    /// The name of the target in [`ConfigTargets`] - `build`, `host`, `target`, or a named target
+    pub tuple: String,
+    /// The directory the outputs of the instance are written to, relative to the build directory of the target
+    pub output_dir: PathBuf,
+    /// The programs used by the instance, mapping the key in the manifest to the key of the instance found for `tuple`
+    pub programs: OrderedMap<String, String>,
+}
+
+/// The metadata of a file recorded next to its hash in the file cache
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FileStat {
+    pub size: u64,
+    pub mtime: SystemTime,
+    pub ino: u64,
+    pub dev: u64,
+    /// When the metadata was read.
+    ///
+    /// A file modified within [`MTIME_GRANULARITY`] of this time may be modified again without changing its mtime,
+    /// so its metadata can't be trusted (as with the "racy git" problem)
+    pub checked: SystemTime,
+}
+
+/// The coarsest mtime resolution of a supported filesystem (FAT records mtimes to 2 seconds)
+pub const MTIME_GRANULARITY: Duration = Duration::from_secs(2);
+
+impl FileStat {
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let checked = SystemTime::now();
+        let metadata = std::fs::metadata(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+
+        #[cfg(unix)]
+        let (ino, dev) = {
+            use std::os::unix::fs::MetadataExt;
+            (metadata.ino(), metadata.dev())
+        };
+        #[cfg(not(unix))]
+        let (ino, dev) = (0, 0);
+
+        Ok(Self {
+            size: metadata.len(),
+            mtime: metadata.modified()?,
+            ino,
+            dev,
+            checked,
+        })
+    }
+
+    /// Whether the file is unchanged since `self` was read, given its current metadata `now`
+    pub fn unchanged(&self, now: &FileStat) -> bool {
+        let racy = self
+            .checked
+            .duration_since(self.mtime)
+            .map_or(true, |age| age < MTIME_GRANULARITY);
+
+        !racy
+            && self.size == now.size
+            && self.mtime == now.mtime
+            && self.ino == now.ino
+            && self.dev == now.dev
+    }
+}
+
+/// An entry of the file cache.
+///
+/// Configurations written before metadata was recorded store only the hash
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "CachedFileRepr")]
+pub struct CachedFile {
+    pub hash: FileHash,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub stat: Option<FileStat>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum CachedFileRepr {
+    Hash(FileHash),
+    Full {
+        hash: FileHash,
+        #[serde(flatten)]
+        stat: Option<FileStat>,
+    },
+}
+
+impl From<CachedFileRepr> for CachedFile {
+    fn from(repr: CachedFileRepr) -> Self {
+        match repr {
+            CachedFileRepr::Hash(hash) => Self { hash, stat: None },
+            CachedFileRepr::Full { hash, stat } => Self { hash, stat },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
 pub struct SubdirCache {
     #[serde(flatten)]
     vars: OrderedMap<String, ConfigVarValue>,
@@ -259,7 +528,7 @@ pub struct ConfigData {
     pub env: OrderedMap<String, String>,
     pub programs: OrderedMap<String, ConfigFoundProgram>,
     pub targets: ConfigTargets,
-    pub file_cache: OrderedMap<String, FileHash>,
+    pub file_cache: OrderedMap<String, CachedFile>,
     pub config_vars: OrderedMap<String, ConfigVarValue>,
     pub global_key: FileHash,
     pub artifacts: Vec<Artifact>,
@@ -267,6 +536,26 @@ pub struct ConfigData {
     pub build_database: OrderedMap<TargetName, BuildTargetInfo>,
     #[serde(default)]
     pub cache_vars: OrderedMap<PathBuf, SubdirCache>,
+    /// The algorithm of every hash in the configuration, including `file_cache`
+    #[serde(default = "HashAlgorithm::legacy")]
+    pub hash_algorithm: HashAlgorithm,
+    /// Target-spec JSON files given to rustc for targets it doesn't have built in, by target tuple or name.
+    ///
+    /// These are set by `--rustc-target-spec`, and take precedence over the `rustc-target-specs` of manifests
+    #[serde(default)]
+    pub rustc_target_specs: OrderedMap<String, PathBuf>,
+    /// The runners that programs built for targets other than the build target are run through, mapping the target tuple to the key of the runner in `programs`
+    #[serde(default)]
+    pub runners: OrderedMap<String, String>,
+    /// The site files applied to the configuration, and the values taken from them
+    #[serde(default)]
+    pub site: site::SiteState,
+    /// The named targets in `targets.others` that were set with `--target-alias`, rather than from the default in the manifest
+    #[serde(default)]
+    pub target_aliases: OrderedSet<String>,
+    /// The archive and checksum file written into the build directory by the last `autobuild dist`, relative to the build directory
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dist_outputs: Vec<PathBuf>,
 }
 
 impl ConfigData {
@@ -289,7 +578,69 @@ impl ConfigData {
             artifacts: Vec::new(),
             build_database: OrderedMap::new(),
             cache_vars: OrderedMap::new(),
+            hash_algorithm: HashAlgorithm::DEFAULT,
+            rustc_target_specs: OrderedMap::new(),
+            runners: OrderedMap::new(),
+            site: site::SiteState::default(),
+            target_aliases: OrderedSet::new(),
+            dist_outputs: Vec::new(),
+        }
+    }
+
+    /// The variables cached for the directory `dir` of the source tree, relative to the source directory
+    pub fn dir_vars(&self, dir: &Path) -> impl Iterator<Item = (&String, &ConfigVarValue)> {
+        self.cache_vars
+            .get(dir)
+            .into_iter()
+            .flat_map(|cache| cache.vars.iter())
+    }
+
+    /// Caches the variable `name` for the directory `dir` in the configuration
+    pub fn set_dir_var(&mut self, dir: &Path, name: String, val: ConfigVarValue) {
+        self.cache_vars
+            .get_or_insert_with_mut(dir.to_path_buf(), |_| SubdirCache::default())
+            .vars
+            .insert(name, val);
+    }
+
+    /// The runner that programs built for `targ` are run through, or `None` if they are run directly
+    pub fn runner(&self, targ: &Target) -> Option<&ConfigFoundProgram> {
+        self.runners
+            .get(targ.get_name())
+            .and_then(|key| self.programs.get(key))
+    }
+
+    /// A command that runs `exe`, a program built for `targ`, through the runner for `targ` if it has one
+    pub fn run_command(&self, targ: &Target, exe: &Path) -> Command {
+        match self.runner(targ) {
+            Some(runner) => {
+                let mut cmd = runner.command();
+                cmd.arg(exe);
+                cmd
+            }
+            None => Command::new(exe),
+        }
+    }
+
+    /// The target that the outputs of `info` are built for: that of its compiler, or otherwise the target of its instance, or the host
+    pub fn output_target(&self, info: &BuildTargetInfo) -> &Target {
+        if let BuildTargetStep::Build(build) = &info.step {
+            let key = info
+                .instance
+                .as_ref()
+                .and_then(|instance| instance.programs.get(&build.compiler_name))
+                .unwrap_or(&build.compiler_name);
+            if let Some(ConfigProgramInfo::Rustc(rustc)) =
+                self.programs.get(key).and_then(|prg| prg.info.as_ref())
+            {
+                return &rustc.target.real_target;
+            }
         }
+
+        info.instance
+            .as_ref()
+            .and_then(|instance| self.targets.get(&instance.tuple))
+            .unwrap_or(&self.targets.host)
     }
 }
 
@@ -299,16 +650,18 @@ pub struct ExtraConfigDirs {
     pub dirs: OrderedMap<String, FormatString>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[derive(Clone, Debug, Deserialize, Serialize, Default, PartialEq, Eq)]
 #[serde(default)]
 pub struct ProgramSpec {
     #[serde(rename = "type")]
     pub ty: Option<ProgramType>,
     pub names: Vec<String>,
     pub target: Option<FormatString>,
+    /// A launcher to run the program through, overridden by `AUTOBUILD_<PROGRAM>_WRAPPER` (or `RUSTC_WRAPPER`, for rustc)
+    pub wrapper: Option<String>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 pub enum ProgramType {
     Rustc,
@@ -334,6 +687,30 @@ pub struct GroupSpec {
     path_spec: Option<FormatString>,
 }
 
+impl GroupSpec {
+    /// The directory containing the manifest of `member`, relative to the directory of the manifest declaring the group
+    pub fn member_dir(&self, member: &str) -> io::Result<PathBuf> {
+        if let Some(spec) = &self.path_spec {
+            let keys = HashMap::<&'static str, &'static str>::new();
+            let mut st = String::new();
+            spec.eval(member, &keys, &mut st)?;
+            Ok(PathBuf::from(st))
+        } else {
+            Ok(PathBuf::from(member))
+        }
+    }
+}
+
+/// The name of the `--enable-<flag>` option for a member of a group
+pub fn group_member_flag(group: &str, member: &str) -> String {
+    format!("{}-{}", group, member)
+}
+
+/// The name of the instance of the target `name` built for `tuple`, when the target is built for several targets
+pub fn target_instance_name(name: &str, tuple: &str) -> String {
+    format!("{}@{}", name, tuple)
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 
 pub struct TargetSpec {
@@ -341,10 +718,45 @@ pub struct TargetSpec {
     optional: bool,
     #[serde(default)]
     deps: Vec<String>,
+    /// Builds an instance of the target for each of these targets, rather than once
+    #[serde(default)]
+    tuples: Vec<String>,
     #[serde(flatten)]
     step: StepSpec,
 }
 
+impl TargetSpec {
+    pub fn step(&self) -> &StepSpec {
+        &self.step
+    }
+}
+
+impl StepSpec {
+    /// How the target is compiled, if it is: a build script is built as a binary that isn't installed by default
+    fn build_spec(&self) -> Option<(Cow<'_, BuildSpec>, Option<&BuildScriptSrc>)> {
+        match self {
+            StepSpec::Subdir(_) => None,
+            StepSpec::Build(build) => Some((Cow::Borrowed(build), None)),
+            StepSpec::Script(script) => {
+                let (BuildScriptSrc::Build(src) | BuildScriptSrc::Configure(src)) = &script.src;
+                let mut artifact = script.artifact.clone();
+                artifact
+                    .install
+                    .get_or_insert(StringOrControl::Control(false));
+                Some((
+                    Cow::Owned(BuildSpec {
+                        src: src.clone(),
+                        ty: script.ty.clone(),
+                        output: Some(BuildOutput::Binary(BuildBinaryInfo::default())),
+                        artifact,
+                    }),
+                    Some(&script.src),
+                ))
+            }
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum StepSpec {
@@ -459,15 +871,25 @@ pub struct BuildLibraryInfo {
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
 #[serde(rename_all = "kebab-case", default)]
 pub struct BuildBinaryInfo {
+    /// Whether the binary finds the dynamic libraries built in the tree relative to itself, so it runs from the build directory
     pub set_local_rpath: Option<bool>,
     pub run_target: Option<bool>,
 }
 
+/// A test harness, built with `rustc --test` and run by `autobuild test`
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct BuildTestInfo {
+    /// Arguments always passed to the harness, before those given to `autobuild test`
+    pub args: Option<Vec<String>>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
 #[serde(rename_all = "kebab-case", default)]
 pub struct BuildInfoDefaults {
     pub library: BuildLibraryInfo,
     pub binary: BuildBinaryInfo,
+    pub test: BuildTestInfo,
     pub artifact: BuildArtifactInfo,
 }
 
@@ -476,6 +898,7 @@ pub struct BuildInfoDefaults {
 pub enum BuildOutput {
     Library(BuildLibraryInfo),
     Binary(BuildBinaryInfo),
+    Test(BuildTestInfo),
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -508,6 +931,76 @@ pub struct BuildScriptSpec {
     pub artifact: BuildArtifactInfo,
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum OptionType {
+    Bool,
+    String,
+    Enum { values: Vec<String> },
+    Path,
+}
+
+impl OptionType {
+    /// Checks that `val` is a valid value for an option of this type
+    pub fn validate(&self, val: &ConfigVarValue) -> Result<(), String> {
+        match (self, val) {
+            (OptionType::Bool, ConfigVarValue::Set | ConfigVarValue::Unset) => Ok(()),
+            (OptionType::Bool, ConfigVarValue::Value(v)) => match &**v {
+                "yes" | "no" | "true" | "false" | "on" | "off" | "1" | "0" => Ok(()),
+                v => Err(format!("expected a boolean, got `{}`", v)),
+            },
+            (OptionType::Enum { values }, ConfigVarValue::Value(v)) => {
+                if values.contains(v) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "expected one of {}, got `{}`",
+                        values.join(", "),
+                        v
+                    ))
+                }
+            }
+            (OptionType::Path, ConfigVarValue::Value(v)) if v.is_empty() => {
+                Err("expected a path, got an empty string".to_string())
+            }
+            (_, ConfigVarValue::Value(_)) => Ok(()),
+            (_, ConfigVarValue::Set) => Err("a value is required".to_string()),
+            (_, ConfigVarValue::Unset) => Ok(()),
+        }
+    }
+}
+
+impl core::fmt::Display for OptionType {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            OptionType::Bool => f.write_str("yes|no"),
+            OptionType::String => f.write_str("STRING"),
+            OptionType::Enum { values } => f.write_str(&values.join("|")),
+            OptionType::Path => f.write_str("PATH"),
+        }
+    }
+}
+
+/// A configuration option declared by a manifest, which is set with `--set NAME=VALUE`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OptionSpec {
+    #[serde(flatten)]
+    pub ty: OptionType,
+    #[serde(default)]
+    pub default: Option<ConfigVarValue>,
+    #[serde(default)]
+    pub help: Option<String>,
+}
+
+/// The `programs`, `env` entries, and `dirs` of a manifest that are visible to every manifest below it
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct InheritSpec {
+    pub programs: Vec<String>,
+    pub env: Vec<String>,
+    pub dirs: Vec<String>,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, Default)]
 #[serde(default)]
 pub struct Manifest {
@@ -515,10 +1008,283 @@ pub struct Manifest {
     pub programs: OrderedMap<String, ProgramSpec>,
     pub target: BuildTargets,
     pub env: Vec<String>,
+    pub inherit: InheritSpec,
+    pub options: OrderedMap<String, OptionSpec>,
+    pub targets: OrderedMap<String, NamedTargetSpec>,
+    #[serde(rename = "rustc-target-specs")]
+    pub rustc_target_specs: OrderedMap<String, PathBuf>,
+    pub dist: DistSpec,
+}
+
+/// Files to add to (or leave out of) the archive made by `autobuild dist`, besides the files reachable from manifests.
+///
+/// Patterns are relative to the directory of the manifest, and may use `*` and `?` within a component and `**` for any number of components.
+/// A pattern that matches a directory matches everything in it
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct DistSpec {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl Manifest {
+    /// The `--enable-<flag>` options declared directly by this manifest, with whether each is enabled by default
+    pub fn optional_target_flags(&self) -> Vec<(String, bool)> {
+        let mut flags = Vec::new();
+
+        for (name, spec) in &self.target.targets {
+            if spec.optional {
+                flags.push((name.clone(), false));
+            }
+        }
+
+        for (name, group) in &self.target.groups {
+            if group.optional {
+                flags.push((name.clone(), false));
+            }
+
+            for member in &group.members {
+                flags.push((group_member_flag(name, member), !group.optional));
+            }
+        }
+
+        flags
+    }
+}
+
+/// Parses every manifest reachable from `src_dir`, without configuring anything.
+///
+/// This is used to describe a project (for example, in `autobuild config --help`) before it is configured.
+pub fn read_manifest_tree(src_dir: &Path) -> io::Result<Vec<(PathBuf, Manifest)>> {
+    trace!(read_manifest_tree);
+    let mut dirs = vec![src_dir.to_path_buf()];
+    let mut manifests = Vec::new();
+    let mut seen = HashSet::new();
+
+    let mut i = 0;
+    while let Some(dir) = dirs.get(i).cloned() {
+        i += 1;
+        if !seen.insert(dir.clone()) {
+            continue;
+        }
+
+        let mut manifest_file = dir.clone();
+        manifest_file.push("autobuild.toml");
+
+        let st = std::fs::read_to_string(&manifest_file)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", manifest_file.display(), e)))?;
+
+        let manifest = toml::from_str::<Manifest>(&st).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", manifest_file.display(), e),
+            )
+        })?;
+
+        for (_, spec) in &manifest.target.targets {
+            if let StepSpec::Subdir(subdir) = &spec.step {
+                dirs.push(dir.join(&subdir.subdir));
+            }
+        }
+
+        for (_, group) in &manifest.target.groups {
+            for member in &group.members {
+                dirs.push(dir.join(group.member_dir(member)?));
+            }
+        }
+
+        manifests.push((dir, manifest));
+    }
+
+    Ok(manifests)
+}
+
+/// The items inherited by a manifest from the manifests above it, along with the manifest that declared each item
+#[derive(Clone, Debug, Default)]
+struct InheritedItems {
+    programs: OrderedMap<String, (PathBuf, ProgramSpec)>,
+    env: OrderedMap<String, PathBuf>,
+    dirs: OrderedMap<String, (PathBuf, FormatString)>,
+}
+
+impl InheritedItems {
+    /// Merges the inherited items into `manifest`, reporting an error if `manifest` redefines an item differently
+    fn apply(&self, manifest: &mut Manifest, manifest_file: &Path) -> io::Result<()> {
+        for (key, (parent, spec)) in &self.programs {
+            match manifest.programs.get(key) {
+                Some(own) if own != spec => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{}: program `{}` conflicts with the definition inherited from {}",
+                            manifest_file.display(),
+                            key,
+                            parent.display()
+                        ),
+                    ))
+                }
+                Some(_) => {}
+                None => {
+                    manifest.programs.insert(key.clone(), spec.clone());
+                }
+            }
+        }
+
+        for (var, _) in &self.env {
+            if !manifest.env.contains(var) {
+                manifest.env.push(var.clone());
+            }
+        }
+
+        for (key, (parent, dir)) in &self.dirs {
+            match manifest.dirs.dirs.get(key) {
+                Some(own) if own != dir => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{}: dir `{}` conflicts with the definition inherited from {}",
+                            manifest_file.display(),
+                            key,
+                            parent.display()
+                        ),
+                    ))
+                }
+                Some(_) => {}
+                None => {
+                    manifest.dirs.dirs.insert(key.clone(), dir.clone());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the items inherited by the subdirectories of `manifest`, which are the items inherited by `manifest` and those it marks as inherited.
+    fn extend_with(&self, manifest: &Manifest, manifest_file: &Path) -> io::Result<Self> {
+        let mut inherited = self.clone();
+
+        for key in &manifest.inherit.programs {
+            let spec = manifest.programs.get(key).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: inherited program `{}` is not defined",
+                        manifest_file.display(),
+                        key
+                    ),
+                )
+            })?;
+            if !inherited.programs.contains_key(key) {
+                inherited
+                    .programs
+                    .insert(key.clone(), (manifest_file.to_path_buf(), spec.clone()));
+            }
+        }
+
+        for var in &manifest.inherit.env {
+            if !inherited.env.contains_key(var) {
+                inherited
+                    .env
+                    .insert(var.clone(), manifest_file.to_path_buf());
+            }
+        }
+
+        for key in &manifest.inherit.dirs {
+            let dir = manifest.dirs.dirs.get(key).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{}: inherited dir `{}` is not defined",
+                        manifest_file.display(),
+                        key
+                    ),
+                )
+            })?;
+            if !inherited.dirs.contains_key(key) {
+                inherited
+                    .dirs
+                    .insert(key.clone(), (manifest_file.to_path_buf(), dir.clone()));
+            }
+        }
+
+        Ok(inherited)
+    }
+
+    /// Hashes the inherited items, so that a manifest is re-read when an item it inherits changes in a parent manifest
+    fn hash(&self, alg: HashAlgorithm, key: FileHash) -> io::Result<FileHash> {
+        alg.hash_reader(format!("{:?}", self).as_bytes(), key)
+    }
 }
 
 use std::io;
 
+/// An optional target (or group member), which is exposed to `autobuild config` as `--enable-<flag>` and `--disable-<flag>`
+#[derive(Clone, Debug)]
+pub struct OptionalTarget {
+    pub flag: String,
+    /// Whether the target is built when neither flag is given
+    pub default: bool,
+    /// The group the target is a member of, if any. Disabling a member removes it from the group instead of disabling the group.
+    pub group: Option<TargetName>,
+}
+
+/// The name of the config var that records the choice made by `--enable-<flag>` or `--disable-<flag>`
+pub fn enable_var_name(flag: &str) -> String {
+    let mut name = String::from("enable_");
+    name.extend(
+        flag.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }),
+    );
+    name
+}
+
+/// The configuration locks currently held by this process, by canonical configuration directory.
+///
+/// `flock` locks belong to the open file, so a second lock of the same directory from this process would wait on itself.
+static HELD_CONFIG_LOCKS: Mutex<Vec<(PathBuf, Weak<FileLock>)>> = Mutex::new(Vec::new());
+
+/// Takes the advisory lock that guards the configuration cache in `cfg_dir`.
+///
+/// Every tool that modifies or removes `.config.toml` must hold this lock while doing so.
+/// If this process already holds the lock, the held lock is shared rather than taken again.
+pub fn lock_config_dir(cfg_dir: &Path) -> io::Result<Arc<FileLock>> {
+    let key = std::fs::canonicalize(cfg_dir).unwrap_or_else(|_| cfg_dir.to_path_buf());
+    let mut held = HELD_CONFIG_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+    held.retain(|(_, lock)| lock.strong_count() != 0);
+    if let Some(lock) = held
+        .iter()
+        .find(|(dir, _)| *dir == key)
+        .and_then(|(_, lock)| lock.upgrade())
+    {
+        return Ok(lock);
+    }
+
+    let mut lock_path = cfg_dir.to_path_buf();
+    lock_path.push(".config.lock");
+    let lock = Arc::new(FileLock::lock_exclusive(lock_path)?);
+    held.push((key, Arc::downgrade(&lock)));
+    Ok(lock)
+}
+
+/// Reads only the `serial` of the configuration cache in `cfg_dir`.
+///
+/// Returns `Ok(None)` if there is no configuration cache, or it cannot be parsed.
+fn read_serial(cfg_dir: &Path) -> io::Result<Option<FileHash>> {
+    #[derive(Deserialize)]
+    struct SerialOnly {
+        serial: FileHash,
+    }
+
+    let mut cfg_path = cfg_dir.to_path_buf();
+    cfg_path.push(".config.toml");
+
+    match std::fs::read_to_string(cfg_path) {
+        Ok(st) => Ok(toml::from_str::<SerialOnly>(&st).ok().map(|v| v.serial)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Config {
     data: Box<ConfigData>,
@@ -529,12 +1295,22 @@ pub struct Config {
     rand: Rand,
     temp_dir: Option<PathBuf>,
     transient_vars: OrderedMap<PathBuf, SubdirCache>,
+    lock: Arc<FileLock>,
+    loaded_serial: Option<FileHash>,
+    reconfigure: bool,
+    optional_targets: OrderedMap<TargetName, OptionalTarget>,
+    manifest_target_specs: OrderedMap<String, PathBuf>,
+    target_names: HashSet<String>,
+    probe_cache: bool,
 }
 
 impl Config {
-    pub fn new(cfg_dir: PathBuf, data: Box<ConfigData>) -> Self {
+    pub fn new(cfg_dir: PathBuf, data: Box<ConfigData>) -> io::Result<Self> {
         trace!(Config::new);
-        Self {
+        let lock = lock_config_dir(&cfg_dir)?;
+        // A cache that couldn't be opened may still be there, and is replaced by this configuration
+        let loaded_serial = read_serial(&cfg_dir)?;
+        Ok(Self {
             data,
             manifests: OrderedMap::new(),
             updated: HashSet::new(),
@@ -543,7 +1319,14 @@ impl Config {
             rand: Rand::init(),
             temp_dir: None,
             transient_vars: OrderedMap::new(),
-        }
+            lock,
+            loaded_serial,
+            reconfigure: false,
+            optional_targets: OrderedMap::new(),
+            manifest_target_specs: OrderedMap::new(),
+            target_names: HashSet::new(),
+            probe_cache: true,
+        })
     }
 
     pub fn config_dir(&self) -> &Path {
@@ -552,6 +1335,7 @@ impl Config {
 
     pub fn open(cfg_dir: PathBuf) -> io::Result<Self> {
         trace!(Config::open);
+        let lock = lock_config_dir(&cfg_dir)?;
         let mut cfg_path = cfg_dir.clone();
         cfg_path.push(".config.toml");
         let mut file = File::open(cfg_path)?;
@@ -561,6 +1345,7 @@ impl Config {
             toml::from_str::<ConfigData>(&st)
                 .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
         );
+        let loaded_serial = Some(data.serial);
 
         Ok(Self {
             data,
@@ -571,20 +1356,32 @@ impl Config {
             rand: Rand::init(),
             temp_dir: None,
             transient_vars: OrderedMap::new(),
+            lock,
+            loaded_serial,
+            reconfigure: false,
+            optional_targets: OrderedMap::new(),
+            manifest_target_specs: OrderedMap::new(),
+            target_names: HashSet::new(),
+            probe_cache: true,
         })
     }
 
     pub fn cleanup(mut self) -> io::Result<()> {
         trace!(Config::clean);
-        use io::Write;
 
         if self.dirty {
+            // The lock only excludes cooperating processes, so check that nobody replaced the cache since we read it
+            if read_serial(&self.cfg_dir)? != self.loaded_serial {
+                return Err(io::Error::other(format!(
+                    "The configuration in {} was modified by another process. Rerun the command to pick up the changes",
+                    self.cfg_dir.display()
+                )));
+            }
             self.data.serial = FileHash::generate_key(&mut self.rand);
             let mut cfg_path = self.cfg_dir.clone();
             cfg_path.push(".config.toml");
             let string = toml::to_string(self.data()).unwrap();
-            let mut file = File::create(cfg_path)?;
-            file.write_all(string.as_bytes())?;
+            crate::fs::write_atomic(&cfg_path, string.as_bytes())?;
         }
         if let Some(temp_dir) = &self.temp_dir {
             std::fs::remove_dir_all(temp_dir)?;
@@ -626,97 +1423,264 @@ impl Config {
         &mut self.data
     }
 
-    fn check_up_to_date_with_hash(&mut self, file: String, key: FileHash) -> bool {
+    fn check_up_to_date_with_hash(
+        &mut self,
+        file: String,
+        key: FileHash,
+        stat: Option<FileStat>,
+    ) -> bool {
         trace!(Config::check_up_to_date_with_hash);
-        if let Some(cached) = self.data().file_cache.get(&file) {
-            if key == *cached {
+        let entry = CachedFile { hash: key, stat };
+        match self.data().file_cache.get(&file) {
+            Some(cached) if *cached == entry => true,
+            Some(cached) if cached.hash == key => {
+                // Only the metadata changed (or is no longer racy), so record it to skip hashing next time
+                self.data_mut().file_cache.insert(file, entry);
                 true
-            } else {
-                self.data_mut().file_cache.insert(file.clone(), key);
+            }
+            _ => {
+                self.data_mut().file_cache.insert(file.clone(), entry);
                 self.updated.insert(file);
                 false
             }
-        } else {
-            self.data_mut().file_cache.insert(file.clone(), key);
-            self.updated.insert(file);
-            false
         }
     }
 
-    // pub fn check_up_to_date(&mut self, file: String) -> io::Result<bool> {
-    //     if self.updated.contains(&file) {
-    //         return Ok(false);
-    //     }
+    /// Checks whether the file at `path`, recorded in the file cache as `file`, is unchanged since the last configure.
+    ///
+    /// The file is only hashed if its metadata changed. `contents` is hashed instead of reading the file, if given
+    fn check_file_up_to_date(
+        &mut self,
+        file: String,
+        path: &Path,
+        contents: Option<(FileStat, &[u8])>,
+    ) -> io::Result<bool> {
+        trace!(Config::check_file_up_to_date);
+        // Stat before hashing, so a write during the read leaves a stale stat that fails the next check
+        let (stat, contents) = match contents {
+            Some((stat, contents)) => (stat, Some(contents)),
+            None => (FileStat::of(path)?, None),
+        };
 
-    //     let mut buf = self.data().src_dir.clone();
-    //     buf.push(&file);
+        if let Some(cached) = self.data().file_cache.get(&file) {
+            if cached
+                .stat
+                .as_ref()
+                .is_some_and(|cached| cached.unchanged(&stat))
+            {
+                return Ok(true);
+            }
+        }
 
-    //     let key = match crate::hash::hash_file(&buf, Sha64State::SHA512_256, self.data().global_key)
-    //     {
-    //         Ok(o) => o,
-    //         Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
-    //         Err(e) => return Err(e),
-    //     };
+        let alg = self.data().hash_algorithm;
+        let key = self.data().global_key;
+        let hash = match contents {
+            Some(contents) => alg.hash_reader(contents, key)?,
+            None => alg.hash_file(path, key)?,
+        };
 
-    //     Ok(self.check_up_to_date_with_hash(file, key))
-    // }
+        Ok(self.check_up_to_date_with_hash(file, hash, Some(stat)))
+    }
 
-    pub fn find_program(&mut self, key: &str, prg_spec: &ProgramSpec) -> io::Result<()> {
-        trace!(Config::find_program);
-        if !self.data().programs.contains_key(key) {
-            // If we've set a variable containing the name of the program (including via env), try to search it
-            let path = if let Some(ConfigVarValue::Value(val)) = self.data().config_vars.get(key) {
-                if val.contains(std::path::MAIN_SEPARATOR) {
-                    if val.starts_with(std::path::MAIN_SEPARATOR) {
-                        PathBuf::from(val)
-                    } else {
-                        // treat this as a relative path
-                        std::fs::canonicalize(val)?
-                    }
-                } else {
-                    // treat this as a program name
+    /// Records the hashes of `files` in the file cache.
+    ///
+    /// Returns `true` if any of the site files is new, has changed, or was removed since the last configure.
+    pub fn check_site_files(&mut self, files: &[site::SiteFile]) -> io::Result<bool> {
+        trace!(Config::check_site_files);
+        let paths = files
+            .iter()
+            .map(|file| file.path.clone())
+            .collect::<Vec<_>>();
+        let mut changed = self.data().site.files != paths;
+        self.data_mut().site.files = paths;
+        for file in files {
+            let name = file
+                .path
+                .clone()
+                .into_os_string()
+                .into_string()
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{}: path is not valid UTF-8", file.path.display()),
+                    )
+                })?;
 
-                    which(val)?
-                }
-            } else {
-                let mut path = None;
+            changed |= !self.check_file_up_to_date(
+                name,
+                &file.path,
+                Some((file.stat, file.contents.as_bytes())),
+            )?;
+        }
 
-                for name in &prg_spec.names {
-                    println!("Checking for {}", name);
-                    // treat all of these as program names
+        Ok(changed)
+    }
 
-                    if let Ok(p) = which(name) {
-                        println!("\tFound {}", p.display());
-                        path = Some(p);
-                        break;
-                    }
-                }
+    /// Treats every manifest as out of date on the next [`Config::read_manifest`], even if its hash is unchanged
+    pub fn force_reconfigure(&mut self) {
+        self.reconfigure = true;
+    }
 
-                if path.is_none() {
-                    match prg_spec.ty {
-                        Some(ProgramType::Rustc) => path = which("rustc").ok(),
-                        _ => {}
-                    }
-                }
+    /// Checks whether `file` (relative to the source directory) is unchanged since the last configure
+    pub fn check_up_to_date(&mut self, file: String) -> io::Result<bool> {
+        trace!(Config::check_up_to_date);
+        if self.updated.contains(&file) {
+            return Ok(false);
+        }
+
+        let mut buf = self.data().src_dir.clone();
+        buf.push(&file);
+
+        match self.check_file_up_to_date(file, &buf, None) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            res => res,
+        }
+    }
+
+    /// Probes compilers again instead of using the results cached by other build directories.
+    ///
+    /// The compilers already found by this configuration are forgotten, so that they are probed again as well
+    pub fn disable_probe_cache(&mut self) {
+        self.probe_cache = false;
+        let probed = self
+            .data()
+            .programs
+            .iter()
+            .filter(|(_, prg)| prg.info.is_some())
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in probed {
+            self.data_mut().programs.remove(&key);
+        }
+        self.force_reconfigure();
+    }
+
+    pub fn probe_cache_enabled(&self) -> bool {
+        self.probe_cache
+    }
+
+    /// Hashes the contents of `path` with the key of this configuration
+    pub fn hash_file(&self, path: &Path) -> io::Result<FileHash> {
+        self.data()
+            .hash_algorithm
+            .hash_file(path, self.data().global_key)
+    }
+
+    /// The target-spec JSON file to give rustc for `target`, if one was set by a manifest or `--rustc-target-spec`
+    pub fn rustc_target_spec(&self, target: &str) -> Option<PathBuf> {
+        let keys = self.data().targets.format_keys();
+        self.data()
+            .rustc_target_specs
+            .iter()
+            .chain(self.manifest_target_specs.iter())
+            .find(|(name, _)| keys.get(&***name).copied().unwrap_or(&***name) == target)
+            .map(|(_, spec)| spec.clone())
+    }
+
+    /// The name of the launcher to run the program `key` through, if any.
+    ///
+    /// An empty name in the environment disables the wrapper set by the manifest
+    fn wrapper_name(key: &str, prg_spec: &ProgramSpec) -> io::Result<Option<String>> {
+        let var = format!(
+            "AUTOBUILD_{}_WRAPPER",
+            key.to_ascii_uppercase().replace('-', "_")
+        );
+
+        let mut vars = vec![var];
+        if prg_spec.ty == Some(ProgramType::Rustc) {
+            vars.push("RUSTC_WRAPPER".to_string());
+        }
 
-                if path.is_none() {
-                    path = which(key).ok();
+        for var in vars {
+            match std::env::var(&var) {
+                Ok(val) if val.is_empty() => return Ok(None),
+                Ok(val) => return Ok(Some(val)),
+                Err(VarError::NotPresent) => {}
+                Err(VarError::NotUnicode(_)) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "Environment Variable \"{}\" was found, but contained invalid UTF-8",
+                            var
+                        ),
+                    ))
                 }
+            }
+        }
 
-                path.ok_or_else(|| {
-                    io::Error::new(
-                        io::ErrorKind::NotFound,
-                        format!("Program {}, was not found", key),
-                    )
-                })?
-            };
+        Ok(prg_spec.wrapper.clone())
+    }
+
+    fn find_wrapper(
+        &self,
+        key: &str,
+        prg_spec: &ProgramSpec,
+    ) -> io::Result<Option<ProgramWrapper>> {
+        let Some(name) = Self::wrapper_name(key, prg_spec)? else {
+            return Ok(None);
+        };
 
+        let location = if name.contains(std::path::MAIN_SEPARATOR) {
+            std::fs::canonicalize(&name)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", name, e)))?
+        } else {
+            which(&name)?
+        };
+
+        let hash = self.hash_file(&location)?;
+
+        Ok(Some(ProgramWrapper { location, hash }))
+    }
+
+    /// Checks whether the program found for `key` (stored as `store_key`) needs to be found again.
+    ///
+    /// This is the case when its wrapper has changed, or when it was probed with a target-spec file that has since been edited
+    /// or that is no longer the one set for its target
+    fn program_out_of_date(
+        &self,
+        store_key: &str,
+        key: &str,
+        prg_spec: &ProgramSpec,
+    ) -> io::Result<bool> {
+        let Some(prg) = self.data().programs.get(store_key) else {
+            return Ok(false);
+        };
+
+        match self.find_wrapper(key, prg_spec) {
+            Ok(wrapper) if wrapper != prg.wrapper => return Ok(true),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e),
+        }
+
+        let Some(ConfigProgramInfo::Rustc(rustc)) = &prg.info else {
+            return Ok(false);
+        };
+
+        let spec = self.rustc_target_spec(rustc.target.real_target.get_name());
+        if spec != rustc.target.target_spec {
+            return Ok(true);
+        }
+
+        match (&spec, &rustc.target.target_spec_hash) {
+            (Some(spec), Some(hash)) => match self.hash_file(spec) {
+                Ok(new_hash) => Ok(new_hash != *hash),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(true),
+                Err(e) => Err(e),
+            },
+            (Some(_), None) => Ok(true),
+            (None, _) => Ok(false),
+        }
+    }
+
+    pub fn find_program(&mut self, key: &str, prg_spec: &ProgramSpec) -> io::Result<()> {
+        trace!(Config::find_program);
+        if !self.data().programs.contains_key(key)
+            || self.program_out_of_date(key, key, prg_spec)?
+        {
             let target = match &prg_spec.target {
                 Some(fmt) => {
-                    let mut keys = HashMap::new();
-                    keys.insert("build", self.data().targets.build.get_name());
-                    keys.insert("host", self.data().targets.host.get_name());
-                    keys.insert("target", self.data().targets.target.get_name());
+                    let keys = self.data().targets.format_keys();
                     let def = self.data().targets.host.get_name();
                     let mut st = String::new();
                     fmt.eval(def, &keys, &mut st)?;
@@ -725,21 +1689,189 @@ impl Config {
                 None => self.data().targets.host.get_name().to_string(),
             };
 
-            let info = match prg_spec.ty {
-                Some(ProgramType::Rustc) => {
-                    Some(ConfigProgramInfo::Rustc(rustc::info(self, &path, target)?))
-                }
-                None => None,
-            };
+            self.find_program_as(key.to_string(), key, prg_spec, target)?;
+        }
 
-            let prg = ConfigFoundProgram {
-                location: path,
-                info,
+        Ok(())
+    }
+
+    /// Finds the program `key` for the target named `tuple`, storing it as `<key>@<tuple>`.
+    ///
+    /// This is used by multi-target builds, which need an instance of each compiler for every target they are built for.
+    pub fn find_program_instance(
+        &mut self,
+        key: &str,
+        prg_spec: &ProgramSpec,
+        tuple: &str,
+    ) -> io::Result<String> {
+        trace!(Config::find_program_instance);
+        let instance_key = format!("{}@{}", key, tuple);
+        if !self.data().programs.contains_key(&instance_key)
+            || self.program_out_of_date(&instance_key, key, prg_spec)?
+        {
+            let target = self
+                .data()
+                .targets
+                .format_keys()
+                .get(tuple)
+                .map(|targ| targ.to_string())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} is not a known target", tuple),
+                    )
+                })?;
+
+            self.find_program_as(instance_key.clone(), key, prg_spec, target)?;
+        }
+
+        Ok(instance_key)
+    }
+
+    /// Finds a runner for each target that isn't the build target, which programs built for that target are run through.
+    ///
+    /// The runner for a target named `host` is the program `HOST_RUNNER` (and likewise for the other targets), which defaults to
+    ///  a user-mode emulator such as `qemu-aarch64`, or `wine` for Windows. Without a runner, programs are run directly, which works
+    ///  if the build system can run them anyway (for example, with `binfmt_misc`)
+    pub fn find_runners(&mut self) -> io::Result<()> {
+        trace!(Config::find_runners);
+        let targets = &self.data().targets;
+        let build = targets.build.clone();
+        let mut named = vec![
+            ("host".to_string(), targets.host.clone()),
+            ("target".to_string(), targets.target.clone()),
+        ];
+        named.extend(
+            targets
+                .others
+                .iter()
+                .map(|(name, targ)| (name.clone(), targ.clone())),
+        );
+
+        let mut runners = OrderedMap::new();
+        let mut seen = HashSet::new();
+
+        for (name, targ) in named {
+            let tuple = targ.get_name();
+            if tuple == build.get_name() || !seen.insert(tuple.to_string()) {
+                continue;
+            }
+
+            let key = format!("{}_RUNNER", name.to_ascii_uppercase().replace('-', "_"));
+
+            // Programs the build system runs natively only use a runner that was set explicitly
+            let explicit = self.data().config_vars.contains_key(&key);
+            if runs_natively(&build, &targ) && !explicit {
+                continue;
+            }
+
+            // A runner found for the previous target of this name would run the wrong programs
+            let stale = self
+                .data()
+                .runners
+                .iter()
+                .any(|(old, old_key)| *old_key == key && old != tuple);
+            if stale {
+                self.data_mut().programs.remove(&key);
+            }
+
+            let spec = ProgramSpec {
+                names: runner_names(&build, &targ),
+                ..Default::default()
             };
 
-            self.data_mut().programs.insert(key.to_string(), prg);
+            match self.find_program(&key, &spec) {
+                Ok(()) => {
+                    runners.insert(tuple.to_string(), key);
+                }
+                // An explicitly set runner must exist
+                Err(e) if e.kind() == io::ErrorKind::NotFound && !explicit => {
+                    println!(
+                        "No runner found for {}, programs built for it are run directly",
+                        tuple
+                    );
+                }
+                Err(e) => return Err(e),
+            }
         }
 
+        self.data_mut().runners = runners;
+
+        Ok(())
+    }
+
+    fn find_program_as(
+        &mut self,
+        store_key: String,
+        key: &str,
+        prg_spec: &ProgramSpec,
+        target: String,
+    ) -> io::Result<()> {
+        // If we've set a variable containing the name of the program (including via env), try to search it
+        let path = if let Some(ConfigVarValue::Value(val)) = self.data().config_vars.get(key) {
+            if val.contains(std::path::MAIN_SEPARATOR) {
+                if val.starts_with(std::path::MAIN_SEPARATOR) {
+                    PathBuf::from(val)
+                } else {
+                    // treat this as a relative path
+                    std::fs::canonicalize(val)?
+                }
+            } else {
+                // treat this as a program name
+
+                which(val)?
+            }
+        } else {
+            let mut path = None;
+
+            for name in &prg_spec.names {
+                println!("Checking for {}", name);
+                // treat all of these as program names
+
+                if let Ok(p) = which(name) {
+                    println!("\tFound {}", p.display());
+                    path = Some(p);
+                    break;
+                }
+            }
+
+            if path.is_none() {
+                match prg_spec.ty {
+                    Some(ProgramType::Rustc) => path = which("rustc").ok(),
+                    _ => {}
+                }
+            }
+
+            if path.is_none() {
+                path = which(key).ok();
+            }
+
+            path.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Program {}, was not found", key),
+                )
+            })?
+        };
+
+        let info = match prg_spec.ty {
+            Some(ProgramType::Rustc) => {
+                Some(ConfigProgramInfo::Rustc(rustc::info(self, &path, target)?))
+            }
+            None => None,
+        };
+
+        // Probes query the real compiler, so the wrapper is only found once they're done
+        let wrapper = self.find_wrapper(key, prg_spec)?;
+
+        let prg = ConfigFoundProgram {
+            location: path,
+            wrapper,
+            info,
+        };
+
+        self.data_mut().programs.insert(store_key, prg);
+
         Ok(())
     }
 
@@ -767,6 +1899,398 @@ impl Config {
 
     pub fn read_manifest(&mut self, src_dir: Option<PathBuf>) -> io::Result<()> {
         trace!(Config::read_manifest);
+        self.read_manifest_inherited(src_dir, &InheritedItems::default())?;
+        self.resolve_instance_deps();
+        self.resolve_optional_targets()
+    }
+
+    /// Points the dependencies of each instance at the instance of the dependency built for the same tuple, if it has one.
+    ///
+    /// This is done once every manifest is read, as a dependency may be declared after its dependants (or in a manifest that wasn't reread).
+    fn resolve_instance_deps(&mut self) {
+        trace!(Config::resolve_instance_deps);
+        let data = self.data_mut();
+        let mut updates = Vec::new();
+
+        for (name, info) in &data.build_database {
+            let Some(instance) = &info.instance else {
+                continue;
+            };
+            let suffix = target_instance_name("", &instance.tuple);
+
+            let deps = info
+                .deps
+                .iter()
+                .map(|dep| {
+                    let base = dep.name.strip_suffix(&*suffix).unwrap_or(&dep.name);
+                    let dep_instance = TargetName {
+                        base_path: dep.base_path.clone(),
+                        name: target_instance_name(base, &instance.tuple),
+                    };
+
+                    if data.build_database.contains_key(&dep_instance) {
+                        dep_instance
+                    } else {
+                        TargetName {
+                            base_path: dep.base_path.clone(),
+                            name: base.to_string(),
+                        }
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            if deps != info.deps {
+                updates.push((name.clone(), deps));
+            }
+        }
+
+        for (name, deps) in updates {
+            data.build_database.get_mut(&name).unwrap().deps = deps;
+        }
+    }
+
+    /// The names of the targets declared by the manifests read so far, which can be set with `--target-alias`
+    pub fn target_names(&self) -> &HashSet<String> {
+        &self.target_names
+    }
+
+    /// The optional targets declared by the manifests read so far
+    pub fn optional_targets(&self) -> &OrderedMap<TargetName, OptionalTarget> {
+        &self.optional_targets
+    }
+
+    fn target_choice(&self, flag: &str) -> io::Result<Option<bool>> {
+        let var = enable_var_name(flag);
+        match self.data().config_vars.get(&var) {
+            None | Some(ConfigVarValue::Unset) => Ok(None),
+            Some(ConfigVarValue::Set) => Ok(Some(true)),
+            Some(ConfigVarValue::Value(v)) if v == "yes" => Ok(Some(true)),
+            Some(ConfigVarValue::Value(v)) if v == "no" => Ok(Some(false)),
+            Some(ConfigVarValue::Value(v)) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} must be `yes` or `no`, but got `{}`", var, v),
+            )),
+        }
+    }
+
+    /// Checks that no two optional targets share a `--enable-<flag>` option by accident.
+    ///
+    /// Flags are recorded under [`enable_var_name`], which doesn't keep the manifest path and maps `-` to `_`,
+    ///  so the same flag in two manifests (or `foo-bar` and `foo_bar`) would be controlled together.
+    /// Instances of a target share the flag of the target, so they don't conflict with it.
+    fn check_optional_flags(&self) -> io::Result<()> {
+        let mut owners = HashMap::<String, (&TargetName, &OptionalTarget)>::new();
+
+        for (name, opt) in &self.optional_targets {
+            let var = enable_var_name(&opt.flag);
+            match owners.get(&var) {
+                Some((owner, owner_opt))
+                    if owner.base_path != name.base_path || owner_opt.flag != opt.flag =>
+                {
+                    let flags = if owner_opt.flag == opt.flag {
+                        format!("--enable-{}", opt.flag)
+                    } else {
+                        format!("--enable-{} and --enable-{}", owner_opt.flag, opt.flag)
+                    };
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Optional targets {} and {} would both be controlled by {}. Rename one of them",
+                            owner, name, flags
+                        ),
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    owners.insert(var, (name, opt));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes the optional targets that are not enabled from the build database.
+    ///
+    /// Every dependency of an enabled target is enabled. It is an error for an enabled target to depend on an explicitly disabled one,
+    ///  except that disabling a group member removes it from the group.
+    fn resolve_optional_targets(&mut self) -> io::Result<()> {
+        trace!(Config::resolve_optional_targets);
+        self.check_optional_flags()?;
+
+        let mut queue = Vec::new();
+        let mut disabled = HashSet::new();
+
+        for (name, _) in &self.data().build_database {
+            match self.optional_targets.get(name) {
+                Some(opt) => match self.target_choice(&opt.flag)? {
+                    Some(true) => queue.push(name.clone()),
+                    Some(false) => {
+                        disabled.insert(name.clone());
+                    }
+                    None if opt.default => queue.push(name.clone()),
+                    None => {}
+                },
+                None => queue.push(name.clone()),
+            }
+        }
+
+        let mut enabled = HashSet::new();
+        let mut dropped_members = Vec::new();
+
+        while let Some(name) = queue.pop() {
+            if !enabled.insert(name.clone()) {
+                continue;
+            }
+
+            let Some(info) = self.data().build_database.get(&name) else {
+                continue;
+            };
+
+            for dep in &info.deps {
+                if disabled.contains(dep) {
+                    let opt = &self.optional_targets[dep];
+                    if opt.group.as_ref() == Some(&name) {
+                        dropped_members.push((name.clone(), dep.clone()));
+                        continue;
+                    }
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "Target {} requires {}, which was disabled by --disable-{}",
+                            name, dep, opt.flag
+                        ),
+                    ));
+                }
+                queue.push(dep.clone());
+            }
+        }
+
+        let removed = self
+            .data()
+            .build_database
+            .iter()
+            .map(|(name, _)| name)
+            .filter(|name| !enabled.contains(*name))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if removed.is_empty() && dropped_members.is_empty() {
+            return Ok(());
+        }
+
+        let data = self.data_mut();
+
+        for name in removed {
+            data.build_database.remove(&name);
+        }
+
+        for (group, member) in dropped_members {
+            if let Some(info) = data.build_database.get_mut(&group) {
+                info.deps.retain(|dep| *dep != member);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The build step of the target `name`, built from `spec` by the compiler of the target (or of `instance`, for an instance
+    ///  of a multi-target build). `script` is set for a build script, which is built for the build system where possible
+    fn build_info(
+        &self,
+        manifest_file: &Path,
+        name: &TargetName,
+        spec: &BuildSpec,
+        script: Option<&BuildScriptSrc>,
+        instance: Option<&TargetInstance>,
+    ) -> io::Result<BuildInfo> {
+        trace!(Config::build_info);
+        let unsupported = |what: String| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{}: Target {} {}", manifest_file.display(), name, what),
+            )
+        };
+
+        let proc_macro = match &spec.ty {
+            None | Some(BuildType::Default(DefaultBuildType::Rust)) => false,
+            Some(BuildType::Default(DefaultBuildType::RustProcMacro)) if script.is_some() => {
+                return Err(unsupported(
+                    "is a build script, which can't be a procedural macro".to_string(),
+                ))
+            }
+            Some(BuildType::Default(DefaultBuildType::RustProcMacro)) => true,
+            Some(BuildType::Custom(ty)) => {
+                return Err(unsupported(format!(
+                    "uses the custom build type {}, which can't be built yet",
+                    ty
+                )))
+            }
+        };
+
+        // Procedural macros run in the compiler, and build scripts run during the build, so they are built for the build
+        //  system if there's a compiler for it
+        let compiler_name = if (proc_macro || script.is_some())
+            && self.data().programs.contains_key("RUSTC_FOR_BUILD")
+        {
+            "RUSTC_FOR_BUILD"
+        } else {
+            "RUSTC"
+        };
+        let key = instance
+            .and_then(|instance| instance.programs.get(compiler_name))
+            .map_or(compiler_name, String::as_str);
+        let rustc = match self
+            .data()
+            .programs
+            .get(key)
+            .and_then(|prg| prg.info.as_ref())
+        {
+            Some(ConfigProgramInfo::Rustc(rustc)) => &rustc.target,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!(
+                    "{}: Target {} is built with {}, which no manifest declares as a Rust compiler",
+                    manifest_file.display(),
+                    name,
+                    compiler_name
+                ),
+                ))
+            }
+        };
+
+        let library_type = match &spec.output {
+            None => None,
+            Some(BuildOutput::Library(lib)) => lib.library_type.as_ref(),
+            Some(BuildOutput::Binary(_) | BuildOutput::Test(_)) => None,
+        };
+
+        let (build_type, prefix, suffix) = match (&spec.output, library_type) {
+            (Some(BuildOutput::Binary(_)), _) => ("bin", &rustc.bin_prefix, &rustc.bin_suffix),
+            // A test harness is an executable, built from a library or binary crate root with `--test`
+            (Some(BuildOutput::Test(_)), _) => ("test", &rustc.bin_prefix, &rustc.bin_suffix),
+            (_, None) if proc_macro => ("proc-macro", &rustc.dylib_prefix, &rustc.dylib_suffix),
+            (_, None | Some(LibraryType::Rlib | LibraryType::RlibStatic)) => {
+                ("rlib", &rustc.rlib_prefix, &rustc.rlib_suffix)
+            }
+            (_, Some(LibraryType::RlibDynamic)) => {
+                ("dylib", &rustc.dylib_prefix, &rustc.dylib_suffix)
+            }
+            (_, Some(LibraryType::Dynamic)) => {
+                ("cdylib", &rustc.cdylib_prefix, &rustc.cdylib_suffix)
+            }
+            (_, Some(LibraryType::Static)) => (
+                "staticlib",
+                &rustc.staticlib_prefix,
+                &rustc.staticlib_suffix,
+            ),
+            (_, Some(LibraryType::RlibProcMacro)) => {
+                ("proc-macro", &rustc.dylib_prefix, &rustc.dylib_suffix)
+            }
+            (_, Some(ty @ (LibraryType::System | LibraryType::DynamicFramework))) => {
+                return Err(unsupported(format!(
+                    "is a {:?} library, which rustc can't build",
+                    ty
+                )))
+            }
+        };
+
+        let base = name.name.split('@').next().unwrap_or(&name.name);
+        let file_name = format!(
+            "{}{}{}",
+            spec.artifact.prefix.as_ref().unwrap_or(prefix),
+            base.replace('-', "_"),
+            spec.artifact.suffix.as_ref().unwrap_or(suffix)
+        );
+
+        let mut dir = name.base_path.clone();
+        if let Some(instance) = instance {
+            dir.push(&instance.output_dir);
+        }
+
+        let run_target = matches!(
+            &spec.output,
+            Some(BuildOutput::Binary(BuildBinaryInfo {
+                run_target: Some(true),
+                ..
+            }))
+        );
+        let local_rpath = matches!(
+            &spec.output,
+            Some(BuildOutput::Binary(BuildBinaryInfo {
+                set_local_rpath: Some(true),
+                ..
+            }))
+        );
+        let run = match script {
+            Some(BuildScriptSrc::Configure(_)) => Some(BuildScriptTaskTiming::Configure),
+            None if !run_target => None,
+            _ if compiler_name == "RUSTC_FOR_BUILD" => Some(BuildScriptTaskTiming::CompileBuild),
+            _ => Some(BuildScriptTaskTiming::CompileHost),
+        };
+
+        Ok(BuildInfo {
+            compiler_name: compiler_name.to_string(),
+            src: spec.src.clone(),
+            build_type: build_type.to_string(),
+            primary_artifacts: vec![dir.join(&file_name)],
+            secondary_artifacts: vec![dir.join(format!("{}.d", file_name))],
+            test: match &spec.output {
+                Some(BuildOutput::Test(test)) => Some(test.clone()),
+                _ => None,
+            },
+            local_rpath,
+            run,
+            rebuild_key: None,
+        })
+    }
+
+    /// Records the primary artifacts of `info`, the build step of the target `name`, to be installed according to `spec`
+    fn add_build_artifacts(
+        &mut self,
+        name: &TargetName,
+        info: &BuildInfo,
+        spec: &BuildSpec,
+        tuple: Option<&String>,
+    ) {
+        let default_dir = match &*info.build_type {
+            "bin" => Some("bindir"),
+            "rlib" | "dylib" | "cdylib" | "staticlib" => Some("libdir"),
+            // Procedural macros are only used by the compiler, and test harnesses are only run by `autobuild test`
+            _ => None,
+        };
+        let install_base = match &spec.artifact.install {
+            Some(StringOrControl::String(base)) => Some(base.clone()),
+            Some(StringOrControl::Control(false)) => None,
+            Some(StringOrControl::Control(true)) | None => default_dir.map(|dir| FormatString {
+                args: vec![FormatArg {
+                    leading_text: String::new(),
+                    fmt: FormatSpec::Keyed(dir.to_string()),
+                }],
+                rest: String::new(),
+            }),
+        };
+
+        for path in &info.primary_artifacts {
+            self.data_mut().artifacts.push(Artifact {
+                path: path.clone(),
+                deps: Vec::new(),
+                target: name.clone(),
+                tuple: tuple.cloned(),
+                aliases: Vec::new(),
+                install_base: install_base.clone(),
+                local_rpath: None,
+            });
+        }
+    }
+
+    fn read_manifest_inherited(
+        &mut self,
+        src_dir: Option<PathBuf>,
+        inherited: &InheritedItems,
+    ) -> io::Result<()> {
+        trace!(Config::read_manifest_inherited);
         if let Some(src_dir) = src_dir {
             if self.manifests.contains_key(&src_dir) {
                 return Ok(());
@@ -775,24 +2299,91 @@ impl Config {
             let mut manifest_file = src_dir.clone();
             manifest_file.push("autobuild.toml");
 
-            let file = File::open(&manifest_file).map_err(|e| {
+            let mut file = File::open(&manifest_file).map_err(|e| {
                 io::Error::new(e.kind(), format!("{}: {}", manifest_file.display(), e))
             })?;
 
-            let mut reader = hash::HashingReader::new(Sha64State::SHA512_256, file);
-            reader.init(self.data().global_key);
+            let stat = FileStat::of(&manifest_file)?;
 
             let mut st = String::new();
 
-            reader.read_to_string(&mut st)?;
+            file.read_to_string(&mut st)?;
 
-            let manifest = toml::from_str::<Manifest>(&st)
+            let mut manifest = toml::from_str::<Manifest>(&st)
                 .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
-            let src_file_dirty = !self.check_up_to_date_with_hash(
-                manifest_file.into_os_string().into_string().unwrap(),
-                reader.finish(),
+            inherited.apply(&mut manifest, &manifest_file)?;
+
+            let manifest_name = manifest_file
+                .clone()
+                .into_os_string()
+                .into_string()
+                .unwrap();
+            let inherited_hash =
+                inherited.hash(self.data().hash_algorithm, self.data().global_key)?;
+
+            // Check both, so that the file cache records the current hash of each
+            let file_dirty = !self.check_file_up_to_date(
+                manifest_name.clone(),
+                &manifest_file,
+                Some((stat, st.as_bytes())),
+            )?;
+            let inherited_dirty = !self.check_up_to_date_with_hash(
+                format!("{}#inherited", manifest_name),
+                inherited_hash,
+                None,
             );
+            let src_file_dirty = file_dirty || inherited_dirty || self.reconfigure;
+
+            let inherited = inherited.extend_with(&manifest, &manifest_file)?;
+
+            for (name, spec) in &manifest.targets {
+                if ConfigTargets::RESERVED_NAMES.contains(&&**name) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "{}: `{}` cannot be used as the name of a target",
+                            manifest_file.display(),
+                            name
+                        ),
+                    ));
+                }
+
+                self.target_names.insert(name.clone());
+
+                if self.data_mut().target_aliases.contains(name) {
+                    continue;
+                }
+
+                let Some(default) = &spec.default else {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "{}: Target {} has no default, and must be set with --target-alias {}=TUPLE",
+                            manifest_file.display(),
+                            name,
+                            name
+                        ),
+                    ));
+                };
+
+                let mut alias = String::new();
+                let targets = &self.data().targets;
+                default.eval(targets.host.get_name(), &targets.format_keys(), &mut alias)?;
+
+                let targ = alias.parse().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Target `{}` is not recognized or cannot be parsed", alias),
+                    )
+                })?;
+
+                // The default may depend on other targets, or the manifest may have changed it
+                let prev = self.data_mut().targets.others.insert(name.clone(), targ);
+                if prev.as_ref().map(Target::get_name) != Some(&*alias) {
+                    self.force_reconfigure();
+                }
+            }
 
             if src_file_dirty {
                 println!("Configuring in {}", src_dir.display());
@@ -808,8 +2399,16 @@ impl Config {
                         }
                     }
                 }
+            }
+
+            for (tuple, spec) in &manifest.rustc_target_specs {
+                self.manifest_target_specs
+                    .insert(tuple.clone(), src_dir.join(spec));
+            }
 
-                for (key, prg) in &manifest.programs {
+            for (key, prg) in &manifest.programs {
+                // Programs are also found again when their wrapper or a target-spec file they use changes
+                if src_file_dirty || self.program_out_of_date(key, key, prg)? {
                     self.find_program(key, prg)?;
                 }
             }
@@ -824,23 +2423,96 @@ impl Config {
                 )
             })?;
 
+            for (name, opt) in &manifest.options {
+                let bad_option = |what: &str, e: String| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "{}: {} for option {}: {}",
+                            manifest_file.display(),
+                            what,
+                            name,
+                            e
+                        ),
+                    )
+                };
+                if let Some(default) = &opt.default {
+                    opt.ty
+                        .validate(default)
+                        .map_err(|e| bad_option("Invalid default", e))?;
+                }
+                if let Some(val) = self.data().config_vars.get(name) {
+                    opt.ty
+                        .validate(val)
+                        .map_err(|e| bad_option("Invalid value", e))?;
+                }
+
+                if src_file_dirty {
+                    let subdir = self
+                        .data_mut()
+                        .cache_vars
+                        .get_or_insert_with_mut(rel_path.to_path_buf(), |_| SubdirCache::default());
+                    match &opt.default {
+                        Some(default) => subdir.vars.insert(name.clone(), default.clone()),
+                        None => subdir.vars.remove(name),
+                    };
+                }
+            }
+
             for (name, spec) in &manifest.target.targets {
                 let target_name = TargetName {
                     base_path: rel_path.to_path_buf(),
                     name: name.clone(),
                 };
-                let step = match &spec.step {
+                let (step, step_manifest) = match &spec.step {
                     StepSpec::Subdir(subdir) => {
                         let mut subdir_path = src_dir.clone();
                         subdir_path.push(&subdir.subdir);
-                        self.read_manifest(Some(subdir_path))?;
-                        BuildTargetStep::Subdir(SubdirInfo {})
+                        self.read_manifest_inherited(Some(subdir_path.clone()), &inherited)?;
+                        (BuildTargetStep::Subdir(SubdirInfo {}), Some(subdir_path))
                     }
-                    StepSpec::Build(_) => todo!(),
-                    StepSpec::Script(_) => todo!(),
+                    step => match step.build_spec() {
+                        Some((build, script)) if spec.tuples.is_empty() => (
+                            BuildTargetStep::Build(self.build_info(
+                                &manifest_file,
+                                &target_name,
+                                &build,
+                                script,
+                                None,
+                            )?),
+                            None,
+                        ),
+                        // Each instance is built with the compiler found for its tuple
+                        _ => (BuildTargetStep::Empty, None),
+                    },
                 };
+                if spec.optional {
+                    self.optional_targets.insert(
+                        target_name.clone(),
+                        OptionalTarget {
+                            flag: name.clone(),
+                            default: false,
+                            group: None,
+                        },
+                    );
+
+                    // The instances are controlled by the same flag, so that they aren't built when the target is disabled
+                    for tuple in &spec.tuples {
+                        self.optional_targets.insert(
+                            TargetName {
+                                base_path: rel_path.to_path_buf(),
+                                name: target_instance_name(name, tuple),
+                            },
+                            OptionalTarget {
+                                flag: name.clone(),
+                                default: false,
+                                group: None,
+                            },
+                        );
+                    }
+                }
                 if src_file_dirty {
-                    let deps = spec
+                    let deps: Vec<TargetName> = spec
                         .deps
                         .iter()
                         .map(|name| {
@@ -854,9 +2526,131 @@ impl Config {
                             }
                         })
                         .collect();
-                    self.data_mut()
+
+                    // The outputs of the target (and its instances) as it was configured before are replaced
+                    let old_outputs = self
+                        .data()
                         .build_database
-                        .insert(target_name, BuildTargetInfo { deps, step });
+                        .iter()
+                        .filter(|(old, _)| {
+                            old.base_path == target_name.base_path
+                                && old.name.split('@').next() == Some(&**name)
+                        })
+                        .filter_map(|(_, info)| match &info.step {
+                            BuildTargetStep::Build(build) => Some(build.primary_artifacts.clone()),
+                            _ => None,
+                        })
+                        .flatten()
+                        .collect::<HashSet<_>>();
+                    self.data_mut()
+                        .artifacts
+                        .retain(|art| !old_outputs.contains(&art.path));
+
+                    if spec.tuples.is_empty() {
+                        if let (Some((build, _)), BuildTargetStep::Build(info)) =
+                            (spec.step.build_spec(), &step)
+                        {
+                            self.add_build_artifacts(&target_name, info, &build, None);
+                        }
+                        self.data_mut().build_database.insert(
+                            target_name,
+                            BuildTargetInfo {
+                                deps,
+                                step,
+                                instance: None,
+                            },
+                        );
+                        continue;
+                    }
+
+                    // Compilers that follow the host by default are found again for each tuple.
+                    // Programs with an explicit `target` are shared between the instances
+                    let programs = step_manifest
+                        .and_then(|path| self.manifests.get(&path))
+                        .unwrap_or(&manifest)
+                        .programs
+                        .iter()
+                        .filter(|(_, prg)| prg.ty.is_some() && prg.target.is_none())
+                        .map(|(key, prg)| (key.clone(), prg.clone()))
+                        .collect::<Vec<_>>();
+
+                    let mut instances = Vec::new();
+
+                    for tuple in &spec.tuples {
+                        let Some(targ) = self
+                            .data()
+                            .targets
+                            .format_keys()
+                            .get(&**tuple)
+                            .map(|targ| targ.to_string())
+                        else {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidInput,
+                                format!(
+                                    "{}: Target {} is built for {}, which is not a known target",
+                                    manifest_file.display(),
+                                    name,
+                                    tuple
+                                ),
+                            ));
+                        };
+
+                        let mut instance_programs = OrderedMap::new();
+                        for (key, prg) in &programs {
+                            let instance_key = self.find_program_instance(key, prg, tuple)?;
+                            instance_programs.insert(key.clone(), instance_key);
+                        }
+
+                        let instance_name = TargetName {
+                            base_path: rel_path.to_path_buf(),
+                            name: target_instance_name(name, tuple),
+                        };
+                        let instance = TargetInstance {
+                            tuple: tuple.clone(),
+                            output_dir: PathBuf::from(targ),
+                            programs: instance_programs,
+                        };
+
+                        let step = match spec.step.build_spec() {
+                            Some((build, script)) => {
+                                let info = self.build_info(
+                                    &manifest_file,
+                                    &instance_name,
+                                    &build,
+                                    script,
+                                    Some(&instance),
+                                )?;
+                                self.add_build_artifacts(
+                                    &instance_name,
+                                    &info,
+                                    &build,
+                                    Some(tuple),
+                                );
+                                BuildTargetStep::Build(info)
+                            }
+                            None => step.clone(),
+                        };
+
+                        self.data_mut().build_database.insert(
+                            instance_name.clone(),
+                            BuildTargetInfo {
+                                deps: deps.clone(),
+                                step,
+                                instance: Some(instance),
+                            },
+                        );
+
+                        instances.push(instance_name);
+                    }
+
+                    self.data_mut().build_database.insert(
+                        target_name,
+                        BuildTargetInfo {
+                            deps: instances,
+                            step: BuildTargetStep::Empty,
+                            instance: None,
+                        },
+                    );
                 }
             }
 
@@ -871,24 +2665,26 @@ impl Config {
                 for member in &group.members {
                     let member_target_name = format!("{}.{}", name, member);
 
-                    let target_name = TargetName {
+                    let member_target_name = TargetName {
                         base_path: rel_path.to_path_buf(),
                         name: member_target_name,
                     };
 
-                    let keys = HashMap::<&'static str, &'static str>::new();
+                    // Members of an optional group are only built if the group (or the member) is enabled
+                    self.optional_targets.insert(
+                        member_target_name.clone(),
+                        OptionalTarget {
+                            flag: group_member_flag(name, member),
+                            default: !group.optional,
+                            group: Some(target_name.clone()),
+                        },
+                    );
 
-                    let subdir = if let Some(spec) = &group.path_spec {
-                        let mut st = String::new();
-                        spec.eval(member, &keys, &mut st)?;
-                        PathBuf::from(st)
-                    } else {
-                        PathBuf::from(member)
-                    };
+                    let subdir = group.member_dir(member)?;
 
                     let mut subdir_path = src_dir.clone();
                     subdir_path.push(subdir);
-                    self.read_manifest(Some(subdir_path))?;
+                    self.read_manifest_inherited(Some(subdir_path), &inherited)?;
                     let step = BuildTargetStep::Subdir(SubdirInfo {});
 
                     if src_file_dirty {
@@ -906,12 +2702,28 @@ impl Config {
                                 }
                             })
                             .collect();
-                        self.data_mut()
-                            .build_database
-                            .insert(target_name.clone(), BuildTargetInfo { deps, step });
+                        self.data_mut().build_database.insert(
+                            member_target_name.clone(),
+                            BuildTargetInfo {
+                                deps,
+                                step,
+                                instance: None,
+                            },
+                        );
                     }
 
-                    group_members.push(target_name);
+                    group_members.push(member_target_name);
+                }
+
+                if group.optional {
+                    self.optional_targets.insert(
+                        target_name.clone(),
+                        OptionalTarget {
+                            flag: name.clone(),
+                            default: false,
+                            group: None,
+                        },
+                    );
                 }
 
                 if src_file_dirty {
@@ -920,6 +2732,7 @@ impl Config {
                         BuildTargetInfo {
                             deps: group_members,
                             step: BuildTargetStep::Empty,
+                            instance: None,
                         },
                     );
                 }
@@ -930,7 +2743,46 @@ impl Config {
             Ok(())
         } else {
             let src_dir = self.data().src_dir.clone();
-            self.read_manifest(Some(src_dir))
+            self.read_manifest_inherited(Some(src_dir), inherited)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::runner_names;
+
+    use target_tuples::Target;
+
+    #[test]
+    fn native_targets_have_no_runner() {
+        let build = Target::parse("x86_64-pc-linux-gnu");
+        for native in [
+            "x86_64-pc-linux-musl",
+            "i686-pc-linux-gnu",
+            "i386-pc-linux-gnu",
+        ] {
+            assert!(
+                runner_names(&build, &Target::parse(native)).is_empty(),
+                "{}",
+                native
+            );
+        }
+
+        assert_eq!(
+            runner_names(&build, &Target::parse("aarch64-unknown-linux-gnu")),
+            ["qemu-aarch64", "qemu-aarch64-static"]
+        );
+        assert_eq!(
+            runner_names(
+                &Target::parse("aarch64-unknown-linux-gnu"),
+                &Target::parse("i686-pc-linux-gnu")
+            ),
+            ["qemu-i386", "qemu-i386-static"]
+        );
+        assert_eq!(
+            runner_names(&build, &Target::parse("x86_64-pc-windows-gnu")),
+            ["wine64", "wine"]
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use target_tuples::Target;
 
 use crate::programs::CompileTask;
+use serde_derive::{Deserialize, Serialize};
 use std::io;
 
 use super::{ConfigFoundProgram, ConfigVarValue};
@@ -9,7 +10,9 @@ use std::{collections::HashMap, path::Path};
 
 pub mod default;
 
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+/// When a script runs, and which system it was built for
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum BuildScriptTaskTiming {
     Configure,
     CompileBuild,
@@ -44,6 +47,8 @@ pub trait BuildScriptTask {
     fn set_install_dir(&mut self, key: &str, dir: &Path);
     fn set_target(&mut self, targ: &str, target: &Target);
     fn set_cross_compiling(&mut self);
+    /// Runs the script through `runner`, when it was built for a target the build system can't run directly
+    fn set_runner(&mut self, runner: &ConfigFoundProgram);
     fn set_autobuild_config_dir(&mut self, dir: &Path);
     fn set_tempdir(&mut self, dir: &Path);
 
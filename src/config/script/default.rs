@@ -1,6 +1,14 @@
-use super::{BuildScriptProvider, BuildScriptTask};
-
-use std::{collections::HashMap, ffi::OsString, io, path::Path, process::Command};
+use super::{BuildScriptOutputs, BuildScriptProvider, BuildScriptTask};
+use crate::config::{ConfigFoundProgram, ConfigVarValue};
+use crate::log::{log, LogLevel};
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    io,
+    path::Path,
+    process::{Command, Stdio},
+};
 
 pub struct BuildScriptProviderDefault {}
 
@@ -14,6 +22,7 @@ impl BuildScriptProvider for BuildScriptProviderDefault {
 
         Box::new(BuildScriptTaskDefault {
             cmd: exec,
+            runner: None,
             env: HashMap::new(),
         })
     }
@@ -21,6 +30,7 @@ impl BuildScriptProvider for BuildScriptProviderDefault {
 
 pub struct BuildScriptTaskDefault<'a> {
     cmd: &'a Path,
+    runner: Option<ConfigFoundProgram>,
     env: HashMap<String, OsString>,
 }
 
@@ -42,6 +52,10 @@ impl<'a> BuildScriptTask for BuildScriptTaskDefault<'a> {
             .insert("CROSSCOMPILING".to_string(), OsString::from("1"));
     }
 
+    fn set_runner(&mut self, runner: &ConfigFoundProgram) {
+        self.runner = Some(runner.clone());
+    }
+
     fn set_target(&mut self, targ: &str, target: &target_tuples::Target) {
         let var_name = targ.to_ascii_uppercase();
         if !var_name.starts_with("AUTOBUILD") {
@@ -57,12 +71,19 @@ impl<'a> BuildScriptTask for BuildScriptTaskDefault<'a> {
         }
     }
 
-    fn set_program(&mut self, prg_name: &str, prg: &crate::config::ConfigFoundProgram) {
+    fn set_program(&mut self, prg_name: &str, prg: &ConfigFoundProgram) {
         if !prg_name.starts_with("AUTOBUILD") {
             self.env.insert(
                 prg_name.to_string(),
                 prg.location.as_os_str().to_os_string(),
             );
+
+            if let Some(wrapper) = &prg.wrapper {
+                self.env.insert(
+                    format!("{}_WRAPPER", prg_name),
+                    wrapper.location.as_os_str().to_os_string(),
+                );
+            }
         }
     }
 
@@ -74,11 +95,102 @@ impl<'a> BuildScriptTask for BuildScriptTaskDefault<'a> {
     }
 
     fn run(&self) -> io::Result<super::BuildScriptOutputs> {
-        let mut cmd = Command::new(&self.cmd);
+        let mut cmd = match &self.runner {
+            Some(runner) => {
+                let mut cmd = runner.command();
+                cmd.arg(self.cmd);
+                cmd
+            }
+            None => Command::new(self.cmd),
+        };
         cmd.envs(&self.env);
         cmd.env("AUTOBUILD", std::env::current_exe().unwrap());
         cmd.env("AUTOBUILD_VERSION", crate::consts::VERSION);
+        cmd.stderr(Stdio::inherit());
+
+        log!(LogLevel::Exec, "{:?}", cmd);
+        let output = cmd
+            .output()
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", self.cmd.display(), e)))?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} failed: {}",
+                self.cmd.display(),
+                output.status
+            )));
+        }
 
-        todo!()
+        // `autobuild:set:NAME=VALUE` sets a variable for the scripts that run after it, and `autobuild:cache:NAME=VALUE` also
+        //  keeps it in the configuration. Other lines are printed
+        let mut outputs = BuildScriptOutputs {
+            set_transient: HashMap::new(),
+            set_cache: HashMap::new(),
+        };
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            let (vars, rest) = if let Some(rest) = line.strip_prefix("autobuild:set:") {
+                (&mut outputs.set_transient, rest)
+            } else if let Some(rest) = line.strip_prefix("autobuild:cache:") {
+                (&mut outputs.set_cache, rest)
+            } else {
+                println!("{}", line);
+                continue;
+            };
+
+            let Some((name, val)) = rest.split_once('=') else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: Expected NAME=VALUE, got {}", self.cmd.display(), line),
+                ));
+            };
+            vars.insert(name.to_string(), ConfigVarValue::Value(val.to_string()));
+        }
+
+        Ok(outputs)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::{BuildScriptTask, BuildScriptTaskDefault};
+    use crate::config::{ConfigFoundProgram, ConfigVarValue};
+
+    use std::{collections::HashMap, fs, os::unix::fs::PermissionsExt, path::Path};
+
+    /// A script built for another target runs through the runner, which here is a stand-in that reports what it was asked to run
+    #[test]
+    fn runs_through_runner() {
+        let dir = std::env::temp_dir().join(format!("autobuild-runner-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let runner = dir.join("runner.sh");
+        fs::write(
+            &runner,
+            "#!/bin/sh\necho \"autobuild:cache:RAN=$1\"\necho \"autobuild:set:CROSS=$CROSSCOMPILING\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&runner, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut task = BuildScriptTaskDefault {
+            cmd: Path::new("./script"),
+            runner: None,
+            env: HashMap::new(),
+        };
+        task.set_cross_compiling();
+        task.set_runner(&ConfigFoundProgram {
+            location: runner,
+            wrapper: None,
+            info: None,
+        });
+        let outputs = task.run();
+        fs::remove_dir_all(&dir).unwrap();
+        let outputs = outputs.unwrap();
+
+        assert_eq!(
+            outputs.set_cache.get("RAN"),
+            Some(&ConfigVarValue::Value("./script".to_string()))
+        );
+        assert_eq!(
+            outputs.set_transient.get("CROSS"),
+            Some(&ConfigVarValue::Value("1".to_string()))
+        );
     }
 }
@@ -0,0 +1,150 @@
+use std::io;
+use std::path::PathBuf;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::install::InstallDirs;
+use crate::log::{log_debug, trace, LogLevel};
+use crate::map::OrderedMap;
+
+use super::{ConfigInstallDirs, ConfigVarValue, FileStat};
+
+/// Site-wide defaults for `autobuild config`, in the style of autoconf's `config.site`.
+///
+/// Values given on the command line (or via the environment) always take precedence over the site file.
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct SiteConfig {
+    pub dirs: ConfigInstallDirs,
+    pub vars: OrderedMap<String, ConfigVarValue>,
+    pub programs: OrderedMap<String, PathBuf>,
+}
+
+impl SiteConfig {
+    /// Merges `other` into `self`, with the values in `other` taking precedence
+    pub fn merge(&mut self, other: SiteConfig) {
+        self.dirs.install_dirs.set_from(&other.dirs.install_dirs);
+        self.dirs.rest.extend(other.dirs.rest);
+        self.vars.extend(other.vars);
+        self.programs.extend(other.programs);
+    }
+}
+
+impl SiteConfig {
+    /// The configuration variables set by the site files, including the paths of the programs they name
+    pub fn config_vars(&self) -> io::Result<OrderedMap<String, ConfigVarValue>> {
+        let mut vars = self.vars.clone();
+
+        for (key, path) in &self.programs {
+            let val = path.to_str().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Site path for program {} is not valid UTF-8", key),
+                )
+            })?;
+
+            vars.insert(key.clone(), ConfigVarValue::Value(val.to_string()));
+        }
+
+        Ok(vars)
+    }
+}
+
+/// What the site files contributed to a configuration, as of the last time they were applied
+#[derive(Clone, Debug, Deserialize, Serialize, Default)]
+#[serde(default)]
+pub struct SiteState {
+    /// The site files that were read, in order of precedence
+    pub files: Vec<PathBuf>,
+    /// The merged values of `files`
+    pub values: SiteConfig,
+}
+
+#[derive(Clone, Debug)]
+pub struct SiteFile {
+    pub path: PathBuf,
+    /// Taken before `contents` was read, so a write racing the read is seen on the next configure
+    pub stat: FileStat,
+    pub contents: String,
+    pub config: SiteConfig,
+}
+
+/// Combines the site files returned by [`read_site_files`], later files overriding earlier ones
+pub fn merge_site_files(files: &[SiteFile]) -> SiteConfig {
+    let mut site = SiteConfig::default();
+
+    for file in files {
+        site.merge(file.config.clone());
+    }
+
+    site
+}
+
+/// Returns the site files to consider, in increasing order of precedence.
+///
+/// If `AUTOBUILD_CONFIG_SITE` is set, it is a list of paths (separated like `PATH`) and is used exclusively.
+/// Otherwise, `$sysconfdir/autobuild/site.toml` and `<user config dir>/autobuild/site.toml` are used.
+pub fn site_file_paths(install_dirs: &InstallDirs) -> Vec<PathBuf> {
+    trace!(site_file_paths);
+    if let Some(paths) = std::env::var_os("AUTOBUILD_CONFIG_SITE") {
+        return std::env::split_paths(&paths)
+            .filter(|p| !p.as_os_str().is_empty())
+            .collect();
+    }
+
+    let mut paths = Vec::new();
+
+    // `prefix()` has no default on windows
+    if install_dirs.prefix.is_some() || !cfg!(windows) {
+        let mut path = install_dirs.sysconfdir();
+        path.push("autobuild");
+        path.push("site.toml");
+        paths.push(path);
+    }
+
+    if let Some(mut path) = dirs::config_dir() {
+        path.push("autobuild");
+        path.push("site.toml");
+        paths.push(path);
+    }
+
+    paths
+}
+
+/// Reads every site file that exists.
+///
+/// Files named by `AUTOBUILD_CONFIG_SITE` must exist. The default locations are skipped if they are missing.
+pub fn read_site_files(install_dirs: &InstallDirs) -> io::Result<Vec<SiteFile>> {
+    trace!(read_site_files);
+    let explicit = std::env::var_os("AUTOBUILD_CONFIG_SITE").is_some();
+    let mut files = Vec::new();
+
+    for path in site_file_paths(install_dirs) {
+        let stat = match FileStat::of(&path) {
+            Ok(stat) => stat,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !explicit => continue,
+            Err(e) => return Err(e),
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+
+        log_debug!(LogLevel::Debug, "Reading site file {}", path.display());
+
+        let config = toml::from_str::<SiteConfig>(&contents).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })?;
+
+        files.push(SiteFile {
+            path,
+            stat,
+            contents,
+            config,
+        });
+    }
+
+    Ok(files)
+}
@@ -128,7 +128,7 @@ impl serde::ser::Serialize for ConfigTargets {
 
         serializer.serialize_entry("build", self.build.get_name())?;
         serializer.serialize_entry("host", self.host.get_name())?;
-        serializer.serialize_entry("target", self.host.get_name())?;
+        serializer.serialize_entry("target", self.target.get_name())?;
 
         for (key, val) in &self.others {
             serializer.serialize_entry(key, val.get_name())?;
@@ -0,0 +1,336 @@
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const ELFCLASS32: u8 = 1;
+pub const ELFCLASS64: u8 = 2;
+
+pub const ELFDATA2LSB: u8 = 1;
+pub const ELFDATA2MSB: u8 = 2;
+
+pub const EM_SPARC: u16 = 2;
+pub const EM_386: u16 = 3;
+pub const EM_MIPS: u16 = 8;
+pub const EM_PPC: u16 = 20;
+pub const EM_PPC64: u16 = 21;
+pub const EM_S390: u16 = 22;
+pub const EM_ARM: u16 = 40;
+pub const EM_SPARCV9: u16 = 43;
+pub const EM_X86_64: u16 = 62;
+pub const EM_AARCH64: u16 = 183;
+pub const EM_RISCV: u16 = 243;
+pub const EM_LOONGARCH: u16 = 258;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_DYNAMIC: u32 = 2;
+pub const PT_INTERP: u32 = 3;
+
+pub const DT_NULL: u64 = 0;
+pub const DT_STRTAB: u64 = 5;
+pub const DT_RPATH: u64 = 15;
+pub const DT_RUNPATH: u64 = 29;
+
+pub const EF_ARM_ABI_FLOAT_SOFT: u32 = 0x200;
+pub const EF_ARM_ABI_FLOAT_HARD: u32 = 0x400;
+
+pub const EF_MIPS_ABI2: u32 = 0x20;
+
+pub const EF_RISCV_FLOAT_ABI: u32 = 0x6;
+pub const EF_RISCV_FLOAT_ABI_SOFT: u32 = 0x0;
+
+/// The fields of an ELF file header that identify the target it was built for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ElfHeader {
+    pub class: u8,
+    pub data: u8,
+    pub osabi: u8,
+    pub machine: u16,
+    pub flags: u32,
+    pub phoff: u64,
+    pub phentsize: u16,
+    pub phnum: u16,
+}
+
+impl ElfHeader {
+    /// Reads the header from the start of an ELF file
+    pub fn read<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut buf = [0u8; 64];
+        r.read_exact(&mut buf[..52])?;
+
+        if buf[..4] != *b"\x7fELF" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not an ELF file",
+            ));
+        }
+
+        let class = buf[4];
+        let data = buf[5];
+        let osabi = buf[7];
+
+        // e_flags follows e_entry, e_phoff, and e_shoff, which are all word-sized
+        let (phoff, flags_off) = match class {
+            ELFCLASS32 => (read_word(&buf[28..], class, data), 36),
+            ELFCLASS64 => {
+                r.read_exact(&mut buf[52..64])?;
+                (read_word(&buf[32..], class, data), 48)
+            }
+            class => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Unknown ELF class {}", class),
+                ))
+            }
+        };
+
+        let u16_at = |off: usize| {
+            let bytes = [buf[off], buf[off + 1]];
+            match data {
+                ELFDATA2MSB => u16::from_be_bytes(bytes),
+                _ => u16::from_le_bytes(bytes),
+            }
+        };
+
+        let machine = u16_at(18);
+        let flags = read_u32(&buf[flags_off..], data);
+
+        Ok(Self {
+            class,
+            data,
+            osabi,
+            machine,
+            flags,
+            phoff,
+            phentsize: u16_at(flags_off + 6),
+            phnum: u16_at(flags_off + 8),
+        })
+    }
+
+    /// Reads the program headers of the file
+    pub fn read_program_headers<R: Read + Seek>(
+        &self,
+        r: &mut R,
+    ) -> io::Result<Vec<ProgramHeader>> {
+        let mut phdr = vec![0u8; self.phentsize as usize];
+
+        let min_size = if self.class == ELFCLASS64 { 40 } else { 20 };
+        if phdr.len() < min_size {
+            return Ok(Vec::new());
+        }
+
+        let mut headers = Vec::with_capacity(self.phnum as usize);
+        for i in 0..(self.phnum as u64) {
+            r.seek(SeekFrom::Start(self.phoff + i * (self.phentsize as u64)))?;
+            r.read_exact(&mut phdr)?;
+
+            let (offset, vaddr, filesz) = match self.class {
+                ELFCLASS64 => (
+                    read_word(&phdr[8..], self.class, self.data),
+                    read_word(&phdr[16..], self.class, self.data),
+                    read_word(&phdr[32..], self.class, self.data),
+                ),
+                _ => (
+                    read_word(&phdr[4..], self.class, self.data),
+                    read_word(&phdr[8..], self.class, self.data),
+                    read_word(&phdr[16..], self.class, self.data),
+                ),
+            };
+
+            headers.push(ProgramHeader {
+                p_type: read_u32(&phdr, self.data),
+                offset,
+                vaddr,
+                filesz,
+            });
+        }
+
+        Ok(headers)
+    }
+
+    /// Reads the program interpreter (`PT_INTERP`) of the file, which is [`None`] for a statically linked file
+    pub fn read_interp<R: Read + Seek>(&self, r: &mut R) -> io::Result<Option<String>> {
+        let headers = self.read_program_headers(r)?;
+        let Some(interp) = headers.iter().find(|phdr| phdr.p_type == PT_INTERP) else {
+            return Ok(None);
+        };
+
+        // Interpreter paths are short - don't trust a corrupt header to allocate for us
+        let mut buf = vec![0u8; interp.filesz.min(4096) as usize];
+        r.seek(SeekFrom::Start(interp.offset))?;
+        r.read_exact(&mut buf)?;
+
+        if let Some(pos) = buf.iter().position(|b| *b == 0) {
+            buf.truncate(pos);
+        }
+
+        String::from_utf8(buf)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Finds the library search path (`DT_RUNPATH`, or otherwise `DT_RPATH`) of the file.
+    ///
+    /// Returns the offset of the string in the file, and the string itself, or [`None`] if the file has no search path
+    pub fn read_rpath<R: Read + Seek>(&self, r: &mut R) -> io::Result<Option<(u64, String)>> {
+        let headers = self.read_program_headers(r)?;
+        let Some(dynamic) = headers.iter().find(|phdr| phdr.p_type == PT_DYNAMIC) else {
+            return Ok(None);
+        };
+
+        let entry_size = if self.class == ELFCLASS64 { 16 } else { 8 };
+        let word_size = entry_size / 2;
+        let mut buf = vec![0u8; dynamic.filesz.min(1 << 20) as usize];
+        r.seek(SeekFrom::Start(dynamic.offset))?;
+        r.read_exact(&mut buf)?;
+
+        let mut strtab = None;
+        let mut runpath = None;
+        let mut rpath = None;
+        for entry in buf.chunks_exact(entry_size) {
+            let tag = read_word(entry, self.class, self.data);
+            let val = read_word(&entry[word_size..], self.class, self.data);
+            match tag {
+                DT_NULL => break,
+                DT_STRTAB => strtab = Some(val),
+                DT_RUNPATH => runpath = Some(val),
+                DT_RPATH => rpath = Some(val),
+                _ => {}
+            }
+        }
+
+        let (Some(strtab), Some(index)) = (strtab, runpath.or(rpath)) else {
+            return Ok(None);
+        };
+
+        // DT_STRTAB is an address, which is found in the file through the segment that loads it
+        let offset = headers
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD)
+            .find(|phdr| phdr.vaddr <= strtab && strtab - phdr.vaddr < phdr.filesz)
+            .map(|phdr| phdr.offset + (strtab - phdr.vaddr) + index)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "The dynamic string table is not in a loaded segment",
+                )
+            })?;
+
+        let mut st = Vec::new();
+        r.seek(SeekFrom::Start(offset))?;
+        io::BufReader::new(r.take(1 << 16)).read_until(0, &mut st)?;
+        if st.last() == Some(&0) {
+            st.pop();
+        }
+
+        String::from_utf8(st)
+            .map(|st| Some((offset, st)))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// The name of the architecture of the file, as used in a target tuple
+    pub fn arch_name(&self) -> Option<&'static str> {
+        let le = self.data == ELFDATA2LSB;
+        let is64 = self.class == ELFCLASS64;
+        Some(match self.machine {
+            EM_386 => "i686",
+            EM_X86_64 => "x86_64",
+            EM_ARM if le => "arm",
+            EM_ARM => "armeb",
+            EM_AARCH64 if le => "aarch64",
+            EM_AARCH64 => "aarch64_be",
+            EM_MIPS => match (is64, le) {
+                (false, false) => "mips",
+                (false, true) => "mipsel",
+                (true, false) => "mips64",
+                (true, true) => "mips64el",
+            },
+            EM_PPC => "powerpc",
+            EM_PPC64 if le => "powerpc64le",
+            EM_PPC64 => "powerpc64",
+            EM_S390 => "s390x",
+            EM_SPARC => "sparc",
+            EM_SPARCV9 => "sparcv9",
+            EM_RISCV if is64 => "riscv64",
+            EM_RISCV => "riscv32",
+            EM_LOONGARCH => "loongarch64",
+            _ => return None,
+        })
+    }
+}
+
+/// A program header of an ELF file, describing a segment
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+}
+
+/// Replaces the library search path of the ELF file at `path` with `rpath`, returning the old search path.
+///
+/// The string is rewritten in place, so `rpath` can be no longer than the old search path
+pub fn set_rpath(path: &Path, rpath: &str) -> io::Result<String> {
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    let header = ElfHeader::read(&mut file)?;
+    let (offset, old) = header.read_rpath(&mut file)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} has no rpath", path.display()),
+        )
+    })?;
+
+    if rpath.len() > old.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "The new rpath `{}` is too long to replace the rpath `{}` of {}",
+                rpath,
+                old,
+                path.display()
+            ),
+        ));
+    }
+
+    let mut buf = rpath.as_bytes().to_vec();
+    buf.resize(old.len(), 0);
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(&buf)?;
+
+    Ok(old)
+}
+
+/// Reads the header and program interpreter of the ELF file at `path`
+pub fn read_file(path: &Path) -> io::Result<(ElfHeader, Option<String>)> {
+    let mut file = File::open(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+    let header = ElfHeader::read(&mut file)?;
+    let interp = header.read_interp(&mut file)?;
+    Ok((header, interp))
+}
+
+fn read_u32(buf: &[u8], data: u8) -> u32 {
+    let bytes = [buf[0], buf[1], buf[2], buf[3]];
+    match data {
+        ELFDATA2MSB => u32::from_be_bytes(bytes),
+        _ => u32::from_le_bytes(bytes),
+    }
+}
+
+/// Reads an address or offset, which is the size of the class of the file
+fn read_word(buf: &[u8], class: u8, data: u8) -> u64 {
+    if class == ELFCLASS64 {
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&buf[..8]);
+        match data {
+            ELFDATA2MSB => u64::from_be_bytes(bytes),
+            _ => u64::from_le_bytes(bytes),
+        }
+    } else {
+        read_u32(buf, data) as u64
+    }
+}
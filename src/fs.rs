@@ -1 +1,208 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
 
+use crate::log::{log, log_debug, trace, LogLevel};
+
+/// An advisory, exclusive lock held on a file for the lifetime of the value.
+///
+/// The lock is released when the `FileLock` is dropped (or the process exits).
+/// Advisory locks only exclude other processes that also take the lock - they do not prevent plain reads or writes.
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+}
+
+impl FileLock {
+    /// Acquires an exclusive lock on `path`, creating the file if it does not exist.
+    ///
+    /// If the lock is currently held by another process, prints a message and blocks until it is released.
+    pub fn lock_exclusive(path: PathBuf) -> io::Result<Self> {
+        trace!(FileLock::lock_exclusive);
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+
+        if !try_lock(&file)? {
+            log!(
+                LogLevel::Diagnostic,
+                "Blocking waiting for lock on {}",
+                path.display()
+            );
+            lock(&file)?;
+        }
+
+        log_debug!(LogLevel::Debug, "Acquired lock on {}", path.display());
+
+        Ok(Self { file })
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = unlock(&self.file);
+    }
+}
+
+#[allow(unused_parens)] // cfg_match is a macro that exists
+fn try_lock(file: &File) -> io::Result<bool> {
+    cfg_match::cfg_match! {
+        unix => ({
+            use std::os::fd::AsRawFd;
+
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+                let err = io::Error::last_os_error();
+
+                if err.kind() == io::ErrorKind::WouldBlock {
+                    Ok(false)
+                } else {
+                    Err(err)
+                }
+            } else {
+                Ok(true)
+            }
+        }),
+        windows => ({
+            use std::os::windows::io::AsRawHandle;
+            use windows_sys::Win32::Foundation::ERROR_LOCK_VIOLATION;
+            use windows_sys::Win32::Storage::FileSystem::{
+                LockFileEx, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
+            };
+
+            let mut overlapped = unsafe { core::mem::zeroed() };
+
+            if unsafe {
+                LockFileEx(
+                    file.as_raw_handle() as _,
+                    LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY,
+                    0,
+                    !0,
+                    !0,
+                    &mut overlapped,
+                )
+            } == 0
+            {
+                let err = io::Error::last_os_error();
+
+                if err.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32) {
+                    Ok(false)
+                } else {
+                    Err(err)
+                }
+            } else {
+                Ok(true)
+            }
+        }),
+        _ => ({
+            let _ = file;
+            Ok(true)
+        })
+    }
+}
+
+#[allow(unused_parens)] // cfg_match is a macro that exists
+fn lock(file: &File) -> io::Result<()> {
+    cfg_match::cfg_match! {
+        unix => ({
+            use std::os::fd::AsRawFd;
+
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }),
+        windows => ({
+            use std::os::windows::io::AsRawHandle;
+            use windows_sys::Win32::Storage::FileSystem::{LockFileEx, LOCKFILE_EXCLUSIVE_LOCK};
+
+            let mut overlapped = unsafe { core::mem::zeroed() };
+
+            if unsafe {
+                LockFileEx(
+                    file.as_raw_handle() as _,
+                    LOCKFILE_EXCLUSIVE_LOCK,
+                    0,
+                    !0,
+                    !0,
+                    &mut overlapped,
+                )
+            } == 0
+            {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }),
+        _ => ({
+            let _ = file;
+            Ok(())
+        })
+    }
+}
+
+#[allow(unused_parens)] // cfg_match is a macro that exists
+fn unlock(file: &File) -> io::Result<()> {
+    cfg_match::cfg_match! {
+        unix => ({
+            use std::os::fd::AsRawFd;
+
+            if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) } != 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }),
+        windows => ({
+            use std::os::windows::io::AsRawHandle;
+            use windows_sys::Win32::Storage::FileSystem::UnlockFile;
+
+            if unsafe { UnlockFile(file.as_raw_handle() as _, 0, 0, !0, !0) } == 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        }),
+        _ => ({
+            let _ = file;
+            Ok(())
+        })
+    }
+}
+
+/// Replaces the contents of `path` with `contents`, such that readers observe either the old file or the new file, but never a partial write.
+///
+/// The contents are written to a temporary file next to `path`, flushed to disk, then renamed over `path`.
+pub fn write_atomic<P: AsRef<Path> + ?Sized>(path: &P, contents: &[u8]) -> io::Result<()> {
+    trace!(write_atomic);
+    use io::Write as _;
+    let path = path.as_ref();
+
+    let mut tmp_name = OsString::from(path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} does not name a file", path.display()),
+        )
+    })?);
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let res = (|| {
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        drop(file);
+        std::fs::rename(&tmp_path, path)
+    })();
+
+    if res.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+
+    res
+}
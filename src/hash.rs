@@ -1,4 +1,7 @@
+use std::fs;
 use std::io;
+use std::path::Path;
+use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
@@ -22,6 +25,12 @@ impl FileHash {
     }
 }
 
+impl AsRef<[u8]> for FileHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 pub trait FileHasher {
     type Output: AsRef<[u8]> + 'static;
 
@@ -115,49 +124,213 @@ impl<R: io::Read, S: FileHasher> io::Read for HashingReader<R, S> {
     }
 }
 
+/// Computes the plain digest of everything read from `r` with `state`.
+///
+/// Unlike [`HashingReader`], no key is mixed in, so the result matches the digest computed by other tools (such as `sha256sum`)
+pub fn digest<S: FileHasher, R: io::Read>(mut state: S, mut r: R) -> io::Result<S::Output> {
+    let mut buf = vec![0u8; S::BLOCK_SIZE];
+    let mut len = 0;
+
+    loop {
+        match r.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+
+        if len == S::BLOCK_SIZE {
+            state.update(&buf);
+            len = 0;
+        }
+    }
+
+    Ok(state.do_final(&buf[..len]))
+}
+
+pub mod blake;
 pub mod sha;
 
-// pub fn hash_file<S: FileHasher, P: AsRef<Path>>(
-//     path: P,
-//     hasher: S,
-//     key: FileHash,
-// ) -> io::Result<FileHash> {
-//     let mut buf = vec![0; S::BLOCK_SIZE];
-//     let file = fs::File::open(path)?;
+/// The hash function used to compute a [`FileHash`]
+#[derive(
+    Copy, Clone, Debug, Hash, PartialEq, Eq, serde_derive::Deserialize, serde_derive::Serialize,
+)]
+pub enum HashAlgorithm {
+    /// SHA-512/256, which configurations written before the algorithm was recorded used
+    #[serde(rename = "sha512-256")]
+    Sha512_256,
+    /// BLAKE2b with a 256-bit output, which is considerably faster than SHA-512/256
+    #[serde(rename = "blake2b-256")]
+    Blake2b256,
+}
+
+impl HashAlgorithm {
+    pub const DEFAULT: HashAlgorithm = HashAlgorithm::Blake2b256;
+
+    pub const ALL: [HashAlgorithm; 2] = [HashAlgorithm::Sha512_256, HashAlgorithm::Blake2b256];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha512_256 => "sha512-256",
+            Self::Blake2b256 => "blake2b-256",
+        }
+    }
+
+    /// The algorithm of a configuration that doesn't record one
+    pub fn legacy() -> Self {
+        Self::Sha512_256
+    }
+
+    /// Hashes everything read from `r`, keyed by `key`
+    pub fn hash_reader<R: io::Read>(self, r: R, key: FileHash) -> io::Result<FileHash> {
+        fn hash_with<R: io::Read, S: FileHasher>(
+            hasher: S,
+            r: R,
+            key: FileHash,
+        ) -> io::Result<FileHash> {
+            let mut reader = HashingReader::new(hasher, r);
+            reader.init(key);
+            io::copy(&mut reader, &mut io::sink())?;
+            Ok(reader.finish())
+        }
+
+        match self {
+            Self::Sha512_256 => hash_with(sha::Sha64State::SHA512_256, r, key),
+            Self::Blake2b256 => hash_with(blake::Blake2bState::BLAKE2B_256, r, key),
+        }
+    }
+
+    /// Hashes the contents of the file at `path`, keyed by `key`
+    pub fn hash_file<P: AsRef<Path>>(self, path: P, key: FileHash) -> io::Result<FileHash> {
+        let path = path.as_ref();
+        let file = fs::File::open(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+
+        self.hash_reader(file, key)
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|alg| alg.name() == s)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown hash algorithm `{}`", s),
+                )
+            })
+    }
+}
+
+/// A hash function producing plain digests, as used by checksum files and other tools
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    Sha1,
+    Sha224,
+    Sha256,
+    Sha384,
+    Sha512,
+    Sha512_256,
+    Blake2b512,
+    Blake2b256,
+}
+
+impl DigestAlgorithm {
+    pub const ALL: [DigestAlgorithm; 8] = [
+        DigestAlgorithm::Sha1,
+        DigestAlgorithm::Sha224,
+        DigestAlgorithm::Sha256,
+        DigestAlgorithm::Sha384,
+        DigestAlgorithm::Sha512,
+        DigestAlgorithm::Sha512_256,
+        DigestAlgorithm::Blake2b512,
+        DigestAlgorithm::Blake2b256,
+    ];
+
+    /// The name of the algorithm. `blake2b` is BLAKE2b-512, as computed by `b2sum`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha1 => "sha1",
+            Self::Sha224 => "sha224",
+            Self::Sha256 => "sha256",
+            Self::Sha384 => "sha384",
+            Self::Sha512 => "sha512",
+            Self::Sha512_256 => "sha512-256",
+            Self::Blake2b512 => "blake2b",
+            Self::Blake2b256 => "blake2b-256",
+        }
+    }
 
-//     let mut reader = HashingReader::new(hasher, file);
+    /// Computes the digest of everything read from `r`
+    pub fn digest_reader<R: io::Read>(self, r: R) -> io::Result<Vec<u8>> {
+        Ok(match self {
+            Self::Sha1 => digest(sha::Sha1State::SHA1, r)?.to_vec(),
+            Self::Sha224 => digest(sha::Sha32State::SHA224, r)?[..28].to_vec(),
+            Self::Sha256 => digest(sha::Sha32State::SHA256, r)?.to_vec(),
+            Self::Sha384 => digest(sha::Sha64State::SHA384, r)?[..48].to_vec(),
+            Self::Sha512 => digest(sha::Sha64State::SHA512, r)?.to_vec(),
+            Self::Sha512_256 => digest(sha::Sha64State::SHA512_256, r)?[..32].to_vec(),
+            Self::Blake2b512 => digest(blake::Blake2bState::BLAKE2B_512, r)?
+                .as_ref()
+                .to_vec(),
+            Self::Blake2b256 => digest(blake::Blake2bState::BLAKE2B_256, r)?
+                .as_ref()
+                .to_vec(),
+        })
+    }
+}
 
-//     reader.init(key);
+impl FromStr for DigestAlgorithm {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> io::Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|alg| alg.name() == s)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unknown digest algorithm `{}`", s),
+                )
+            })
+    }
+}
 
-//     loop {
-//         if reader.read(&mut buf)? == 0 {
-//             break;
-//         }
-//     }
+/// Formats `bytes` as lowercase hex, in order
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut string = String::with_capacity(bytes.len() * 2);
 
-//     Ok(reader.finish())
-// }
+    for b in bytes {
+        string.push(ALPHA[(b >> 4) as usize] as char);
+        string.push(ALPHA[(b & 0xf) as usize] as char);
+    }
+
+    string
+}
 
 const ALPHA: [u8; 16] = [
     b'0', b'1', b'2', b'3', b'4', b'5', b'6', b'7', b'8', b'9', b'a', b'b', b'c', b'd', b'e', b'f',
 ];
 
+impl core::fmt::Display for FileHash {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut bytes = self.0;
+        bytes.reverse();
+
+        f.write_str(&to_hex(&bytes))
+    }
+}
+
 impl Serialize for FileHash {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        let mut string = Vec::<u8>::with_capacity(64);
-
-        for b in self.0.iter().rev() {
-            string.push(ALPHA[(b >> 4) as usize]);
-            string.push(ALPHA[(b & 0xf) as usize]);
-        }
-
-        // SAFETY: The Vec is entirely ASCII
-        let string = unsafe { String::from_utf8_unchecked(string) };
-
-        serializer.serialize_str(&string)
+        serializer.collect_str(self)
     }
 }
 
@@ -193,16 +366,10 @@ impl<'de> Deserialize<'de> for FileHash {
                                 hi @ (b'0'..=b'9' | b'A'..=b'F' | b'a'..=b'f'),
                                 lo @ (b'0'..=b'9' | b'A'..=b'F' | b'a'..=b'f'),
                             ) => {
-                                let mut mask = (hi << 1) & 0xF0 | (lo >> 3) & 0xF;
-
-                                mask |= mask >> 1;
-                                mask |= mask >> 2;
-
-                                let val = (hi << 4) & 0xF0 | lo & 0x0F;
-
-                                let val = val + (0xAA & mask);
+                                // Letters have bit 6 set, and their low nibble is one past the digit value - 10
+                                let nibble = |c: u8| (c & 0xF) + 9 * (c >> 6);
 
-                                Ok(val)
+                                Ok(nibble(hi) << 4 | nibble(lo))
                             }
                             _ => Err(E::invalid_value(serde::de::Unexpected::Str(v), &self)),
                         }
@@ -1 +1,255 @@
+use super::FileHasher;
 
+const IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+const SIGMA: [[usize; 16]; 12] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+];
+
+/// The state of a BLAKE2b hash (RFC 7693), producing up to 64 bytes of output
+#[derive(Copy, Clone)]
+pub struct Blake2bState {
+    state: [u64; 8],
+    processed_bytes: u128,
+    /// The final block must be compressed with the finalization flag, but a full block may turn out to be the last one.
+    /// So each full block is held back until the next block (or the tail) is given.
+    pending: Option<[u8; 128]>,
+    output_len: usize,
+}
+
+#[allow(dead_code)]
+impl Blake2bState {
+    pub const BLAKE2B_512: Blake2bState = Blake2bState::with_output_len(64);
+
+    pub const BLAKE2B_256: Blake2bState = Blake2bState::with_output_len(32);
+
+    /// The initial state of an unkeyed hash, producing `output_len` bytes.
+    ///
+    /// Panics if `output_len` is not between 1 and 64
+    pub const fn with_output_len(output_len: usize) -> Self {
+        assert!(output_len > 0 && output_len <= 64);
+        let mut state = IV;
+        // Parameter block: digest length, no key, fanout 1, depth 1
+        state[0] ^= 0x01010000 ^ (output_len as u64);
+        Self {
+            state,
+            processed_bytes: 0,
+            pending: None,
+            output_len,
+        }
+    }
+
+    #[inline]
+    fn compress(&mut self, block: &[u8; 128], last: bool) {
+        #[inline(always)]
+        fn g(v: &mut [u64; 16], a: usize, b: usize, c: usize, d: usize, x: u64, y: u64) {
+            v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+            v[d] = (v[d] ^ v[a]).rotate_right(32);
+            v[c] = v[c].wrapping_add(v[d]);
+            v[b] = (v[b] ^ v[c]).rotate_right(24);
+            v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+            v[d] = (v[d] ^ v[a]).rotate_right(16);
+            v[c] = v[c].wrapping_add(v[d]);
+            v[b] = (v[b] ^ v[c]).rotate_right(63);
+        }
+
+        let mut m = [0u64; 16];
+        for (m, v) in m.iter_mut().zip(block.chunks_exact(8)) {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(v);
+            *m = u64::from_le_bytes(bytes);
+        }
+
+        let mut v = [0u64; 16];
+        v[..8].copy_from_slice(&self.state);
+        v[8..].copy_from_slice(&IV);
+
+        v[12] ^= self.processed_bytes as u64;
+        v[13] ^= (self.processed_bytes >> 64) as u64;
+        if last {
+            v[14] = !v[14];
+        }
+
+        for s in &SIGMA {
+            g(&mut v, 0, 4, 8, 12, m[s[0]], m[s[1]]);
+            g(&mut v, 1, 5, 9, 13, m[s[2]], m[s[3]]);
+            g(&mut v, 2, 6, 10, 14, m[s[4]], m[s[5]]);
+            g(&mut v, 3, 7, 11, 15, m[s[6]], m[s[7]]);
+            g(&mut v, 0, 5, 10, 15, m[s[8]], m[s[9]]);
+            g(&mut v, 1, 6, 11, 12, m[s[10]], m[s[11]]);
+            g(&mut v, 2, 7, 8, 13, m[s[12]], m[s[13]]);
+            g(&mut v, 3, 4, 9, 14, m[s[14]], m[s[15]]);
+        }
+
+        for (i, h) in self.state.iter_mut().enumerate() {
+            *h ^= v[i] ^ v[i + 8];
+        }
+    }
+}
+
+impl FileHasher for Blake2bState {
+    type Output = Blake2bOutput;
+    const BLOCK_SIZE: usize = 128;
+
+    #[inline]
+    fn update(&mut self, msg: &[u8]) {
+        assert!(msg.len() == 128);
+
+        if let Some(pending) = self.pending.take() {
+            self.compress(&pending, false);
+        }
+
+        let mut block = [0u8; 128];
+        block.copy_from_slice(msg);
+
+        self.processed_bytes += 128;
+        self.pending = Some(block);
+    }
+
+    #[inline]
+    fn do_final(mut self, msg_tail: &[u8]) -> Blake2bOutput {
+        assert!(msg_tail.len() < 128);
+
+        match self.pending.take() {
+            // A message that fills its last block exactly ends with that block
+            Some(pending) if msg_tail.is_empty() => self.compress(&pending, true),
+            pending => {
+                if let Some(pending) = pending {
+                    self.compress(&pending, false);
+                }
+
+                let mut block = [0u8; 128];
+                block[..msg_tail.len()].copy_from_slice(msg_tail);
+                self.processed_bytes += msg_tail.len() as u128;
+                self.compress(&block, true);
+            }
+        }
+
+        let mut bytes = [0u8; 64];
+
+        for (output, val) in bytes.chunks_exact_mut(8).zip(self.state) {
+            output.copy_from_slice(&val.to_le_bytes())
+        }
+
+        Blake2bOutput {
+            bytes,
+            len: self.output_len,
+        }
+    }
+}
+
+/// The digest of a [`Blake2bState`], which is as long as the output length it was created with
+#[derive(Copy, Clone, Debug)]
+pub struct Blake2bOutput {
+    bytes: [u8; 64],
+    len: usize,
+}
+
+impl AsRef<[u8]> for Blake2bOutput {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Blake2bState;
+    use crate::hash::digest;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn blake2b_512() {
+        assert_eq!(
+            hex(digest(Blake2bState::BLAKE2B_512, &b""[..]).unwrap().as_ref()),
+            "786a02f742015903c6c6fd852552d272912f4740e15847618a86e217f71f5419d25e1031afee585313896444934eb04b903a685b1448b755d56f701afe9be2ce"
+        );
+        // RFC 7693, Appendix A
+        assert_eq!(
+            hex(digest(Blake2bState::BLAKE2B_512, &b"abc"[..]).unwrap().as_ref()),
+            "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923"
+        );
+    }
+
+    #[test]
+    fn blake2b_256() {
+        assert_eq!(
+            hex(digest(Blake2bState::BLAKE2B_256, &b""[..])
+                .unwrap()
+                .as_ref()),
+            "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8"
+        );
+        assert_eq!(
+            hex(digest(Blake2bState::BLAKE2B_256, &b"abc"[..])
+                .unwrap()
+                .as_ref()),
+            "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319"
+        );
+    }
+
+    #[test]
+    fn block_boundaries() {
+        // A message that fills its last block exactly must finalize that block rather than an empty one
+        let cases = [
+            (
+                0,
+                "786a02f742015903c6c6fd852552d272",
+                "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8",
+            ),
+            (
+                127,
+                "48ecda8767100b9741d220295b2a4dde",
+                "07e3b27589f5174ed036e5368e25951911891d0480aa5cf720b05fa4cbd2bbf5",
+            ),
+            (
+                128,
+                "a60f05571d05c7cf7b46146739e85fec",
+                "1f7d4642e96d746bafdc2f63ef0cf4e5083616edff9f1ba3e4f901dc731664c6",
+            ),
+            (
+                129,
+                "d0c51da7c07a55dfe28cb72bb78edc67",
+                "1a54ddf12e8f18fec47c87a4d3c09bb30197463be32cf22b100e6ba85a7a9b84",
+            ),
+            (
+                256,
+                "c0984974e0291b6cbc1b10e682566b19",
+                "0573633838ba938fb394bbd1554255f1d2aaa7dc75fc35a7c42e2bd24e7cb8b7",
+            ),
+        ];
+
+        for (len, blake2b_512, blake2b_256) in cases {
+            let msg = vec![0x5au8; len];
+            assert_eq!(
+                hex(&digest(Blake2bState::BLAKE2B_512, &*msg).unwrap().as_ref()[..16]),
+                blake2b_512
+            );
+            assert_eq!(
+                hex(digest(Blake2bState::BLAKE2B_256, &*msg).unwrap().as_ref()),
+                blake2b_256
+            );
+        }
+    }
+}
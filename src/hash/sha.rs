@@ -221,7 +221,8 @@ impl FileHasher for Sha64State {
 
         real_msg[tail_len] = 0x80;
 
-        if tail_len < 17 {
+        // The length needs 16 bytes after the terminator, which may not fit in the tail's block
+        if tail_len >= 112 {
             self.update_with(&real_msg);
             real_msg.fill(0);
         }
@@ -241,3 +242,402 @@ impl FileHasher for Sha64State {
         output
     }
 }
+
+/// The state of a SHA-2 hash with 32-bit words (SHA-256 and SHA-224)
+#[derive(Copy, Clone)]
+pub struct Sha32State {
+    state: [u32; 8],
+    processed_bytes: u64,
+}
+
+#[allow(dead_code)]
+impl Sha32State {
+    pub const SHA256: Sha32State = Sha32State::with_init([
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ]);
+
+    /// Only the first 28 bytes of the output are the SHA-224 digest
+    pub const SHA224: Sha32State = Sha32State::with_init([
+        0xc1059ed8, 0x367cd507, 0x3070dd17, 0xf70e5939, 0xffc00b31, 0x68581511, 0x64f98fa7,
+        0xbefa4fa4,
+    ]);
+
+    pub const fn with_init(state: [u32; 8]) -> Self {
+        Self {
+            state,
+            processed_bytes: 0,
+        }
+    }
+
+    #[inline]
+    fn compress(&mut self, w: &[u32; 64]) {
+        static K: [u32; 64] = [
+            0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+            0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+            0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+            0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+            0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+            0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+            0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+            0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+            0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+            0xc67178f2,
+        ];
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        #[allow(non_snake_case)] // Using the names from the algorithm
+        for i in 0..64 {
+            let S0 = (a.rotate_right(2)) ^ (a.rotate_right(13)) ^ (a.rotate_right(22));
+            let S1 = (e.rotate_right(6)) ^ (e.rotate_right(11)) ^ (e.rotate_right(25));
+            let ch = (e & f) ^ (!e & g);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp1 = h
+                .wrapping_add(S1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let temp2 = S0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (h, v) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *h = (*h).wrapping_add(v);
+        }
+    }
+
+    #[inline]
+    fn update_with(&mut self, msg: &[u8]) {
+        let mut w = [0u32; 64];
+
+        for (w, v) in w.iter_mut().zip(msg.chunks_exact(4)) {
+            *w = u32::from_be_bytes([v[0], v[1], v[2], v[3]]);
+        }
+
+        for i in 16..64 {
+            let s0 = (w[i - 15].rotate_right(7)) ^ (w[i - 15].rotate_right(18)) ^ (w[i - 15] >> 3);
+            let s1 = (w[i - 2].rotate_right(17)) ^ (w[i - 2].rotate_right(19)) ^ (w[i - 2] >> 10);
+
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        self.compress(&w);
+    }
+}
+
+impl FileHasher for Sha32State {
+    type Output = [u8; 32];
+    const BLOCK_SIZE: usize = 64;
+
+    #[inline]
+    fn update(&mut self, msg: &[u8]) {
+        assert!(msg.len() == 64);
+
+        self.processed_bytes += 64;
+
+        self.update_with(msg);
+    }
+
+    #[inline]
+    fn do_final(mut self, msg_tail: &[u8]) -> [u8; 32] {
+        assert!(msg_tail.len() < 64);
+
+        let mut real_msg = [0u8; 64];
+        let tail_len = msg_tail.len();
+
+        let processed_bytes = self.processed_bytes + (tail_len as u64);
+
+        real_msg[..tail_len].copy_from_slice(msg_tail);
+
+        real_msg[tail_len] = 0x80;
+
+        // The length needs 8 bytes after the terminator, which may not fit in the tail's block
+        if tail_len >= 56 {
+            self.update_with(&real_msg);
+            real_msg.fill(0);
+        }
+
+        let processed_bits = processed_bytes << 3;
+
+        real_msg[56..].copy_from_slice(&processed_bits.to_be_bytes());
+
+        self.update_with(&real_msg);
+
+        let mut output = [0u8; 32];
+
+        for (output, val) in output.chunks_exact_mut(4).zip(self.state) {
+            output.copy_from_slice(&val.to_be_bytes())
+        }
+
+        output
+    }
+}
+
+/// The state of a SHA-1 hash.
+///
+/// SHA-1 is broken for collision resistance, and is only provided to compute identifiers used by other tools, such as git object names
+#[derive(Copy, Clone)]
+pub struct Sha1State {
+    state: [u32; 5],
+    processed_bytes: u64,
+}
+
+#[allow(dead_code)]
+impl Sha1State {
+    pub const SHA1: Sha1State = Sha1State {
+        state: [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0],
+        processed_bytes: 0,
+    };
+
+    #[inline]
+    fn update_with(&mut self, msg: &[u8]) {
+        let mut w = [0u32; 80];
+
+        for (w, v) in w.iter_mut().zip(msg.chunks_exact(4)) {
+            *w = u32::from_be_bytes([v[0], v[1], v[2], v[3]]);
+        }
+
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+
+        for (i, w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (h, v) in self.state.iter_mut().zip([a, b, c, d, e]) {
+            *h = (*h).wrapping_add(v);
+        }
+    }
+}
+
+impl FileHasher for Sha1State {
+    type Output = [u8; 20];
+    const BLOCK_SIZE: usize = 64;
+
+    #[inline]
+    fn update(&mut self, msg: &[u8]) {
+        assert!(msg.len() == 64);
+
+        self.processed_bytes += 64;
+
+        self.update_with(msg);
+    }
+
+    #[inline]
+    fn do_final(mut self, msg_tail: &[u8]) -> [u8; 20] {
+        assert!(msg_tail.len() < 64);
+
+        let mut real_msg = [0u8; 64];
+        let tail_len = msg_tail.len();
+
+        let processed_bytes = self.processed_bytes + (tail_len as u64);
+
+        real_msg[..tail_len].copy_from_slice(msg_tail);
+
+        real_msg[tail_len] = 0x80;
+
+        if tail_len >= 56 {
+            self.update_with(&real_msg);
+            real_msg.fill(0);
+        }
+
+        let processed_bits = processed_bytes << 3;
+
+        real_msg[56..].copy_from_slice(&processed_bits.to_be_bytes());
+
+        self.update_with(&real_msg);
+
+        let mut output = [0u8; 20];
+
+        for (output, val) in output.chunks_exact_mut(4).zip(self.state) {
+            output.copy_from_slice(&val.to_be_bytes())
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Sha1State, Sha32State, Sha64State};
+    use crate::hash::digest;
+
+    const MSG_448: &[u8] = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn million_a() -> Vec<u8> {
+        vec![b'a'; 1_000_000]
+    }
+
+    #[test]
+    fn sha256() {
+        let cases: [(&[u8], &str); 3] = [
+            (
+                b"",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                b"abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                MSG_448,
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+        ];
+
+        for (msg, expected) in cases {
+            assert_eq!(hex(&digest(Sha32State::SHA256, msg).unwrap()), expected);
+        }
+
+        assert_eq!(
+            hex(&digest(Sha32State::SHA256, &*million_a()).unwrap()),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn sha224() {
+        let cases: [(&[u8], &str); 3] = [
+            (
+                b"",
+                "d14a028c2a3a2bc9476102bb288234c415a2b01f828ea62ac5b3e42f",
+            ),
+            (
+                b"abc",
+                "23097d223405d8228642a477bda255b32aadbce4bda0b3f7e36c9da7",
+            ),
+            (
+                MSG_448,
+                "75388b16512776cc5dba5da1fd890150b0c6455cb4f58b1952522525",
+            ),
+        ];
+
+        for (msg, expected) in cases {
+            assert_eq!(
+                hex(&digest(Sha32State::SHA224, msg).unwrap()[..28]),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn sha1() {
+        let cases: [(&[u8], &str); 3] = [
+            (b"", "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+            (b"abc", "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            (MSG_448, "84983e441c3bd26ebaae4aa1f95129e5e54670f1"),
+        ];
+
+        for (msg, expected) in cases {
+            assert_eq!(hex(&digest(Sha1State::SHA1, msg).unwrap()), expected);
+        }
+
+        assert_eq!(
+            hex(&digest(Sha1State::SHA1, &*million_a()).unwrap()),
+            "34aa973cd4c4daa4f61eeb2bdbad27316534016f"
+        );
+    }
+
+    #[test]
+    fn sha512() {
+        assert_eq!(
+            hex(&digest(Sha64State::SHA512, &b""[..]).unwrap()),
+            "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"
+        );
+        assert_eq!(
+            hex(&digest(Sha64State::SHA512, &b"abc"[..]).unwrap()),
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+        assert_eq!(
+            hex(&digest(Sha64State::SHA512_256, &b"abc"[..]).unwrap()[..32]),
+            "53048e2681941ef99b2e29b76b4c7dabe4c2d0c634fc6d46e0e2f13107e7af23"
+        );
+    }
+
+    #[test]
+    fn padding_boundaries() {
+        // Lengths around the point where the message length no longer fits in the final block
+        let cases = [
+            (
+                55,
+                "5f25f149aa92e3e13093aed8216072fae623f35e26ca605b6cce17e04b7ccf44",
+                "55b80d96c523566d3c8a3b8de03a5549fd04915c",
+                "ec6804e4c4322e6b66925d535d88d689",
+            ),
+            (
+                56,
+                "301c69927f1603720c9f847b7e5e3bef77a7b9f75344490fe9039f13c36b842a",
+                "bfe3466cd0dcd5e29b11e7885010fa7c61b737a6",
+                "4ce5ef415c3182ed34ad333727f00951",
+            ),
+            (
+                64,
+                "cc7321cce5e4409bd8077d58422e1214969059bbd40b4eeb0de0a642f40f7282",
+                "eece723b8a411e8c53e7bf49514234da5d394236",
+                "9fe6e4f426a9910feab46508a97b58f9",
+            ),
+            (
+                111,
+                "d6a176283e178096b5e9ca970527f602511de5e958f7c324bdceeb5508a55e14",
+                "565095451674dfb0e22515c8201471390025bb1c",
+                "421318daeb8461d426c4e5a8be95e8d3",
+            ),
+            (
+                112,
+                "63655fc0b11b1eda2a7ab24a5fd1d5216558ef4c0076a828ccb1159aa572513f",
+                "c039dcff5bac72c3c0478d8bd48b0411f9738b30",
+                "efa85a2ad32eee7cd93fe9ef92a7f260",
+            ),
+            (
+                128,
+                "349d65e9ba1de7b0a13f9a3eadcc5b0202f15d6008fe9477f2a7b80f6194b20f",
+                "3f3bf5bb6111e4eb5a06c996ec9bad396384707b",
+                "ed24df3079846053b9f164968155d8c7",
+            ),
+        ];
+
+        for (len, sha256, sha1, sha512) in cases {
+            let msg = vec![0x5au8; len];
+            assert_eq!(hex(&digest(Sha32State::SHA256, &*msg).unwrap()), sha256);
+            assert_eq!(hex(&digest(Sha1State::SHA1, &*msg).unwrap()), sha1);
+            assert_eq!(
+                hex(&digest(Sha64State::SHA512, &*msg).unwrap()[..16]),
+                sha512
+            );
+        }
+    }
+}
@@ -6,6 +6,24 @@ use std::{
 use crate::log::{log_debug, trace};
 mod store;
 
+/// Generates [`InstallDirs::ARGS`] and [`InstallDirs::set_from_arg`] from one list of `flag => field: description`
+macro_rules! install_dir_args {
+    ($($flag:literal => $field:ident: $desc:literal,)*) => {
+        /// The command line flags accepted by [`InstallDirs::set_from_arg`], with a description of each
+        pub const ARGS: [(&'static str, &'static str); [$($flag),*].len()] = [$(($flag, $desc)),*];
+
+        pub fn set_from_arg(&mut self, key: &str, val: String) -> Result<(), ()> {
+            trace!(InstallDirs::set_from_arg);
+            match key {
+                $($flag => self.$field = Some(PathBuf::from(val)),)*
+                _ => return Err(()),
+            }
+
+            Ok(())
+        }
+    };
+}
+
 #[derive(Default, Clone, Debug, Hash, PartialEq, Eq)]
 #[non_exhaustive]
 pub struct InstallDirs {
@@ -70,30 +88,24 @@ impl InstallDirs {
             .push(target)
     }
 
-    pub fn set_from_arg(&mut self, key: &str, val: String) -> Result<(), ()> {
-        trace!(InstallDirs::set_from_arg);
-        match key {
-            "--prefix" => self.prefix = Some(PathBuf::from(val)),
-            "--exec-prefix" => self.exec_prefix = Some(PathBuf::from(val)),
-            "--bindir" => self.bindir = Some(PathBuf::from(val)),
-            "--sbindir" => self.sbindir = Some(PathBuf::from(val)),
-            "--libdir" => self.libdir = Some(PathBuf::from(val)),
-            "--libexecdir" => self.libexecdir = Some(PathBuf::from(val)),
-            "--includedir" => self.includedir = Some(PathBuf::from(val)),
-            "--datarootdir" => self.datarootdir = Some(PathBuf::from(val)),
-            "--datadir" => self.datadir = Some(PathBuf::from(val)),
-            "--mandir" => self.mandir = Some(PathBuf::from(val)),
-            "--docdir" => self.docdir = Some(PathBuf::from(val)),
-            "--infodir" => self.infodir = Some(PathBuf::from(val)),
-            "--localedir" => self.localedir = Some(PathBuf::from(val)),
-            "--localstatedir" => self.localstatedir = Some(PathBuf::from(val)),
-            "--runstatedir" => self.runstatedir = Some(PathBuf::from(val)),
-            "--sharedstatedir" => self.sharedstatedir = Some(PathBuf::from(val)),
-            "--sysconfdir" => self.sysconfdir = Some(PathBuf::from(val)),
-            _ => return Err(()),
-        }
-
-        Ok(())
+    install_dir_args! {
+        "--prefix" => prefix: "Installation prefix",
+        "--exec-prefix" => exec_prefix: "Prefix for architecture-dependent files",
+        "--bindir" => bindir: "User executables",
+        "--sbindir" => sbindir: "System administrator executables",
+        "--libdir" => libdir: "Object code libraries",
+        "--libexecdir" => libexecdir: "Executables run by other programs",
+        "--includedir" => includedir: "C header files",
+        "--datarootdir" => datarootdir: "Read-only architecture-independent data root",
+        "--datadir" => datadir: "Read-only architecture-independent data",
+        "--mandir" => mandir: "Man pages",
+        "--docdir" => docdir: "Documentation",
+        "--infodir" => infodir: "Info documentation",
+        "--localedir" => localedir: "Locale-dependent data",
+        "--localstatedir" => localstatedir: "Modifiable single-machine data",
+        "--runstatedir" => runstatedir: "Modifiable per-process data",
+        "--sharedstatedir" => sharedstatedir: "Modifiable architecture-independent data",
+        "--sysconfdir" => sysconfdir: "Read-only single-machine data",
     }
 
     pub fn read_env(&mut self) {
@@ -601,4 +613,61 @@ impl InstallDirs {
             self.sysconfdir = Some(sysconfdir.clone());
         }
     }
+
+    /// Replaces the directories that still have the value from `old` with their value in `new`.
+    ///
+    /// Used to update the directories that came from a set of defaults, without replacing the ones set since.
+    pub fn replace_from(&mut self, old: &InstallDirs, new: &InstallDirs) {
+        if self.prefix == old.prefix {
+            self.prefix = new.prefix.clone();
+        }
+        if self.exec_prefix == old.exec_prefix {
+            self.exec_prefix = new.exec_prefix.clone();
+        }
+        if self.bindir == old.bindir {
+            self.bindir = new.bindir.clone();
+        }
+        if self.sbindir == old.sbindir {
+            self.sbindir = new.sbindir.clone();
+        }
+        if self.libdir == old.libdir {
+            self.libdir = new.libdir.clone();
+        }
+        if self.libexecdir == old.libexecdir {
+            self.libexecdir = new.libexecdir.clone();
+        }
+        if self.includedir == old.includedir {
+            self.includedir = new.includedir.clone();
+        }
+        if self.datarootdir == old.datarootdir {
+            self.datarootdir = new.datarootdir.clone();
+        }
+        if self.datadir == old.datadir {
+            self.datadir = new.datadir.clone();
+        }
+        if self.mandir == old.mandir {
+            self.mandir = new.mandir.clone();
+        }
+        if self.docdir == old.docdir {
+            self.docdir = new.docdir.clone();
+        }
+        if self.infodir == old.infodir {
+            self.infodir = new.infodir.clone();
+        }
+        if self.localedir == old.localedir {
+            self.localedir = new.localedir.clone();
+        }
+        if self.localstatedir == old.localstatedir {
+            self.localstatedir = new.localstatedir.clone();
+        }
+        if self.runstatedir == old.runstatedir {
+            self.runstatedir = new.runstatedir.clone();
+        }
+        if self.sharedstatedir == old.sharedstatedir {
+            self.sharedstatedir = new.sharedstatedir.clone();
+        }
+        if self.sysconfdir == old.sysconfdir {
+            self.sysconfdir = new.sysconfdir.clone();
+        }
+    }
 }
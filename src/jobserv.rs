@@ -7,6 +7,8 @@ use std::path::Path;
 
 use core::marker::PhantomData;
 
+use crate::log::trace;
+
 mod unix;
 mod windows;
 
@@ -148,4 +150,82 @@ impl<'a> JobServer<'a> {
     pub fn generate_auth(&self) -> OsString {
         self.con.generate_auth()
     }
+
+    /// Connects to the jobserver of the `make` (or `cargo`) running autobuild, if `MAKEFLAGS` or `CARGO_MAKEFLAGS` names one
+    pub fn from_env() -> io::Result<Option<Self>> {
+        trace!(JobServer::from_env);
+        for var in ["CARGO_MAKEFLAGS", "MAKEFLAGS"] {
+            let Ok(flags) = std::env::var(var) else {
+                continue;
+            };
+
+            // The last flag wins, as with make
+            let auth = flags.split_ascii_whitespace().rev().find_map(|flag| {
+                flag.strip_prefix("--jobserver-auth=")
+                    .or_else(|| flag.strip_prefix("--jobserver-fds="))
+            });
+
+            if let Some(auth) = auth {
+                return Ok(Some(Self {
+                    con: Connection::parse_auth(auth)?,
+                    phantom_life_token: PhantomData,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Creates a jobserver that allows `jobs` jobs to run at once, including the one that autobuild implicitly holds
+    pub fn new(jobs: usize) -> io::Result<Self> {
+        trace!(JobServer::new);
+        Ok(Self {
+            con: Connection::PipePair(unix::PipePair::new(jobs.saturating_sub(1))?),
+            phantom_life_token: PhantomData,
+        })
+    }
+
+    /// Waits for a token, which is released when the returned guard is dropped
+    pub fn acquire(&self) -> io::Result<JobToken<'_>> {
+        let tok = self.con.acquire()?;
+        Ok(JobToken {
+            con: &self.con,
+            tok: Some(tok),
+        })
+    }
+
+    /// Adds `n` tokens to the pool, to wake up `n` threads blocked in [`JobServer::acquire`] that no longer need a token.
+    ///
+    /// Each thread woken this way must give up the token it gets with [`JobToken::forget`], so the pool keeps its size
+    pub fn wake_waiters(&self, n: usize) -> io::Result<()> {
+        for _ in 0..n {
+            self.con.release(match self.con {
+                Connection::Semaphore(_) => Token::Semaphore,
+                _ => Token::Char(b'+'),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl JobToken<'_> {
+    /// Drops the token without returning it to the pool
+    pub fn forget(mut self) {
+        self.tok = None;
+    }
+}
+
+/// A token held from a [`JobServer`], allowing one more job to run
+pub struct JobToken<'a> {
+    con: &'a Connection,
+    tok: Option<Token>,
+}
+
+impl Drop for JobToken<'_> {
+    fn drop(&mut self) {
+        if let Some(tok) = self.tok.take() {
+            // A token that can't be returned only reduces the parallelism of the build
+            let _ = self.con.release(tok);
+        }
+    }
 }
@@ -27,7 +27,9 @@ impl PipePair {
 
         let mut buf = vec![b'P'; token_count];
 
-        if unsafe { libc::write(write_fd_no, buf.as_mut_ptr().cast(), token_count as _) } != 0 {
+        if unsafe { libc::write(write_fd_no, buf.as_mut_ptr().cast(), token_count as _) }
+            != token_count as isize
+        {
             return Err(io::Error::last_os_error());
         }
 
@@ -3,6 +3,7 @@ use std::io::{self, IsTerminal};
 
 mod config;
 mod consts;
+mod elf;
 mod fs;
 mod hash;
 mod helpers;
@@ -15,6 +16,7 @@ mod programs;
 mod rand;
 mod serialize;
 mod set;
+mod tar;
 mod targ;
 mod tools;
 
@@ -7,6 +7,9 @@ use target_tuples::Target;
 
 use crate::hash::FileHash;
 
+pub mod artifacts;
+pub mod cache;
+pub mod rpath;
 pub mod rustc;
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
@@ -22,7 +25,6 @@ pub struct DepInfo {
     pub hash: FileHash,
 }
 
-#[allow(dead_code)]
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 pub enum CompileTaskStep {
     Compile,
@@ -45,12 +47,16 @@ pub trait Compiler {
 #[allow(dead_code)]
 pub trait CompileTask {
     fn compiler(&self) -> &dyn Compiler;
+    /// Adds a source file to compile. For rustc, this is the crate root
+    fn add_source(&mut self, src: &Path);
     fn add_compile_lib(&mut self, lib: &Path);
     fn add_link_lib(&mut self, lib: &dyn CompileTask);
     fn add_preprocess_lib(&mut self, lib: &dyn CompileTask);
     fn add_compile_flag(&mut self, flag: &OsStr);
     fn add_link_flag(&mut self, flag: &OsStr);
     fn add_preprocess_flag(&mut self, flag: &OsStr);
+    /// Sets the library search path of the linked output, such as `$ORIGIN/../lib`
+    fn set_rpath(&mut self, rpath: &str);
     fn name(&self) -> &str;
     fn link_outputs(&self) -> Vec<&Path>;
     fn run_outputs(&self) -> Vec<&Path>;
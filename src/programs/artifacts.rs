@@ -0,0 +1,298 @@
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    fs::FileLock,
+    hash::{FileHash, HashAlgorithm},
+    log::{log_debug, trace, LogLevel},
+};
+
+use super::{cache::cache_dir, DepInfo};
+
+/// The size the artifact cache is trimmed to if `AUTOBUILD_ARTIFACT_CACHE_SIZE` isn't set (10 GiB)
+pub const DEFAULT_MAX_SIZE: u64 = 10 << 30;
+
+/// Hit and miss counts of the artifact cache, kept since it was last cleared
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ArtifactCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+/// A cache of compile outputs shared by every build directory of the user.
+///
+/// Outputs are stored in a directory named by their key (see [`artifact_key`]), as `<cache>/artifacts/<key>/<n>`
+/// for the `n`th output of the compile task.
+///
+/// Entries are restored and evicted with the cache locked, so that an entry is never removed while it is being copied.
+/// Entries are stored without the lock, since they only appear once complete
+pub struct ArtifactCache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+/// Builds the key of the outputs of a compile task.
+///
+/// The key covers the identity of the compiler (its binary, toolchain, and target) and of the wrapper it runs through, the flags
+///  given to it, the content of the libraries it links to (by crate name), the inputs it reads, and the names of the outputs.
+/// The inputs are listed before the compiler runs, so the key doesn't depend on an earlier build. Nothing is hashed with the
+///  key of a configuration, and paths in the build directory are left out, so the key is the same in every build directory
+pub fn artifact_key(
+    compiler: FileHash,
+    wrapper: FileHash,
+    flags: &[&OsStr],
+    libs: &[(&str, FileHash)],
+    inputs: &[DepInfo],
+    outputs: &[&Path],
+) -> io::Result<FileHash> {
+    let mut id = Vec::new();
+    id.extend_from_slice(compiler.as_ref());
+    id.extend_from_slice(wrapper.as_ref());
+
+    for flag in flags {
+        id.extend_from_slice(flag.as_encoded_bytes());
+        id.push(0);
+    }
+    id.push(0);
+
+    for (name, hash) in libs {
+        id.extend_from_slice(name.as_bytes());
+        id.push(0);
+        id.extend_from_slice(hash.as_ref());
+    }
+    id.push(0);
+
+    for input in inputs {
+        id.extend_from_slice(input.dep_path.as_os_str().as_encoded_bytes());
+        id.push(0);
+        id.extend_from_slice(input.hash.as_ref());
+    }
+    id.push(0);
+
+    for output in outputs {
+        if let Some(name) = output.file_name() {
+            id.extend_from_slice(name.as_encoded_bytes());
+        }
+        id.push(0);
+    }
+
+    HashAlgorithm::DEFAULT.hash_reader(&*id, FileHash::ZERO)
+}
+
+/// Parses a size such as `512M` or `10G` (in powers of 1024), as used by `AUTOBUILD_ARTIFACT_CACHE_SIZE`
+pub fn parse_size(size: &str) -> Option<u64> {
+    let (digits, shift) = match size.as_bytes().last()? {
+        b'K' | b'k' => (&size[..size.len() - 1], 10),
+        b'M' | b'm' => (&size[..size.len() - 1], 20),
+        b'G' | b'g' => (&size[..size.len() - 1], 30),
+        b'T' | b't' => (&size[..size.len() - 1], 40),
+        _ => (size, 0),
+    };
+
+    digits.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+impl ArtifactCache {
+    /// Opens the artifact cache in the user's cache directory, or [`None`] if the platform has no cache directory
+    pub fn open() -> io::Result<Option<Self>> {
+        trace!(ArtifactCache::open);
+        let Some(mut dir) = cache_dir() else {
+            return Ok(None);
+        };
+        dir.push("artifacts");
+
+        let max_size = match std::env::var("AUTOBUILD_ARTIFACT_CACHE_SIZE") {
+            Ok(size) => parse_size(&size).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("AUTOBUILD_ARTIFACT_CACHE_SIZE: `{}` is not a size", size),
+                )
+            })?,
+            Err(_) => DEFAULT_MAX_SIZE,
+        };
+
+        Ok(Some(Self::with_dir(dir, max_size)))
+    }
+
+    pub fn with_dir(dir: PathBuf, max_size: u64) -> Self {
+        Self { dir, max_size }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn entry_dir(&self, key: FileHash) -> PathBuf {
+        self.dir.join(key.to_string())
+    }
+
+    /// Locks the cache against restoring and evicting entries in other processes. The lock file is beside the cache, so clearing it keeps the lock
+    fn lock(&self) -> io::Result<FileLock> {
+        let lock_path = self.dir.with_extension("lock");
+        if let Some(parent) = lock_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        FileLock::lock_exclusive(lock_path)
+    }
+
+    fn stats_file(&self) -> PathBuf {
+        self.dir.join("stats.toml")
+    }
+
+    /// Reads the statistics of the cache. A missing or unreadable file is treated as no activity
+    pub fn stats(&self) -> ArtifactCacheStats {
+        let file = self.stats_file();
+        match fs::read_to_string(&file) {
+            Ok(st) => toml::from_str(&st).unwrap_or_else(|e| {
+                log_debug!(LogLevel::Debug, "{}: {}", file.display(), e);
+                ArtifactCacheStats::default()
+            }),
+            Err(_) => ArtifactCacheStats::default(),
+        }
+    }
+
+    fn update_stats(&self, f: impl FnOnce(&mut ArtifactCacheStats)) {
+        let mut stats = self.stats();
+        f(&mut stats);
+
+        // Statistics are best-effort, so a concurrent build may lose an update
+        let res = fs::create_dir_all(&self.dir).and_then(|_| {
+            let st = toml::to_string(&stats).map_err(io::Error::other)?;
+            fs::write(self.stats_file(), st)
+        });
+
+        if let Err(e) = res {
+            log_debug!(
+                LogLevel::Debug,
+                "Could not update artifact cache statistics: {}",
+                e
+            );
+        }
+    }
+
+    /// Copies the outputs stored under `key` to `outputs`, returning `false` if they aren't in the cache
+    pub fn restore(&self, key: FileHash, outputs: &[&Path]) -> io::Result<bool> {
+        trace!(ArtifactCache::restore);
+        let entry = self.entry_dir(key);
+        let _lock = self.lock()?;
+
+        let all_present = (0..outputs.len()).all(|n| entry.join(n.to_string()).is_file());
+        if !all_present {
+            self.update_stats(|stats| stats.misses += 1);
+            return Ok(false);
+        }
+
+        for (n, output) in outputs.iter().enumerate() {
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(entry.join(n.to_string()), output)?;
+        }
+
+        // The stamp records when the entry was last used, for eviction
+        fs::write(entry.join("stamp"), b"")?;
+
+        self.update_stats(|stats| stats.hits += 1);
+        Ok(true)
+    }
+
+    /// Stores `outputs` under `key`, then evicts the least recently used entries if the cache is too large
+    pub fn store(&self, key: FileHash, outputs: &[&Path]) -> io::Result<()> {
+        trace!(ArtifactCache::store);
+        let entry = self.entry_dir(key);
+        if entry.exists() {
+            return Ok(());
+        }
+
+        // Fill a temporary directory first, so that a concurrent build never restores a partial entry
+        let tmp = self.dir.join(format!("{}.{}.tmp", key, std::process::id()));
+        fs::create_dir_all(&tmp)?;
+
+        let res = (|| {
+            for (n, output) in outputs.iter().enumerate() {
+                fs::copy(output, tmp.join(n.to_string()))?;
+            }
+            fs::write(tmp.join("stamp"), b"")?;
+            fs::rename(&tmp, &entry)
+        })();
+
+        if let Err(e) = res {
+            let _ = fs::remove_dir_all(&tmp);
+            // Another build stored the same outputs first
+            if entry.exists() {
+                return Ok(());
+            }
+            return Err(e);
+        }
+
+        self.evict()
+    }
+
+    /// Removes the least recently used entries until the cache is no larger than its maximum size
+    pub fn evict(&self) -> io::Result<()> {
+        trace!(ArtifactCache::evict);
+        let _lock = self.lock()?;
+        let mut entries = Vec::new();
+        let mut total = 0;
+
+        for ent in fs::read_dir(&self.dir)? {
+            let ent = ent?;
+            let path = ent.path();
+            if !ent.file_type()?.is_dir() || path.extension() == Some(OsStr::new("tmp")) {
+                continue;
+            }
+
+            let mut size = 0;
+            for file in fs::read_dir(&path)? {
+                size += file?.metadata()?.len();
+            }
+
+            let used = fs::metadata(path.join("stamp"))
+                .and_then(|m| m.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+
+            total += size;
+            entries.push((used, size, path));
+        }
+
+        if total <= self.max_size {
+            return Ok(());
+        }
+
+        entries.sort_by_key(|(used, _, _)| *used);
+
+        let mut evicted = 0;
+        for (_, size, path) in entries {
+            if total <= self.max_size {
+                break;
+            }
+            log_debug!(LogLevel::Debug, "Evicting {}", path.display());
+            fs::remove_dir_all(&path)?;
+            total -= size;
+            evicted += 1;
+        }
+
+        self.update_stats(|stats| stats.evictions += evicted);
+        Ok(())
+    }
+
+    /// Removes every entry and the statistics of the cache
+    pub fn clear(&self) -> io::Result<()> {
+        trace!(ArtifactCache::clear);
+        let _lock = self.lock()?;
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(io::Error::new(
+                e.kind(),
+                format!("Could not delete {}: {}", self.dir.display(), e),
+            )),
+            _ => Ok(()),
+        }
+    }
+}
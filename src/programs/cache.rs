@@ -0,0 +1,258 @@
+use std::{
+    ffi::OsStr,
+    fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::SystemTime,
+};
+
+use serde_derive::{Deserialize, Serialize};
+use target_tuples::Target;
+
+use crate::{
+    fs::{write_atomic, FileLock},
+    hash::{FileHash, HashAlgorithm},
+    log::{log_debug, trace, LogLevel},
+    map::OrderedMap,
+};
+
+use super::rustc::RustcVersion;
+
+/// The results of probing a compiler, shared between every build directory of the user
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ProbeCache {
+    #[serde(default)]
+    pub rustc: OrderedMap<String, RustcProbe>,
+}
+
+/// Identifies the compiler binary that a probe was run against
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProbeKey {
+    pub hash: FileHash,
+    pub size: u64,
+    pub mtime: SystemTime,
+    /// The output of `rustc -vV`.
+    ///
+    /// A rustup proxy is the same binary for every toolchain, so this distinguishes the toolchain it runs
+    pub version: String,
+    pub target_spec: Option<PathBuf>,
+    pub target_spec_hash: Option<FileHash>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RustcProbe {
+    pub key: ProbeKey,
+    pub info: RustcVersion,
+}
+
+/// The directory the probe cache is stored in, or [`None`] if the platform has no cache directory
+pub fn cache_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|mut dir| {
+        dir.push("autobuild");
+        dir
+    })
+}
+
+/// The file the probe cache is stored in
+pub fn cache_file() -> Option<PathBuf> {
+    cache_dir().map(|mut file| {
+        file.push("probes.toml");
+        file
+    })
+}
+
+/// Locks the probe cache in `dir` against other writers
+fn lock(mut dir: PathBuf) -> io::Result<FileLock> {
+    dir.push("probes.lock");
+    FileLock::lock_exclusive(dir)
+}
+
+/// The name an entry is stored under, which is replaced whenever the same compiler is probed again for the same target.
+///
+/// The target is named by its canonical form, so that every spelling of a target finds the same entry
+fn entry_name(rustc: &Path, target: &Target) -> String {
+    format!("{}@{}", rustc.display(), target)
+}
+
+impl ProbeCache {
+    /// Reads the probe cache. A missing or unreadable cache is treated as empty
+    pub fn read() -> Self {
+        trace!(ProbeCache::read);
+        let Some(file) = cache_file() else {
+            return Self::default();
+        };
+
+        let st = match fs::read_to_string(&file) {
+            Ok(st) => st,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    log_debug!(LogLevel::Debug, "{}: {}", file.display(), e);
+                }
+                return Self::default();
+            }
+        };
+
+        match toml::from_str(&st) {
+            Ok(cache) => cache,
+            Err(e) => {
+                log_debug!(LogLevel::Debug, "{}: {}", file.display(), e);
+                Self::default()
+            }
+        }
+    }
+
+    /// Applies `f` to the probe cache on disk, and writes back the result.
+    ///
+    /// The cache is locked while it is read, modified, and written, so that concurrent updates from other build directories aren't lost.
+    /// The file is replaced atomically, so readers that don't take the lock never see a partial file
+    pub fn update<F: FnOnce(&mut Self)>(f: F) -> io::Result<()> {
+        trace!(ProbeCache::update);
+        let (Some(dir), Some(file)) = (cache_dir(), cache_file()) else {
+            return Ok(());
+        };
+
+        fs::create_dir_all(&dir)?;
+        let _lock = lock(dir)?;
+
+        let mut cache = Self::read();
+        f(&mut cache);
+
+        let st = toml::to_string(&cache).map_err(io::Error::other)?;
+        write_atomic(&file, st.as_bytes())
+    }
+
+    /// The key of the probe of `rustc` for `target`, if there is one
+    pub fn rustc_key(&self, rustc: &Path, target: &Target) -> Option<&ProbeKey> {
+        self.rustc
+            .get(&entry_name(rustc, target))
+            .map(|probe| &probe.key)
+    }
+
+    /// Gets the probe of `rustc` for `target`, if it was made against the same binary
+    pub fn get_rustc(
+        &self,
+        rustc: &Path,
+        target: &Target,
+        key: &ProbeKey,
+    ) -> Option<&RustcVersion> {
+        self.rustc
+            .get(&entry_name(rustc, target))
+            .filter(|probe| probe.key == *key)
+            .map(|probe| &probe.info)
+    }
+
+    pub fn insert_rustc(
+        &mut self,
+        rustc: &Path,
+        target: &Target,
+        key: ProbeKey,
+        info: RustcVersion,
+    ) {
+        self.rustc
+            .insert(entry_name(rustc, target), RustcProbe { key, info });
+    }
+}
+
+impl ProbeKey {
+    /// Identifies the rustc binary at `rustc`, and the target-spec file given to it (if any).
+    ///
+    /// If the size and modification time of the binary match `cached`, the key of a previous probe, the binary is assumed
+    ///  to be unchanged and isn't hashed or run again, unless it is a rustup proxy, whose toolchain can change without the binary changing
+    pub fn for_rustc(
+        rustc: &Path,
+        target_spec: Option<&Path>,
+        cached: Option<&ProbeKey>,
+    ) -> io::Result<Self> {
+        let metadata = fs::metadata(rustc)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", rustc.display(), e)))?;
+        let size = metadata.len();
+        let mtime = metadata.modified()?;
+
+        let unchanged = cached.filter(|key| key.size == size && key.mtime == mtime);
+
+        // The key must be the same for every build directory, so the global key of the config can't be used
+        let hash = match unchanged {
+            Some(key) => key.hash,
+            None => HashAlgorithm::DEFAULT.hash_file(rustc, FileHash::ZERO)?,
+        };
+        let target_spec_hash = target_spec
+            .map(|spec| HashAlgorithm::DEFAULT.hash_file(spec, FileHash::ZERO))
+            .transpose()?;
+
+        let version = match unchanged {
+            Some(key) if !is_rustup_proxy(rustc, &metadata) => key.version.clone(),
+            _ => rustc_version_verbose(rustc)?,
+        };
+
+        Ok(Self {
+            hash,
+            size,
+            mtime,
+            version,
+            target_spec: target_spec.map(Path::to_path_buf),
+            target_spec_hash,
+        })
+    }
+}
+
+/// Runs `rustc -vV`
+fn rustc_version_verbose(rustc: &Path) -> io::Result<String> {
+    let output = Command::new(rustc)
+        .arg(OsStr::new("-vV"))
+        .stdin(Stdio::null())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} -vV failed", rustc.display()),
+        ));
+    }
+
+    String::from_utf8(output.stdout).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Checks whether `rustc` is a rustup proxy, which rustup installs as a link to the `rustup` binary next to it
+#[allow(unused_parens)] // cfg_match is a macro that exists
+fn is_rustup_proxy(rustc: &Path, metadata: &fs::Metadata) -> bool {
+    let Ok(rustup) =
+        fs::metadata(rustc.with_file_name(format!("rustup{}", std::env::consts::EXE_SUFFIX)))
+    else {
+        return false;
+    };
+
+    cfg_match::cfg_match! {
+        unix => ({
+            use std::os::unix::fs::MetadataExt;
+
+            rustup.dev() == metadata.dev() && rustup.ino() == metadata.ino()
+        }),
+        _ => ({
+            // Without the file identity, a copy of rustup is indistinguishable from a link
+            rustup.len() == metadata.len()
+        })
+    }
+}
+
+/// Removes the probe cache, returning the path of the file removed (if it existed)
+pub fn clear() -> io::Result<Option<PathBuf>> {
+    trace!(clear);
+    let (Some(dir), Some(file)) = (cache_dir(), cache_file()) else {
+        return Ok(None);
+    };
+
+    // Without a cache directory there is nothing to remove, and nothing to lock
+    if !dir.is_dir() {
+        return Ok(None);
+    }
+    let _lock = lock(dir)?;
+
+    match fs::remove_file(&file) {
+        Ok(()) => Ok(Some(file)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            format!("Could not delete {}: {}", file.display(), e),
+        )),
+    }
+}
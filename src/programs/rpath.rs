@@ -0,0 +1,112 @@
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use target_tuples::{Target, OS};
+
+use crate::elf;
+use crate::install::InstallDirs;
+
+use super::CompileTask;
+
+/// The token that the dynamic loader of `targ` replaces with the directory of the binary, or [`None`] if it has no such token
+pub fn origin_token(targ: &Target) -> Option<&'static str> {
+    match targ.operating_system() {
+        Some(OS::Darwin | OS::MacOSX | OS::IOS | OS::TvOS | OS::WatchOS) => Some("@loader_path"),
+        Some(OS::Win32 | OS::WASI | OS::Emscripten | OS::None | OS::Unknown) | None => None,
+        Some(_) => Some("$ORIGIN"),
+    }
+}
+
+/// The path of `to` relative to the directory `from`. Both must be absolute, or relative to the same directory
+pub fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from = from.components().collect::<Vec<_>>();
+    let to = to.components().collect::<Vec<_>>();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut path = PathBuf::new();
+    for comp in &from[common..] {
+        if *comp != Component::CurDir {
+            path.push("..");
+        }
+    }
+    for comp in &to[common..] {
+        path.push(comp);
+    }
+    path
+}
+
+/// The rpath that makes a binary in `bin_dir` search each of `lib_dirs`, relative to wherever the binary is
+pub fn origin_rpath(origin: &str, bin_dir: &Path, lib_dirs: &[&Path]) -> String {
+    let mut rpath = String::new();
+    for dir in lib_dirs {
+        let rel = relative_path(bin_dir, dir);
+        let entry = if rel.as_os_str().is_empty() {
+            origin.to_string()
+        } else {
+            format!("{}/{}", origin, rel.display())
+        };
+        if !rpath.split(':').any(|e| e == entry) {
+            if !rpath.is_empty() {
+                rpath.push(':');
+            }
+            rpath.push_str(&entry);
+        }
+    }
+    rpath
+}
+
+impl InstallDirs {
+    /// The rpath that an installed binary in [`InstallDirs::bindir`] uses to find the libraries installed in [`InstallDirs::libdir`]
+    pub fn install_rpath(&self, origin: &str) -> String {
+        origin_rpath(origin, &self.bindir(), &[&self.libdir()])
+    }
+}
+
+/// Makes the binary built by `task` find the dynamic libraries built by `libs` in the build directory, through a rpath relative to the binary.
+///
+/// The rpath is padded with `/` to be at least as long as the rpath used once the binary is installed into `dirs`,
+///  so that `autobuild install` can rewrite it in place. Returns the rpath, or [`None`] if the target has no relative rpaths
+pub fn set_local_rpath(
+    task: &mut dyn CompileTask,
+    libs: &[&dyn CompileTask],
+    dirs: &InstallDirs,
+) -> Option<String> {
+    let origin = origin_token(task.compiler().target())?;
+
+    let bin_dir = task.run_outputs().first()?.parent()?.to_path_buf();
+    let lib_dirs = libs
+        .iter()
+        .flat_map(|lib| lib.run_outputs())
+        .filter_map(Path::parent)
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+    if lib_dirs.is_empty() {
+        return None;
+    }
+
+    let lib_dirs = lib_dirs.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+    let mut rpath = origin_rpath(origin, &bin_dir, &lib_dirs);
+    let install_len = dirs.install_rpath(origin).len();
+    if rpath.len() < install_len {
+        // Repeated separators name the same directory
+        rpath.extend(core::iter::repeat('/').take(install_len - rpath.len()));
+    }
+
+    task.set_rpath(&rpath);
+    Some(rpath)
+}
+
+/// Rewrites the rpath set by [`set_local_rpath`] in the binary installed at `path`, which was built for `targ`, to find the
+///  libraries installed into `dirs`.
+///
+/// Returns `false` if the rpath of a binary for `targ` can't be rewritten
+pub fn set_install_rpath(path: &Path, targ: &Target, dirs: &InstallDirs) -> io::Result<bool> {
+    match origin_token(targ) {
+        // Only ELF binaries use `$ORIGIN`
+        Some(origin @ "$ORIGIN") => {
+            elf::set_rpath(path, &dirs.install_rpath(origin))?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
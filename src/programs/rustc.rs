@@ -1,7 +1,8 @@
 use std::{
     borrow::Cow,
     ffi::{OsStr, OsString},
-    io,
+    fs,
+    io::{self, Write as _},
     path::{Path, PathBuf},
     process::{Command, Stdio},
     slice::Iter,
@@ -9,11 +10,20 @@ use std::{
 
 use serde_derive::{Deserialize, Serialize};
 
-use crate::{config::Config, set::OrderedSet};
+use crate::{
+    config::{Config, ConfigFoundProgram, ConfigProgramInfo},
+    hash::{FileHash, HashAlgorithm},
+    log::{log, trace, LogLevel},
+    set::OrderedSet,
+};
 
-use target_tuples::{Architecture, Target};
+use target_tuples::{Architecture, Target, OS};
 
-use super::{CompileTask, Compiler};
+use super::{
+    artifacts::{artifact_key, ArtifactCache},
+    cache::{ProbeCache, ProbeKey},
+    CompileTask, CompileTaskStep, CompileTaskType, Compiler,
+};
 
 mod store;
 
@@ -31,6 +41,10 @@ pub struct RustcTarget {
     pub cdylib_suffix: String,
     pub bin_prefix: String,
     pub bin_suffix: String,
+    /// The target-spec JSON file given to rustc as `--target`, for a target rustc doesn't have built in
+    pub target_spec: Option<PathBuf>,
+    /// The hash of `target_spec` when the target was probed
+    pub target_spec_hash: Option<FileHash>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -48,12 +62,96 @@ pub struct RustcVersion {
     pub features_available: OrderedSet<RustcFeature>,
 }
 
-#[allow(dead_code)]
+impl RustcVersion {
+    /// The `--target` given to this rustc, or [`None`] for a rustc that only compiles for its target (such as `x86_64-pc-linux-gnu-rustc`)
+    pub fn target_arg(&self) -> Option<&OsStr> {
+        match &self.target.target_spec {
+            Some(spec) => Some(spec.as_os_str()),
+            None if self
+                .abs_path
+                .to_string_lossy()
+                .contains(&self.target.rustc_target) =>
+            {
+                None
+            }
+            None => Some(OsStr::new(&self.target.rustc_target)),
+        }
+    }
+}
+
+/// A rustc found by the configuration, which compile tasks run through the wrapper of its program
+pub struct Rustc<'a> {
+    program: &'a ConfigFoundProgram,
+    version: &'a RustcVersion,
+    /// Identifies the compiler binary, the toolchain it runs, and its target, independently of the build directory
+    identity: FileHash,
+    /// Where outputs are restored from instead of running the compiler, if the artifact cache is enabled
+    artifact_cache: Option<&'a ArtifactCache>,
+}
+
+impl<'a> Rustc<'a> {
+    /// The rustc run by `program`, or an error if `program` isn't a Rust compiler
+    pub fn new(
+        program: &'a ConfigFoundProgram,
+        artifact_cache: Option<&'a ArtifactCache>,
+    ) -> io::Result<Self> {
+        trace!(Rustc::new);
+        let Some(ConfigProgramInfo::Rustc(version)) = &program.info else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a Rust compiler", program.location.display()),
+            ));
+        };
+
+        // The probe cache usually knows the binary already, so it is only hashed if it changed
+        let path = &version.abs_path;
+        let key = ProbeKey::for_rustc(
+            path,
+            version.target.target_spec.as_deref(),
+            ProbeCache::read().rustc_key(path, &version.target.real_target),
+        )?;
+
+        let mut id = Vec::new();
+        id.extend_from_slice(key.hash.as_ref());
+        id.extend_from_slice(key.version.as_bytes());
+        id.push(0);
+        id.extend_from_slice(version.target.rustc_target.as_bytes());
+        id.push(0);
+        if let Some(spec_hash) = &key.target_spec_hash {
+            id.extend_from_slice(spec_hash.as_ref());
+        }
+        let identity = HashAlgorithm::DEFAULT.hash_reader(&*id, FileHash::ZERO)?;
+
+        Ok(Self {
+            program,
+            version,
+            identity,
+            artifact_cache,
+        })
+    }
+
+    pub fn program(&self) -> &'a ConfigFoundProgram {
+        self.program
+    }
+
+    pub fn identity(&self) -> FileHash {
+        self.identity
+    }
+}
+
 pub struct RustCompileTask<'a> {
-    compiler: &'a RustcVersion,
+    compiler: &'a Rustc<'a>,
     output: &'a Path,
+    /// The dep-info written next to `output`, listing the source files of the crate
+    dep_info: PathBuf,
     crate_name: Cow<'a, str>,
     compile_type: RustCompileType,
+    /// The crate root
+    src: Option<PathBuf>,
+    /// The crates given with `--extern`, and the library of each
+    externs: Vec<(String, PathBuf)>,
+    /// The directories of the libraries in `externs`, where rustc finds the crates they depend on in turn
+    lib_dirs: Vec<PathBuf>,
     preprocess_flags: Vec<OsString>,
     compile_flags: Vec<OsString>,
     link_flags: Vec<OsString>,
@@ -61,85 +159,441 @@ pub struct RustCompileTask<'a> {
 
 impl<'a> CompileTask for RustCompileTask<'a> {
     fn compiler(&self) -> &dyn Compiler {
-        todo!()
+        self.compiler
+    }
+
+    fn add_source(&mut self, src: &Path) {
+        self.src = Some(src.to_path_buf());
+    }
+
+    fn add_compile_lib(&mut self, lib: &Path) {
+        // A library built elsewhere is named as rustc names its own, such as `libfoo.rlib`
+        let stem = lib.file_stem().and_then(OsStr::to_str).unwrap_or("");
+        let name = stem
+            .strip_prefix(&*self.compiler.version.target.rlib_prefix)
+            .unwrap_or(stem);
+        self.add_extern(name.to_string(), lib);
     }
 
-    fn add_compile_lib(&mut self, _lib: &Path) {
-        todo!()
+    fn add_link_lib(&mut self, lib: &dyn CompileTask) {
+        for output in lib.link_outputs() {
+            self.add_extern(lib.name().to_string(), output);
+        }
     }
 
-    fn add_link_lib(&mut self, _lib: &dyn CompileTask) {
-        todo!()
+    fn add_preprocess_lib(&mut self, lib: &dyn CompileTask) {
+        // Procedural macros are loaded by rustc itself, but are still given with `--extern`
+        for output in lib.link_outputs() {
+            self.add_extern(lib.name().to_string(), output);
+        }
     }
 
-    fn add_preprocess_lib(&mut self, _lib: &dyn CompileTask) {
-        todo!()
+    fn add_compile_flag(&mut self, flag: &OsStr) {
+        self.compile_flags.push(flag.to_os_string());
     }
 
-    fn add_compile_flag(&mut self, _flag: &OsStr) {
-        todo!()
+    fn add_link_flag(&mut self, flag: &OsStr) {
+        self.link_flags.push(flag.to_os_string());
     }
 
-    fn add_link_flag(&mut self, _flag: &OsStr) {
-        todo!()
+    fn add_preprocess_flag(&mut self, flag: &OsStr) {
+        self.preprocess_flags.push(flag.to_os_string());
     }
 
-    fn add_preprocess_flag(&mut self, _flag: &OsStr) {
-        todo!()
+    fn set_rpath(&mut self, rpath: &str) {
+        self.link_flags.push(OsString::from("-C"));
+        self.link_flags
+            .push(OsString::from(format!("link-arg=-Wl,-rpath,{}", rpath)));
     }
 
     fn name(&self) -> &str {
-        todo!()
+        &self.crate_name
     }
 
     fn link_outputs(&self) -> Vec<&Path> {
-        todo!()
+        match self.compile_type {
+            RustCompileType::Bin | RustCompileType::Test => Vec::new(),
+            _ => vec![self.output],
+        }
     }
 
     fn run_outputs(&self) -> Vec<&Path> {
-        todo!()
+        match self.compile_type {
+            RustCompileType::Rlib | RustCompileType::Staticlib => Vec::new(),
+            _ => vec![self.output],
+        }
     }
 
     fn gather_deps(&self) -> std::io::Result<Vec<super::DepInfo>> {
-        todo!()
+        let st = fs::read_to_string(&self.dep_info)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", self.dep_info.display(), e)))?;
+
+        parse_dep_info(&st)
+            .into_iter()
+            .filter(|path| path != self.output && *path != self.dep_info)
+            .map(|dep_path| {
+                // A source that was removed changes the inputs as much as one that was modified
+                let hash = match HashAlgorithm::DEFAULT.hash_file(&dep_path, FileHash::ZERO) {
+                    Ok(hash) => hash,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => FileHash::ZERO,
+                    Err(e) => return Err(e),
+                };
+                Ok(super::DepInfo { dep_path, hash })
+            })
+            .collect()
     }
 
     fn run_steps(
         &mut self,
-        _from: super::CompileTaskStep,
-        _to: super::CompileTaskStep,
+        from: super::CompileTaskStep,
+        to: super::CompileTaskStep,
     ) -> std::io::Result<Vec<super::DepInfo>> {
-        todo!()
+        // Only the final outputs are cached, so a partial run always invokes the compiler
+        let cache = match (self.compiler.artifact_cache, from, to) {
+            (Some(cache), CompileTaskStep::Compile, CompileTaskStep::Link) => cache,
+            _ => return self.invoke_rustc(from, to),
+        };
+
+        let mut outputs: Vec<PathBuf> = Vec::new();
+        for output in self.link_outputs().into_iter().chain(self.run_outputs()) {
+            if !outputs.iter().any(|o| o == output) {
+                outputs.push(output.to_path_buf());
+            }
+        }
+        let outputs = outputs.iter().map(PathBuf::as_path).collect::<Vec<_>>();
+
+        let mut flags = vec![OsStr::new("--crate-name"), OsStr::new(&*self.crate_name)];
+        flags.extend(self.compile_type.rustc_flags().iter().map(OsStr::new));
+        flags.extend(
+            self.preprocess_flags
+                .iter()
+                .chain(&self.compile_flags)
+                .chain(&self.link_flags)
+                .map(OsString::as_os_str),
+        );
+
+        let libs = self
+            .externs
+            .iter()
+            .map(|(name, lib)| {
+                Ok((
+                    &**name,
+                    HashAlgorithm::DEFAULT.hash_file(lib, FileHash::ZERO)?,
+                ))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+
+        let wrapper = self
+            .compiler
+            .program
+            .rebuild_key(HashAlgorithm::DEFAULT, FileHash::ZERO)?;
+
+        // The dep-info of the previous build lists the inputs, unless the command or one of those inputs changed since
+        let inputs = match self.previous_inputs()? {
+            Some(inputs) => inputs,
+            None => self.list_inputs()?,
+        };
+        let key = artifact_key(
+            self.compiler.identity,
+            wrapper,
+            &flags,
+            &libs,
+            &inputs,
+            &outputs,
+        )?;
+
+        if cache.restore(key, &outputs)? {
+            log!(
+                LogLevel::Verbose,
+                "Restored {} from the artifact cache",
+                self.crate_name
+            );
+            return Ok(inputs);
+        }
+
+        let deps = self.invoke_rustc(from, to)?;
+        cache.store(key, &outputs)?;
+        Ok(deps)
     }
 }
 
-#[allow(dead_code)]
+impl<'a> RustCompileTask<'a> {
+    fn add_extern(&mut self, name: String, lib: &Path) {
+        if let Some(dir) = lib.parent() {
+            if !self.lib_dirs.iter().any(|d| d == dir) {
+                self.lib_dirs.push(dir.to_path_buf());
+            }
+        }
+        self.externs.push((name, lib.to_path_buf()));
+    }
+
+    /// The rustc command for the task, without the outputs to emit or the crate root
+    fn command(&self) -> Command {
+        let mut cmd = self.compiler.program.command();
+        cmd.arg("--crate-name")
+            .arg(&*self.crate_name)
+            .args(self.compile_type.rustc_flags());
+        if let Some(target) = self.compiler.version.target_arg() {
+            cmd.arg("--target").arg(target);
+        }
+        cmd.args(&self.preprocess_flags)
+            .args(&self.compile_flags)
+            .args(&self.link_flags);
+
+        for (name, lib) in &self.externs {
+            let mut arg = OsString::from(format!("{}=", name));
+            arg.push(lib);
+            cmd.arg("--extern").arg(arg);
+        }
+        for dir in &self.lib_dirs {
+            let mut arg = OsString::from("dependency=");
+            arg.push(dir);
+            cmd.arg("-L").arg(arg);
+        }
+
+        cmd
+    }
+
+    fn src(&self) -> io::Result<&Path> {
+        self.src.as_deref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("No crate root was given for {}", self.crate_name),
+            )
+        })
+    }
+
+    /// Runs `cmd`, a command built by [`RustCompileTask::command`] with its outputs and crate root
+    fn run(&self, mut cmd: Command) -> io::Result<()> {
+        log!(LogLevel::Exec, "{:?}", cmd);
+
+        let location = &self.compiler.program.location;
+        let status = cmd
+            .status()
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", location.display(), e)))?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "Could not compile {}: {} {}",
+                self.crate_name,
+                location.display(),
+                status
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Lists the inputs of the crate by running rustc to write only the dep-info, which expands macros but doesn't compile
+    fn list_inputs(&self) -> io::Result<Vec<super::DepInfo>> {
+        let mut emit = OsString::from("--emit=dep-info=");
+        emit.push(&self.dep_info);
+
+        if let Some(dir) = self.dep_info.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut cmd = self.command();
+        cmd.arg(emit).arg(self.src()?);
+        self.run(cmd)?;
+
+        self.stamp_dep_info()
+    }
+
+    /// Identifies the command that wrote the dep-info, and the contents of the inputs it lists
+    fn dep_info_stamp(&self, inputs: &[super::DepInfo]) -> io::Result<FileHash> {
+        let cmd = self.command();
+        let mut id = cmd.get_program().as_encoded_bytes().to_vec();
+        for arg in cmd.get_args().chain([self.src()?.as_os_str()]) {
+            id.push(0);
+            id.extend_from_slice(arg.as_encoded_bytes());
+        }
+        for input in inputs {
+            id.push(0);
+            id.extend_from_slice(input.dep_path.as_os_str().as_encoded_bytes());
+            id.extend_from_slice(input.hash.as_ref());
+        }
+
+        HashAlgorithm::DEFAULT.hash_reader(&*id, FileHash::ZERO)
+    }
+
+    /// Records the stamp of the dep-info that rustc just wrote in a comment at its end, and returns the inputs it lists
+    fn stamp_dep_info(&self) -> io::Result<Vec<super::DepInfo>> {
+        let inputs = self.gather_deps()?;
+        let stamp = self.dep_info_stamp(&inputs)?;
+
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(&self.dep_info)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", self.dep_info.display(), e)))?;
+        writeln!(file, "{}{}", DEP_INFO_STAMP, stamp)?;
+
+        Ok(inputs)
+    }
+
+    /// The inputs listed in the dep-info of the previous build, if it was written by the same command and none of its inputs changed since
+    fn previous_inputs(&self) -> io::Result<Option<Vec<super::DepInfo>>> {
+        let st = match fs::read_to_string(&self.dep_info) {
+            Ok(st) => st,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(io::Error::new(
+                    e.kind(),
+                    format!("{}: {}", self.dep_info.display(), e),
+                ))
+            }
+        };
+
+        let Some(recorded) = st
+            .lines()
+            .find_map(|line| line.strip_prefix(DEP_INFO_STAMP))
+        else {
+            return Ok(None);
+        };
+
+        let inputs = self.gather_deps()?;
+        if self.dep_info_stamp(&inputs)?.to_string() == recorded {
+            Ok(Some(inputs))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn invoke_rustc(
+        &mut self,
+        _from: CompileTaskStep,
+        to: CompileTaskStep,
+    ) -> io::Result<Vec<super::DepInfo>> {
+        // rustc can't link without compiling, so every run starts from the source. Stopping at the compile step only writes the metadata
+        let mut emit = match to {
+            CompileTaskStep::Compile => {
+                let mut emit = OsString::from("--emit=metadata=");
+                emit.push(self.output.with_extension("rmeta"));
+                emit
+            }
+            CompileTaskStep::Link => {
+                let mut emit = OsString::from("--emit=link=");
+                emit.push(self.output);
+                emit
+            }
+        };
+        emit.push(",dep-info=");
+        emit.push(&self.dep_info);
+
+        if let Some(dir) = self.output.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut cmd = self.command();
+        cmd.arg(emit).arg(self.src()?);
+        self.run(cmd)?;
+
+        self.stamp_dep_info()
+    }
+}
+
+/// The comment that records [`RustCompileTask::dep_info_stamp`] in a dep-info file
+const DEP_INFO_STAMP: &str = "# autobuild-stamp: ";
+
+/// The inputs listed in the Makefile-style dep-info written by rustc, which gives each input an empty rule (`src/lib.rs:`)
+pub fn parse_dep_info(st: &str) -> Vec<PathBuf> {
+    st.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.strip_suffix(':'))
+        .filter(|path| !path.is_empty())
+        .map(|path| PathBuf::from(path.replace("\\ ", " ")))
+        .collect()
+}
+
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 pub enum RustCompileType {
     Bin,
-    Cdylib,
+    Rlib,
     Dylib,
+    Cdylib,
+    Staticlib,
+    ProcMacro,
+    /// A libtest harness, which runs the `#[test]` functions of the crate
+    Test,
 }
 
-impl Compiler for RustcVersion {
+impl RustCompileType {
+    /// The output type named by `raw`, as in [`CompileTaskType::raw_build_type`]
+    pub fn from_raw(raw: &str) -> Option<Self> {
+        match raw {
+            "bin" => Some(Self::Bin),
+            "rlib" => Some(Self::Rlib),
+            "dylib" => Some(Self::Dylib),
+            "cdylib" => Some(Self::Cdylib),
+            "staticlib" => Some(Self::Staticlib),
+            "proc-macro" => Some(Self::ProcMacro),
+            "test" => Some(Self::Test),
+            _ => None,
+        }
+    }
+
+    /// The flags that select this output type
+    pub fn rustc_flags(&self) -> &'static [&'static str] {
+        match self {
+            Self::Bin => &["--crate-type", "bin"],
+            Self::Rlib => &["--crate-type", "rlib"],
+            Self::Dylib => &["--crate-type", "dylib"],
+            Self::Cdylib => &["--crate-type", "cdylib"],
+            Self::Staticlib => &["--crate-type", "staticlib"],
+            Self::ProcMacro => &["--crate-type", "proc-macro"],
+            Self::Test => &["--test"],
+        }
+    }
+}
+
+impl<'b> Compiler for Rustc<'b> {
     fn abs_path(&self) -> &Path {
-        &self.abs_path
+        &self.version.abs_path
     }
 
     fn default_flags(&self) -> &[&OsStr] {
-        todo!()
+        &[]
     }
 
     fn target(&self) -> &Target {
-        &self.target.real_target
+        &self.version.target.real_target
     }
 
     fn create_compile_task<'a>(
         &'a self,
-        _output: &'a Path,
-        _name: &'a str,
-        _build_type: super::CompileTaskType,
+        output: &'a Path,
+        name: &'a str,
+        build_type: CompileTaskType,
     ) -> Box<dyn super::CompileTask + 'a> {
-        todo!()
+        let compile_type = RustCompileType::from_raw(&build_type.raw_build_type).unwrap_or(
+            if build_type.executable {
+                RustCompileType::Bin
+            } else {
+                RustCompileType::Rlib
+            },
+        );
+
+        let mut dep_info = output.as_os_str().to_os_string();
+        dep_info.push(".d");
+
+        let crate_name = if name.contains('-') {
+            Cow::Owned(name.replace('-', "_"))
+        } else {
+            Cow::Borrowed(name)
+        };
+
+        Box::new(RustCompileTask {
+            compiler: self,
+            output,
+            dep_info: PathBuf::from(dep_info),
+            crate_name,
+            compile_type,
+            src: None,
+            externs: Vec::new(),
+            lib_dirs: Vec::new(),
+            preprocess_flags: Vec::new(),
+            compile_flags: Vec::new(),
+            link_flags: Vec::new(),
+        })
     }
 }
 
@@ -291,6 +745,8 @@ fn test_target_rustc<P: AsRef<OsStr>>(
             cdylib_suffix,
             bin_prefix,
             bin_suffix,
+            target_spec: None,
+            target_spec_hash: None,
         }))
     } else {
         Ok(None)
@@ -324,21 +780,21 @@ pub fn rustc_detect_target<P: AsRef<OsStr>>(
     let try_target = {
         use core::fmt::Write as _;
         let mut st = String::new();
-        let _ = writeln!(st, "{}", parsed.arch());
+        let _ = write!(st, "{}", parsed.arch());
         if let Some(os) = parsed.operating_system() {
-            let _ = writeln!(st, "-{}", os);
+            let _ = write!(st, "-{}", os);
         }
         let mut has_sep = false;
         if let Some(env) = parsed.environment() {
             has_sep = true;
-            let _ = writeln!(st, "-{}", env);
+            let _ = write!(st, "-{}", env);
         }
 
         if let Some(obj) = parsed.object_format() {
             if !has_sep {
                 st.push('-');
             }
-            let _ = writeln!(st, "{}", obj);
+            let _ = write!(st, "{}", obj);
         }
         st
     };
@@ -353,21 +809,21 @@ pub fn rustc_detect_target<P: AsRef<OsStr>>(
     let try_target = {
         use core::fmt::Write as _;
         let mut st = String::new();
-        let _ = writeln!(st, "{}-unknown", parsed.arch());
+        let _ = write!(st, "{}-unknown", parsed.arch());
         if let Some(os) = parsed.operating_system() {
-            let _ = writeln!(st, "-{}", os);
+            let _ = write!(st, "-{}", os);
         }
         let mut has_sep = false;
         if let Some(env) = parsed.environment() {
             has_sep = true;
-            let _ = writeln!(st, "-{}", env);
+            let _ = write!(st, "-{}", env);
         }
 
         if let Some(obj) = parsed.object_format() {
             if !has_sep {
                 st.push('-');
             }
-            let _ = writeln!(st, "{}", obj);
+            let _ = write!(st, "{}", obj);
         }
         st
     };
@@ -384,21 +840,21 @@ pub fn rustc_detect_target<P: AsRef<OsStr>>(
         let try_target = {
             use core::fmt::Write as _;
             let mut st = String::new();
-            let _ = writeln!(st, "{}-pc", parsed.arch());
+            let _ = write!(st, "{}-pc", parsed.arch());
             if let Some(os) = parsed.operating_system() {
-                let _ = writeln!(st, "-{}", os);
+                let _ = write!(st, "-{}", os);
             }
             let mut has_sep = false;
             if let Some(env) = parsed.environment() {
                 has_sep = true;
-                let _ = writeln!(st, "-{}", env);
+                let _ = write!(st, "-{}", env);
             }
 
             if let Some(obj) = parsed.object_format() {
                 if !has_sep {
                     st.push('-');
                 }
-                let _ = writeln!(st, "{}", obj);
+                let _ = write!(st, "{}", obj);
             }
             st
         };
@@ -408,6 +864,23 @@ pub fn rustc_detect_target<P: AsRef<OsStr>>(
         }
     }
 
+    // rustc spells some operating systems differently from the canonical target
+    let rustc_os = match parsed.operating_system() {
+        Some(OS::Win32) => Some("windows"),
+        Some(OS::MacOSX) => Some("darwin"),
+        _ => None,
+    };
+
+    if let Some(os) = rustc_os {
+        let try_target = match parsed.environment() {
+            Some(env) => format!("{}-{}-{}-{}", parsed.arch(), parsed.vendor(), os, env),
+            None => format!("{}-{}-{}", parsed.arch(), parsed.vendor(), os),
+        };
+        if let Some(targ) = test_target_rustc(rustc, &mut target, try_target)? {
+            return Ok(targ);
+        }
+    }
+
     // TODO: Add more heuristics
 
     Err(io::Error::new(
@@ -439,13 +912,51 @@ fn rustc_test_edition<P: AsRef<OsStr>>(rustc: &P, edition: RustEdition) -> io::R
     Ok(output.status.success())
 }
 
+/// Probes rustc using the target-spec JSON file `spec` for `target`, instead of a target built in to rustc
+pub fn rustc_spec_target<P: AsRef<OsStr>>(
+    rustc: &P,
+    mut target: String,
+    spec: &Path,
+    spec_hash: FileHash,
+) -> io::Result<RustcTarget> {
+    let try_target = spec.to_str().map(str::to_string).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{}: path is not valid UTF-8", spec.display()),
+        )
+    })?;
+
+    match test_target_rustc(rustc, &mut target, try_target)? {
+        Some(mut targ) => {
+            targ.target_spec = Some(spec.to_path_buf());
+            targ.target_spec_hash = Some(spec_hash);
+            Ok(targ)
+        }
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} does not accept the target spec {} for {} (custom targets may require a nightly rustc)",
+                rustc.as_ref().to_string_lossy(),
+                spec.display(),
+                target
+            ),
+        )),
+    }
+}
+
 pub fn rustc_info<P: AsRef<OsStr>>(rustc: &P, target: String) -> io::Result<RustcVersion> {
+    let target = rustc_detect_target(rustc, target)?;
+    rustc_info_for_target(rustc, target)
+}
+
+fn rustc_info_for_target<P: AsRef<OsStr>>(
+    rustc: &P,
+    target: RustcTarget,
+) -> io::Result<RustcVersion> {
     let cli = test_rustc_cli(rustc)?;
 
     match cli {
         RustcCli::Rustc => {
-            let target = rustc_detect_target(rustc, target)?;
-
             let supported_editions = RustEdition::all()
                 .filter_map(|edition| match rustc_test_edition(rustc, edition) {
                     Ok(true) => Some(Ok(edition)),
@@ -469,9 +980,56 @@ pub fn rustc_info<P: AsRef<OsStr>>(rustc: &P, target: String) -> io::Result<Rust
 }
 
 pub fn info<P: AsRef<OsStr>>(
-    _: &mut Config,
+    cfg: &mut Config,
+    rustc: &P,
+    target: String,
+) -> io::Result<RustcVersion> {
+    let spec = cfg.rustc_target_spec(&target);
+
+    if !cfg.probe_cache_enabled() {
+        return probe(cfg, rustc, target, spec.as_deref());
+    }
+
+    let path = Path::new(rustc.as_ref());
+    // The same target that the probe records as `real_target`, which `Rustc::new` looks the entry up by
+    let entry_target = Target::parse(&target);
+    let cache = ProbeCache::read();
+    let key = ProbeKey::for_rustc(path, spec.as_deref(), cache.rustc_key(path, &entry_target))?;
+
+    if let Some(info) = cache.get_rustc(path, &entry_target, &key) {
+        let mut info = info.clone();
+        // The hash recorded in the config is keyed by that config, so it can't be shared
+        info.target.target_spec_hash = spec
+            .as_deref()
+            .map(|spec| cfg.hash_file(spec))
+            .transpose()?;
+        return Ok(info);
+    }
+
+    let info = probe(cfg, rustc, target.clone(), spec.as_deref())?;
+
+    let mut cached = info.clone();
+    cached.target.target_spec_hash = None;
+    if let Err(e) = ProbeCache::update(|cache| cache.insert_rustc(path, &entry_target, key, cached))
+    {
+        log!(LogLevel::Warning, "Could not write the probe cache: {}", e);
+    }
+
+    Ok(info)
+}
+
+fn probe<P: AsRef<OsStr>>(
+    cfg: &Config,
     rustc: &P,
     target: String,
+    spec: Option<&Path>,
 ) -> io::Result<RustcVersion> {
-    rustc_info(rustc, target)
+    match spec {
+        Some(spec) => {
+            let spec_hash = cfg.hash_file(spec)?;
+            let target = rustc_spec_target(rustc, target, spec, spec_hash)?;
+            rustc_info_for_target(rustc, target)
+        }
+        None => rustc_info(rustc, target),
+    }
 }
@@ -11,7 +11,7 @@ impl serde::Serialize for RustcTarget {
     where
         S: serde::Serializer,
     {
-        let mut s = serializer.serialize_struct("RustcTarget", 12)?;
+        let mut s = serializer.serialize_struct("RustcTarget", 14)?;
         s.serialize_field("real-target", self.real_target.get_name())?;
         s.serialize_field("rustc-target", &self.rustc_target)?;
         s.serialize_field("rlib-prefix", &self.rlib_prefix)?;
@@ -24,6 +24,14 @@ impl serde::Serialize for RustcTarget {
         s.serialize_field("cdylib-suffix", &self.cdylib_suffix)?;
         s.serialize_field("bin-prefix", &self.bin_prefix)?;
         s.serialize_field("bin-suffix", &self.bin_suffix)?;
+        match &self.target_spec {
+            Some(spec) => s.serialize_field("target-spec", spec)?,
+            None => s.skip_field("target-spec")?,
+        }
+        match &self.target_spec_hash {
+            Some(hash) => s.serialize_field("target-spec-hash", hash)?,
+            None => s.skip_field("target-spec-hash")?,
+        }
 
         s.end()
     }
@@ -48,6 +56,8 @@ impl<'de> serde::Deserialize<'de> for RustcTarget {
             CdylibSuffix,
             BinPrefix,
             BinSuffix,
+            TargetSpec,
+            TargetSpecHash,
             __Other,
         }
 
@@ -66,6 +76,8 @@ impl<'de> serde::Deserialize<'de> for RustcTarget {
                     Self::CdylibSuffix => "cdylib-suffix",
                     Self::BinPrefix => "bin-prefix",
                     Self::BinSuffix => "bin-suffix",
+                    Self::TargetSpec => "target-spec",
+                    Self::TargetSpecHash => "target-spec-hash",
                     Self::__Other => "",
                 }
             }
@@ -77,7 +89,7 @@ impl<'de> serde::Deserialize<'de> for RustcTarget {
             type Value = RustcTargetField;
 
             fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                formatter.write_str("`real-target`, `rustc-target`, `rlib-prefix`, `rlib-suffix`, `dylib-prefix`, `dylib-suffix`, `staticlib-prefix`, `staticlib-suffix`, `cdylib-prefix`, `cdylib-suffix`, `bin-prefix`, or `bin-suffix`")
+                formatter.write_str("`real-target`, `rustc-target`, `rlib-prefix`, `rlib-suffix`, `dylib-prefix`, `dylib-suffix`, `staticlib-prefix`, `staticlib-suffix`, `cdylib-prefix`, `cdylib-suffix`, `bin-prefix`, `bin-suffix`, `target-spec`, or `target-spec-hash`")
             }
 
             fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
@@ -101,6 +113,8 @@ impl<'de> serde::Deserialize<'de> for RustcTarget {
                     "cdylib-suffix" | "cdylib_suffix" => Ok(RustcTargetField::CdylibSuffix),
                     "bin-prefix" | "bin_preifx" => Ok(RustcTargetField::BinPrefix),
                     "bin-suffix" | "bin_suffix" => Ok(RustcTargetField::BinSuffix),
+                    "target-spec" | "target_spec" => Ok(RustcTargetField::TargetSpec),
+                    "target-spec-hash" | "target_spec_hash" => Ok(RustcTargetField::TargetSpecHash),
                     _ => Ok(RustcTargetField::__Other),
                 }
             }
@@ -131,6 +145,8 @@ impl<'de> serde::Deserialize<'de> for RustcTarget {
             {
                 const __VAL: Option<String> = None;
                 let mut target = None;
+                let mut target_spec = None;
+                let mut target_spec_hash = None;
                 let mut fields = [__VAL; 11];
 
                 while let Some(key) = map.next_key_seed(RustcTargetFieldVisitor)? {
@@ -143,6 +159,16 @@ impl<'de> serde::Deserialize<'de> for RustcTarget {
                                 return Err(A::Error::duplicate_field("real-target"));
                             }
                         }
+                        RustcTargetField::TargetSpec => {
+                            if target_spec.replace(map.next_value()?).is_some() {
+                                return Err(A::Error::duplicate_field("target-spec"));
+                            }
+                        }
+                        RustcTargetField::TargetSpecHash => {
+                            if target_spec_hash.replace(map.next_value()?).is_some() {
+                                return Err(A::Error::duplicate_field("target-spec-hash"));
+                            }
+                        }
                         RustcTargetField::__Other => continue,
                         x => {
                             let val = x as usize - 1;
@@ -181,6 +207,8 @@ impl<'de> serde::Deserialize<'de> for RustcTarget {
                         .ok_or_else(|| A::Error::missing_field("cdylib-suffix"))?,
                     bin_prefix: bin_prefix.ok_or_else(|| A::Error::missing_field("bin-prefix"))?,
                     bin_suffix: bin_suffix.ok_or_else(|| A::Error::missing_field("bin-suffix"))?,
+                    target_spec,
+                    target_spec_hash,
                 })
             }
 
@@ -225,6 +253,9 @@ impl<'de> serde::Deserialize<'de> for RustcTarget {
                     .next_element()?
                     .ok_or_else(|| A::Error::missing_field("bin-suffix"))?;
 
+                let target_spec = seq.next_element()?.flatten();
+                let target_spec_hash = seq.next_element()?.flatten();
+
                 while let Some(IgnoredAny) = seq.next_element()? {}
 
                 Ok(RustcTarget {
@@ -240,6 +271,8 @@ impl<'de> serde::Deserialize<'de> for RustcTarget {
                     cdylib_suffix,
                     bin_prefix,
                     bin_suffix,
+                    target_spec,
+                    target_spec_hash,
                 })
             }
         }
@@ -259,6 +292,8 @@ impl<'de> serde::Deserialize<'de> for RustcTarget {
                 "cdylib-suffix",
                 "bin-prefix",
                 "bin-suffix",
+                "target-spec",
+                "target-spec-hash",
             ],
             RustcTargetVisitor,
         )
@@ -0,0 +1,185 @@
+use std::io::{self, Write};
+
+use crate::log::trace;
+
+const BLOCK_SIZE: usize = 512;
+
+/// The largest size (and mtime) that fits in the octal fields of a ustar header
+const MAX_OCTAL: u64 = 0o77777777777;
+
+/// The kind of an entry of a tar archive
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EntryType {
+    File,
+    Dir,
+    Symlink,
+}
+
+impl EntryType {
+    fn typeflag(self) -> u8 {
+        match self {
+            EntryType::File => b'0',
+            EntryType::Symlink => b'2',
+            EntryType::Dir => b'5',
+        }
+    }
+}
+
+/// Writes a ustar archive, using pax extended headers for paths and sizes that don't fit in a ustar header.
+///
+/// Every entry is owned by uid and gid 0 with no user or group name, and has the same mtime,
+/// so the archive only depends on the paths, modes and contents of its entries (and the order they are appended in)
+pub struct TarWriter<W> {
+    inner: W,
+    mtime: u64,
+}
+
+fn write_octal(field: &mut [u8], val: u64) {
+    let digits = field.len() - 1;
+    let st = format!("{:0width$o}", val, width = digits);
+    field[..digits].copy_from_slice(st.as_bytes());
+    field[digits] = 0;
+}
+
+/// Splits `path` into the prefix and name fields of a ustar header, if it fits
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+
+    path.char_indices()
+        .filter(|(_, c)| *c == '/')
+        .map(|(pos, _)| (&path[..pos], &path[pos + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && !name.is_empty() && name.len() <= 100)
+}
+
+/// Truncates `st` to at most `len` bytes, at a character boundary
+fn truncate(st: &str, len: usize) -> &str {
+    let mut end = st.len().min(len);
+    while !st.is_char_boundary(end) {
+        end -= 1;
+    }
+    &st[..end]
+}
+
+/// Splits `path` into the prefix and name fields of a ustar header, truncating each if the path doesn't fit.
+///
+/// Readers that understand the pax `path` record ignore these fields, but others still get a name that is close to the real path
+fn split_path_lossy(path: &str) -> (&str, &str) {
+    if let Some(split) = split_path(path) {
+        return split;
+    }
+
+    match path.trim_end_matches('/').rsplit_once('/') {
+        Some((prefix, name)) => (truncate(prefix, 155), truncate(name, 100)),
+        None => ("", truncate(path, 100)),
+    }
+}
+
+/// Formats a pax extended header record, `<len> <key>=<val>\n`, where `len` counts the whole record
+fn pax_record(key: &str, val: &str) -> Vec<u8> {
+    let rest = key.len() + val.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    format!("{} {}={}\n", len, key, val).into_bytes()
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(inner: W, mtime: u64) -> Self {
+        Self {
+            inner,
+            mtime: mtime.min(MAX_OCTAL),
+        }
+    }
+
+    fn write_header(
+        &mut self,
+        path: &str,
+        ty: u8,
+        mode: u32,
+        size: u64,
+        link: &str,
+    ) -> io::Result<()> {
+        let mut header = [0u8; BLOCK_SIZE];
+        let (prefix, name) = split_path_lossy(path);
+
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        write_octal(&mut header[100..108], mode as u64);
+        write_octal(&mut header[108..116], 0);
+        write_octal(&mut header[116..124], 0);
+        write_octal(&mut header[124..136], size.min(MAX_OCTAL));
+        write_octal(&mut header[136..148], self.mtime);
+        header[156] = ty;
+        header[157..157 + link.len().min(100)]
+            .copy_from_slice(&link.as_bytes()[..link.len().min(100)]);
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        write_octal(&mut header[329..337], 0);
+        write_octal(&mut header[337..345], 0);
+        header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+        // The checksum is computed with the checksum field filled with spaces
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|b| *b as u32).sum();
+        write_octal(&mut header[148..155], sum as u64);
+        header[155] = b' ';
+
+        self.inner.write_all(&header)
+    }
+
+    fn write_padded(&mut self, data: &[u8]) -> io::Result<()> {
+        self.inner.write_all(data)?;
+        let pad = (BLOCK_SIZE - data.len() % BLOCK_SIZE) % BLOCK_SIZE;
+        self.inner.write_all(&[0u8; BLOCK_SIZE][..pad])
+    }
+
+    /// Appends an entry. `data` is the contents of a file, and is ignored for other entries
+    pub fn append(
+        &mut self,
+        path: &str,
+        ty: EntryType,
+        mode: u32,
+        data: &[u8],
+        link: &str,
+    ) -> io::Result<()> {
+        trace!(TarWriter::append);
+        let size = if ty == EntryType::File {
+            data.len() as u64
+        } else {
+            0
+        };
+
+        let mut records = Vec::new();
+        if split_path(path).is_none() {
+            records.extend(pax_record("path", path));
+        }
+        if link.len() > 100 {
+            records.extend(pax_record("linkpath", link));
+        }
+        if size > MAX_OCTAL {
+            records.extend(pax_record("size", &size.to_string()));
+        }
+
+        if !records.is_empty() {
+            self.write_header("././@PaxHeader", b'x', 0o644, records.len() as u64, "")?;
+            self.write_padded(&records)?;
+        }
+
+        self.write_header(path, ty.typeflag(), mode, size, link)?;
+
+        if ty == EntryType::File {
+            self.write_padded(data)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the end-of-archive marker, and returns the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0u8; BLOCK_SIZE * 2])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
@@ -1,6 +1,10 @@
-use target_tuples::Target;
+use std::io;
+use std::path::Path;
 
-use crate::log::{dbg, trace};
+use target_tuples::{Architecture, Environment, Target, OS};
+
+use crate::elf;
+use crate::log::{dbg, log, log_debug, trace, LogLevel};
 
 pub mod uname;
 
@@ -29,18 +33,18 @@ macro_rules! gen_guess_table{
     {
         $($match_kernel:tt, $match_arch:tt $(,$match_sys:tt)? => $targ_arch:ident-$targ_vendor:ident-$targ_os:ident$(-$targ_env:ident)?;)*
     } => {
-        fn guess_from_uname(uname: &uname::Uname) -> ::target_tuples::Target{
+        fn guess_from_uname(uname: &uname::Uname) -> io::Result<::target_tuples::Target>{
             match (&*uname.kernel, &*uname.arch, uname.sys.as_deref()){
                 $((maybe_stringify!($match_kernel),maybe_stringify!($match_arch), opt_stringify!($($match_sys)?)) => {
-                    let arch = target_tuples::Architecture::parse(::core::stringify!($targ_arch));
+                    let arch = ::core::stringify!($targ_arch).parse::<target_tuples::Architecture>().map_err(|_| io::Error::new(io::ErrorKind::Unsupported, format!("The host architecture {} is not supported", ::core::stringify!($targ_arch))))?;
                     let vendor = target_tuples::Vendor::parse(::core::stringify!($targ_vendor));
                     let os = target_tuples::OS::parse(::core::stringify!($targ_os));
                     let env = opt_stringify!($($targ_env)?).map(target_tuples::Environment::parse);
 
-                    ::target_tuples::Target::from_components(arch,vendor, Some(os), env, None)
+                    Ok(::target_tuples::Target::from_components(arch,vendor, Some(os), env, None))
                 })*
-                (kernel,arch,sys) => panic!("Could not identify host target. If the target is supported as a host target by lccc, this is a bug. If you know the name of the target, you can file an issue report. The name string is kernel={}, arch={}, os={}",
-                            kernel,arch,sys.unwrap_or("<not provided>"))
+                (kernel,arch,sys) => Err(io::Error::new(io::ErrorKind::NotFound, format!("Could not identify host target. If the target is supported as a host target by lccc, this is a bug. If you know the name of the target, you can file an issue report, and set AUTOBUILD_HOST in the meantime. The name string is kernel={}, arch={}, os={}",
+                            kernel,arch,sys.unwrap_or("<not provided>"))))
             }
         }
     }
@@ -52,9 +56,270 @@ with_builtin_macros::with_builtin! {
     }
 }
 
-pub fn ident_target() -> Target {
+/// The target autobuild itself was compiled for
+const BUILD_TARGET: &str = env!("AUTOBUILD_BUILD_TARGET");
+
+/// Guesses the host from the ELF header of the running executable, which is at least able to run on the host.
+///
+/// The operating system comes from `uname` if it's available, or otherwise the target autobuild was compiled for.
+fn guess_from_elf(uname: Option<&uname::Uname>) -> io::Result<Target> {
+    trace!(guess_from_elf);
+    let exe = std::env::current_exe()?;
+    let (header, _) = elf::read_file(&exe)?;
+
+    let arch = header.arch_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "Unknown ELF machine {} in {}",
+                header.machine,
+                exe.display()
+            ),
+        )
+    })?;
+
+    let sys = match uname {
+        Some(uname) => {
+            let os = match &*uname.kernel {
+                "Linux" => "linux",
+                "FreeBSD" => "freebsd",
+                "NetBSD" => "netbsd",
+                "OpenBSD" => "openbsd",
+                "DragonFly" => "dragonfly",
+                kernel => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        format!("Unknown ELF-based kernel {}", kernel),
+                    ))
+                }
+            };
+            let env = match uname.sys.as_deref() {
+                Some("MUSL/Linux") => Some("musl"),
+                Some("uClibc/Linux") => Some("uclibc"),
+                Some("Android") => Some("android"),
+                _ if os == "linux" => Some("gnu"),
+                _ => None,
+            };
+            match env {
+                Some(env) => format!("{}-{}", os, env),
+                None => os.to_string(),
+            }
+        }
+        None => BUILD_TARGET.split_once('-').unwrap().1.to_string(),
+    };
+
+    let vendor = match arch {
+        "i686" | "x86_64" => "pc",
+        "s390x" => "ibm",
+        _ => "unknown",
+    };
+
+    // The build target's vendor may be anything, and we've already picked ours
+    let sys = match sys.split_once('-') {
+        Some((vendor, rest))
+            if target_tuples::Vendor::parse(vendor) != target_tuples::Vendor::Unknown
+                || vendor == "unknown" =>
+        {
+            rest.to_string()
+        }
+        _ => sys,
+    };
+
+    let name = format!("{}-{}-{}", arch, vendor, sys);
+    name.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("The host target {} is not supported", name),
+        )
+    })
+}
+
+/// Reads the ELF file that best describes the userspace of the host: the running executable, or `/bin/sh` if the executable is statically linked
+fn host_elf() -> io::Result<(elf::ElfHeader, Option<String>)> {
+    let exe = elf::read_file(&std::env::current_exe()?);
+
+    match &exe {
+        Ok((_, Some(_))) => exe,
+        _ => match elf::read_file(Path::new("/bin/sh")) {
+            Ok(sh) if sh.1.is_some() => Ok(sh),
+            Ok(sh) => exe.or(Ok(sh)),
+            Err(e) => exe.map_err(|_| e),
+        },
+    }
+}
+
+/// Refines a Linux target using the ELF files of the host.
+///
+/// `uname` reports the same system name for glibc and musl, and nothing about the float ABI, so the guess table can't tell them apart.
+/// The program interpreter identifies the C library, and `e_flags` identifies the ABI on ARM, MIPS, and RISC-V.
+fn refine_linux(targ: Target) -> Target {
+    trace!(refine_linux);
+    if targ.operating_system() != Some(OS::Linux)
+        || targ.environment() == Some(Environment::Android)
+    {
+        return targ;
+    }
+
+    let (header, interp) = match host_elf() {
+        Ok(elf) => elf,
+        Err(e) => {
+            log_debug!(LogLevel::Debug, "refine_linux: {}", e);
+            return targ;
+        }
+    };
+
+    log_debug!(
+        LogLevel::Debug,
+        "refine_linux: interp={:?}, e_flags={:#x}",
+        interp,
+        header.flags
+    );
+
+    let musl = match &interp {
+        Some(interp) => interp.contains("ld-musl"),
+        None => matches!(
+            targ.environment(),
+            Some(Environment::Musl | Environment::MuslEABI | Environment::MuslEABIHF)
+        ),
+    };
+
+    let mut arch = targ.arch();
+    // The suffix of the n32 and n64 ABIs after `musl`, which [`Environment`] can't represent
+    let mut musl_abi = None;
+
+    let env = match header.machine {
+        elf::EM_ARM => {
+            // A 32-bit userspace on an aarch64 kernel is reported as aarch64 by uname
+            arch = if header.data == elf::ELFDATA2LSB {
+                Architecture::Arm
+            } else {
+                Architecture::ArmBe
+            };
+            let hard = header.flags & elf::EF_ARM_ABI_FLOAT_HARD != 0
+                || (header.flags & elf::EF_ARM_ABI_FLOAT_SOFT == 0
+                    && interp.as_deref().is_some_and(|i| i.contains("armhf")));
+            match (musl, hard) {
+                (false, false) => Environment::GNUEABI,
+                (false, true) => Environment::GNUEABIHF,
+                (true, false) => Environment::MuslEABI,
+                (true, true) => Environment::MuslEABIHF,
+            }
+        }
+        elf::EM_MIPS => {
+            let le = header.data == elf::ELFDATA2LSB;
+            // The kernel reports the same machine for both byte orders, and a 64-bit kernel may run a 32-bit userspace
+            let (name, env, abi) = if header.class == elf::ELFCLASS64 {
+                ("mips64", Environment::GNUABI64, Some("abi64"))
+            } else if header.flags & elf::EF_MIPS_ABI2 != 0 {
+                ("mips64", Environment::GNUABIN32, Some("abin32"))
+            } else {
+                ("mips", Environment::GNU, None)
+            };
+            arch = if le {
+                Architecture::parse(&format!("{}el", name))
+            } else {
+                Architecture::parse(name)
+            };
+            if musl {
+                musl_abi = abi;
+                Environment::Musl
+            } else {
+                env
+            }
+        }
+        elf::EM_RISCV => {
+            if header.flags & elf::EF_RISCV_FLOAT_ABI == elf::EF_RISCV_FLOAT_ABI_SOFT {
+                // There's no target name for a soft-float Linux userspace, so keep the usual one
+                log!(
+                    LogLevel::Warning,
+                    "The host uses the RISC-V soft-float ABI, which is not distinguished by the host target"
+                );
+            }
+            if musl {
+                Environment::Musl
+            } else {
+                Environment::GNU
+            }
+        }
+        _ if musl => Environment::Musl,
+        _ => Environment::GNU,
+    };
+
+    let refined = Target::from_components(
+        arch,
+        targ.vendor(),
+        targ.operating_system(),
+        Some(env),
+        targ.object_format(),
+    );
+
+    match musl_abi {
+        // The parsed target keeps the full name, such as `mips64-unknown-linux-muslabi64`
+        Some(abi) => format!("{}{}", refined.get_name(), abi)
+            .parse()
+            .unwrap_or(refined),
+        None => refined,
+    }
+}
+
+/// Determines the host target.
+///
+/// `AUTOBUILD_HOST` overrides the guess if it is set. Otherwise the host is identified from `uname`,
+/// then the ELF header of the running executable, and finally the target autobuild was compiled for.
+pub fn ident_target() -> io::Result<Target> {
     trace!(ident_target);
-    let uname = uname::uname().expect("Could not determine the system names for the host target. If the target is supported as a host target by lccc, this is a bug.");
+    if let Some(host) = std::env::var_os("AUTOBUILD_HOST") {
+        let host = host.to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "AUTOBUILD_HOST contains invalid UTF-8",
+            )
+        })?;
+        return host.parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "AUTOBUILD_HOST is set to `{}`, which is not a recognized target",
+                    host
+                ),
+            )
+        });
+    }
+
+    let uname = uname::uname();
+
+    let err = match &uname {
+        Ok(uname) => match guess_from_uname(uname) {
+            Ok(targ) => return Ok(dbg!(refine_linux(targ))),
+            Err(e) => e,
+        },
+        Err(e) => io::Error::new(
+            e.kind(),
+            format!(
+                "Could not determine the system names for the host target: {}",
+                e
+            ),
+        ),
+    };
+
+    log_debug!(LogLevel::Debug, "ident_target: {}", err);
+
+    match guess_from_elf(uname.as_ref().ok()) {
+        Ok(targ) => return Ok(dbg!(refine_linux(targ))),
+        Err(e) => log_debug!(LogLevel::Debug, "ident_target: {}", e),
+    }
 
-    dbg!(guess_from_uname(&uname))
+    // If we get here, the host is almost certainly the target we were built for, but tell the user how we got here anyways
+    match BUILD_TARGET.parse() {
+        Ok(targ) => {
+            log!(LogLevel::Warning, "{}", err);
+            log!(
+                LogLevel::Warning,
+                "Assuming the host is {}, which autobuild was compiled for",
+                BUILD_TARGET
+            );
+            Ok(dbg!(targ))
+        }
+        Err(_) => Err(err),
+    }
 }
@@ -1,4 +1,6 @@
 use std::io;
+#[cfg(unix)]
+use std::path::Path;
 
 use crate::log::{dbg, log_debug, trace, LogLevel};
 
@@ -14,6 +16,40 @@ pub struct Uname {
 
 const HOST_OS_NAME: Option<&str> = core::option_env!("HOST_OS_NAME");
 
+/// Identifies the system name (as printed by `uname -o`) of a Linux host from its files, or [`None`] if `kernel` isn't Linux or the userspace can't be identified.
+///
+/// Android keeps its dynamic linker in `/system`, and the C library of other systems is named by the program interpreter of the host's executables
+#[cfg(unix)]
+fn linux_sys(kernel: &str) -> Option<String> {
+    if kernel != "Linux" {
+        return None;
+    }
+
+    if ["/system/bin/linker", "/system/bin/linker64"]
+        .iter()
+        .any(|linker| Path::new(linker).exists())
+    {
+        return Some("Android".to_string());
+    }
+
+    let interp = match super::host_elf() {
+        Ok((_, interp)) => interp?,
+        Err(e) => {
+            log_debug!(LogLevel::Debug, "linux_sys: {}", e);
+            return None;
+        }
+    };
+
+    let sys = if interp.contains("ld-musl") {
+        "MUSL/Linux"
+    } else if interp.contains("ld-uClibc") {
+        "uClibc/Linux"
+    } else {
+        "GNU/Linux"
+    };
+    Some(sys.to_string())
+}
+
 #[allow(unused_parens)] // cfg_match is a macro that exists
 pub fn uname() -> io::Result<Uname> {
     trace!(uname);
@@ -45,7 +81,10 @@ pub fn uname() -> io::Result<Uname> {
             let arch = arch.to_str().map_err(|_| dbg!(io::Error::new(io::ErrorKind::InvalidData, format!("expected UTF-8 only in \"{:?}\"", arch))))?.to_string();
 
 
-            dbg!(Ok(Uname { kernel, arch, sys: HOST_OS_NAME.map(ToString::to_string), kver, krelease, hostname}))
+            // The system name of the target autobuild was compiled for is only a guess at the host's, so it comes last
+            let sys = linux_sys(&kernel).or_else(|| HOST_OS_NAME.map(ToString::to_string));
+
+            dbg!(Ok(Uname { kernel, arch, sys, kver, krelease, hostname}))
         }),
         windows => ({
             use windows_sys::Win32::System::{SystemInformation, WindowsProgramming};
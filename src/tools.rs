@@ -14,6 +14,20 @@ macro_rules! def_tools{
             }
         }
 
+        /// The names of every subcommand, not including aliases
+        pub const TOOL_NAMES: &[&str] = &[$(::core::stringify!($tool)),*];
+
+        pub fn tool_options(x: &str) -> Option<&'static ToolOptions>{
+            match x{
+                $(::core::stringify!($tool) $($(| ::core::stringify!($else))+)? => Some(&$tool::OPTIONS),)*
+                _ => None
+            }
+        }
+
+        /// The source of each tool, for checking the options it parses against its [`ToolOptions`]
+        #[cfg(test)]
+        const TOOL_SOURCES: &[(&str, &str)] = &[$((::core::stringify!($tool), include_str!(::core::concat!("tools/", ::core::stringify!($tool), ".rs")))),*];
+
         pub fn print_subcommands(){
             println!("Available Subcommands:");
             $(println!("\t{}", ::core::stringify!($tool));)*
@@ -44,12 +58,62 @@ macro_rules! def_tools{
 
 def_tools! {
     tool config alias configure;
+    tool build;
     tool rustc;
     tool clean;
     tool which;
     tool guess;
+    tool hash;
     tool uname;
     tool install;
+    tool dist;
+    tool test;
+    tool doc;
+    tool completions;
+}
+
+/// What a shell should complete for the argument of an option
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OptionArg {
+    /// The option does not take an argument
+    None,
+    /// Free-form text
+    Text,
+    Dir,
+    /// The name of a config variable or an option declared by the project
+    ConfigVar,
+    /// An extra installation directory, in `key=path` form
+    InstallDir,
+    /// The name of a target in the build database, in `path:name` form
+    Target,
+}
+
+/// The options accepted by a tool, used to generate shell completions
+pub struct ToolOptions {
+    pub flags: &'static [(&'static str, OptionArg)],
+    /// Whether the tool accepts the installation directory flags from [`InstallDirs::ARGS`][crate::install::InstallDirs::ARGS]
+    pub install_dirs: bool,
+    /// Whether the tool accepts `--enable-<flag>` and `--disable-<flag>` for the optional targets of the project
+    pub optional_targets: bool,
+    /// What the positional arguments of the tool are completed with
+    pub positional: OptionArg,
+}
+
+impl ToolOptions {
+    pub fn flag_arg(&self, flag: &str) -> Option<OptionArg> {
+        if self.install_dirs
+            && crate::install::InstallDirs::ARGS
+                .iter()
+                .any(|(name, _)| *name == flag)
+        {
+            return Some(OptionArg::Dir);
+        }
+
+        self.flags
+            .iter()
+            .find(|(name, _)| *name == flag)
+            .map(|(_, arg)| *arg)
+    }
 }
 
 pub fn print_version() {
@@ -91,3 +155,35 @@ pub fn require_arg<I: Iterator<Item = String>>(
 //     )
 //     .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::{tool_options, TOOL_SOURCES};
+
+    /// The flags matched by the match arms of `src`, such as `"--flag" =>`
+    fn parsed_flags(src: &str) -> Vec<&str> {
+        src.lines()
+            .filter_map(|line| line.trim_start().split_once("=>"))
+            .map(|(pat, _)| pat)
+            .filter(|pat| pat.starts_with("\"--"))
+            .flat_map(|pat| pat.split('|'))
+            .filter_map(|alt| alt.trim().strip_prefix('"')?.strip_suffix('"'))
+            .filter(|flag| flag.starts_with("--") && *flag != "--")
+            .collect()
+    }
+
+    #[test]
+    fn parsed_flags_are_listed() {
+        for (tool, src) in TOOL_SOURCES {
+            let opts = tool_options(tool).unwrap();
+            for flag in parsed_flags(src) {
+                assert!(
+                    opts.flag_arg(flag).is_some(),
+                    "{} parses {}, but it is missing from its OPTIONS",
+                    tool,
+                    flag
+                );
+            }
+        }
+    }
+}
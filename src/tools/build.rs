@@ -0,0 +1,523 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env::Args,
+    ffi::{OsStr, OsString},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::config::script::default::BuildScriptProviderDefault;
+use crate::config::script::{BuildScriptProvider, BuildScriptTaskTiming, VarKind};
+use crate::config::{
+    BuildInfo, BuildTargetInfo, BuildTargetStep, Config, ConfigData, ConfigVarValue, TargetName,
+};
+use crate::hash::{FileHash, HashAlgorithm};
+use crate::log::{current_level, set_logging_level, trace, LogLevel};
+use crate::programs::artifacts::ArtifactCache;
+use crate::programs::rpath::set_local_rpath;
+use crate::programs::rustc::Rustc;
+use crate::programs::{CompileTask, CompileTaskStep, CompileTaskType, Compiler, DepInfo};
+
+use super::{require_arg, OptionArg, ToolOptions};
+
+pub const OPTIONS: ToolOptions = ToolOptions {
+    flags: &[
+        ("--cfg-dir", OptionArg::Dir),
+        ("--no-artifact-cache", OptionArg::None),
+        ("--verbose", OptionArg::None),
+        ("--version", OptionArg::None),
+        ("--help", OptionArg::None),
+    ],
+    install_dirs: false,
+    optional_targets: false,
+    positional: OptionArg::Target,
+};
+
+fn print_help() {
+    println!(" [OPTIONS] [--] [target...]");
+    println!("Builds the targets of a build directory configured by `autobuild config`");
+    println!("If targets (as `path:name`, or `name` for a target of the root manifest) are given, only those and the targets they depend on are built");
+    println!("Options:");
+    println!("\t--cfg-dir DIR: The build directory (default: the current directory)");
+    println!("\t--no-artifact-cache: Always run the compiler, without restoring or storing outputs in the shared artifact cache");
+    println!("\t--verbose: Print each compiler command");
+    println!("\t--version: Print version information and exit");
+    println!("\t--help: Print this message and exit");
+}
+
+/// A target with a build step, and what it is compiled with
+struct BuildJob<'a> {
+    name: &'a TargetName,
+    build: &'a BuildInfo,
+    /// The key of the compiler in the programs of the configuration
+    compiler: &'a str,
+    /// The primary artifact, in the build directory
+    output: PathBuf,
+    crate_name: String,
+}
+
+/// What a build changes in the configuration, which is kept even if a later target fails
+#[derive(Default)]
+struct BuildUpdates {
+    /// The rebuild key of each target that was built
+    keys: Vec<(TargetName, FileHash)>,
+    /// The variables cached by the scripts that ran, with the directory of each script
+    cached: Vec<(PathBuf, String, ConfigVarValue)>,
+    /// The rpath that each binary with `set-local-rpath` was linked with, by the path of its artifact
+    rpaths: Vec<(PathBuf, Option<String>)>,
+}
+
+/// The key in [`ConfigData::programs`] of the compiler of `build`, the build step of `info`
+fn compiler_key<'a>(info: &'a BuildTargetInfo, build: &'a BuildInfo) -> &'a str {
+    info.instance
+        .as_ref()
+        .and_then(|instance| instance.programs.get(&build.compiler_name))
+        .unwrap_or(&build.compiler_name)
+}
+
+/// Orders `names` (or every target, if none are named) and their dependencies so that every target comes after the targets it depends on
+fn build_order<'a>(config: &'a ConfigData, names: &[TargetName]) -> io::Result<Vec<&'a TargetName>> {
+    fn visit<'a>(
+        config: &'a ConfigData,
+        name: &'a TargetName,
+        visited: &mut HashSet<&'a TargetName>,
+        order: &mut Vec<&'a TargetName>,
+    ) {
+        if !visited.insert(name) {
+            return;
+        }
+        let Some(info) = config.build_database.get(name) else {
+            return;
+        };
+        for dep in &info.deps {
+            if let Some((dep, _)) = config.build_database.get_key_value(dep) {
+                visit(config, dep, visited, order);
+            }
+        }
+        order.push(name);
+    }
+
+    let mut visited = HashSet::new();
+    let mut order = Vec::new();
+    if names.is_empty() {
+        for (name, _) in &config.build_database {
+            visit(config, name, &mut visited, &mut order);
+        }
+    }
+    for name in names {
+        let Some((name, _)) = config.build_database.get_key_value(name) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No such target {}", name),
+            ));
+        };
+        visit(config, name, &mut visited, &mut order);
+    }
+
+    Ok(order)
+}
+
+/// Finds the targets with a build step that `name` depends on, looking through the targets (such as groups) that build nothing themselves
+fn build_deps<'a>(
+    config: &'a ConfigData,
+    name: &'a TargetName,
+    seen: &mut HashSet<&'a TargetName>,
+    deps: &mut Vec<&'a TargetName>,
+) {
+    let Some(info) = config.build_database.get(name) else {
+        return;
+    };
+    for dep in &info.deps {
+        if !seen.insert(dep) {
+            continue;
+        }
+        match config.build_database.get(dep).map(|info| &info.step) {
+            Some(BuildTargetStep::Build(_)) => deps.push(dep),
+            Some(_) => build_deps(config, dep, seen, deps),
+            None => {}
+        }
+    }
+}
+
+/// Gives `task` the library built by `dep`, according to the kind of library it is
+fn add_dep(task: &mut dyn CompileTask, dep: &dyn CompileTask, build_type: &str) {
+    match build_type {
+        "proc-macro" => task.add_preprocess_lib(dep),
+        "rlib" | "dylib" => task.add_link_lib(dep),
+        // Libraries with a C interface are linked as native libraries
+        "staticlib" | "cdylib" => {
+            let kind = if build_type == "staticlib" {
+                "static"
+            } else {
+                "dylib"
+            };
+            for lib in dep.link_outputs() {
+                if let Some(dir) = lib.parent() {
+                    let mut arg = OsString::from("native=");
+                    arg.push(dir);
+                    task.add_link_flag(OsStr::new("-L"));
+                    task.add_link_flag(&arg);
+                }
+                task.add_link_flag(OsStr::new("-l"));
+                task.add_link_flag(OsStr::new(&format!("{}={}", kind, dep.name())));
+            }
+        }
+        // Binaries are only ordered before their dependants
+        _ => {}
+    }
+}
+
+/// Computes the key that decides whether a target is rebuilt.
+///
+/// The key covers the compiler and its wrapper (through [`ConfigFoundProgram::rebuild_key`](crate::config::ConfigFoundProgram::rebuild_key)),
+///  the build step, the rpath it is linked with, the libraries of its dependencies, and the inputs listed in the dep-info of the last build
+fn rebuild_key(
+    config: &ConfigData,
+    rustc: &Rustc,
+    job: &BuildJob,
+    rpath: Option<&str>,
+    libs: &[&Path],
+    inputs: &[DepInfo],
+) -> io::Result<FileHash> {
+    let mut id = Vec::new();
+    id.extend_from_slice(
+        rustc
+            .program()
+            .rebuild_key(config.hash_algorithm, config.global_key)?
+            .as_ref(),
+    );
+    id.extend_from_slice(rustc.identity().as_ref());
+    id.extend_from_slice(job.build.build_type.as_bytes());
+    id.push(0);
+    id.extend_from_slice(job.build.src.as_os_str().as_encoded_bytes());
+    id.push(0);
+    id.extend_from_slice(rpath.unwrap_or_default().as_bytes());
+    id.push(0);
+
+    for lib in libs {
+        id.extend_from_slice(lib.as_os_str().as_encoded_bytes());
+        id.push(0);
+        id.extend_from_slice(HashAlgorithm::DEFAULT.hash_file(lib, FileHash::ZERO)?.as_ref());
+    }
+    id.push(0);
+
+    for input in inputs {
+        id.extend_from_slice(input.dep_path.as_os_str().as_encoded_bytes());
+        id.push(0);
+        id.extend_from_slice(input.hash.as_ref());
+    }
+
+    config.hash_algorithm.hash_reader(&*id, config.global_key)
+}
+
+/// Runs the build script (or `run-target` binary) built by `task`, the build step of `job`, with the configuration in its environment.
+///
+/// The variables it sets are given to the scripts of the same directory that run after it, and those it caches are also
+///  recorded in `cached`
+fn run_script(
+    config: &ConfigData,
+    build_dir: &Path,
+    job: &BuildJob,
+    task: &dyn CompileTask,
+    timing: BuildScriptTaskTiming,
+    transient: &mut HashMap<PathBuf, HashMap<String, ConfigVarValue>>,
+    cached: &mut Vec<(PathBuf, String, ConfigVarValue)>,
+) -> io::Result<()> {
+    trace!(run_script);
+    let provider = BuildScriptProviderDefault {};
+    let mut script = provider.new_task(task, timing);
+    let dir = &job.name.base_path;
+
+    let tempdir = job.output.with_extension("tmp");
+    fs::create_dir_all(&tempdir)?;
+    script.set_autobuild_config_dir(build_dir);
+    script.set_tempdir(&tempdir);
+
+    let targets = &config.targets;
+    script.set_target("build", &targets.build);
+    script.set_target("host", &targets.host);
+    script.set_target("target", &targets.target);
+    for (name, targ) in &targets.others {
+        script.set_target(name, targ);
+    }
+    for (key, prg) in &config.programs {
+        script.set_program(key, prg);
+    }
+    for (key, path) in config.dirs.install_dirs.as_canonical_env() {
+        script.set_install_dir(key, &path);
+    }
+    for (key, path) in &config.dirs.rest {
+        script.set_install_dir(key, path);
+    }
+
+    // The first value given for a variable is kept, so variables set during this build take precedence over cached ones
+    let vars = transient
+        .get(dir)
+        .into_iter()
+        .flatten()
+        .map(|(name, val)| (name, val, VarKind::DirTransient))
+        .chain(
+            config
+                .dir_vars(dir)
+                .map(|(name, val)| (name, val, VarKind::DirCache)),
+        )
+        .chain(
+            config
+                .config_vars
+                .iter()
+                .map(|(name, val)| (name, val, VarKind::Config)),
+        );
+    for (name, val, kind) in vars {
+        match val {
+            ConfigVarValue::Set => script.set_var(name, "1", kind),
+            ConfigVarValue::Value(val) => script.set_var(name, val, kind),
+            ConfigVarValue::Unset => {}
+        }
+    }
+
+    // Programs built for a target other than the build system run through the runner of that target, if it needs one
+    let targ = config.output_target(&config.build_database[job.name]);
+    if targ.get_name() != targets.build.get_name() {
+        script.set_cross_compiling();
+        if let Some(runner) = config.runner(targ) {
+            script.set_runner(runner);
+        }
+    }
+
+    let outputs = script.run()?;
+    let vars = transient.entry(dir.clone()).or_default();
+    vars.extend(outputs.set_transient);
+    for (name, val) in outputs.set_cache {
+        vars.insert(name.clone(), val.clone());
+        cached.push((dir.clone(), name, val));
+    }
+
+    Ok(())
+}
+
+/// Builds the targets of `order` that are out of date, recording what each build changes in the configuration in `updates`
+fn build_all(
+    config: &ConfigData,
+    build_dir: &Path,
+    order: &[&TargetName],
+    cache: Option<&ArtifactCache>,
+    updates: &mut BuildUpdates,
+) -> io::Result<()> {
+    trace!(build_all);
+    let mut jobs = Vec::new();
+    for name in order {
+        let info = &config.build_database[*name];
+        let BuildTargetStep::Build(build) = &info.step else {
+            continue;
+        };
+        let base = name.name.split('@').next().unwrap_or(&name.name);
+        jobs.push(BuildJob {
+            name,
+            build,
+            compiler: compiler_key(info, build),
+            output: build_dir.join(build.primary_artifacts.first().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Target {} has no outputs", name),
+                )
+            })?),
+            crate_name: base.to_string(),
+        });
+    }
+
+    let mut compilers = HashMap::new();
+    for job in &jobs {
+        if compilers.contains_key(job.compiler) {
+            continue;
+        }
+        let program = config.programs.get(job.compiler).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} was not found by the configuration", job.compiler),
+            )
+        })?;
+        compilers.insert(job.compiler, Rustc::new(program, cache)?);
+    }
+
+    let mut tasks = jobs
+        .iter()
+        .map(|job| {
+            compilers[job.compiler].create_compile_task(
+                &job.output,
+                &job.crate_name,
+                CompileTaskType {
+                    raw_build_type: job.build.build_type.clone(),
+                    leaf: false,
+                    executable: matches!(&*job.build.build_type, "bin" | "test"),
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+    let index = jobs
+        .iter()
+        .enumerate()
+        .map(|(n, job)| (job.name, n))
+        .collect::<HashMap<_, _>>();
+
+    let mut transient = HashMap::new();
+
+    for (n, job) in jobs.iter().enumerate() {
+        // Dependencies come first in the build order, so they are already built
+        let (built, rest) = tasks.split_at_mut(n);
+        let task = &mut *rest[0];
+        task.add_source(&config.src_dir.join(&job.name.base_path).join(&job.build.src));
+
+        let mut deps = Vec::new();
+        build_deps(config, job.name, &mut HashSet::new(), &mut deps);
+        let mut libs = Vec::new();
+        let mut dylibs = Vec::new();
+        for dep in deps {
+            let Some(&dep) = index.get(dep) else {
+                continue;
+            };
+            add_dep(task, &*built[dep], &jobs[dep].build.build_type);
+            libs.extend(built[dep].link_outputs());
+            if matches!(&*jobs[dep].build.build_type, "dylib" | "cdylib") {
+                dylibs.push(&*built[dep]);
+            }
+        }
+
+        // The rpath is padded to fit the installed rpath, so it depends on the installation directories of the target
+        let rpath = if job.build.local_rpath {
+            let mut dirs = config.dirs.install_dirs.clone();
+            if let Some(instance) = &config.build_database[job.name].instance {
+                dirs.set_target(
+                    config
+                        .targets
+                        .get(&instance.tuple)
+                        .map_or(&*instance.tuple, |targ| targ.get_name()),
+                );
+            }
+            set_local_rpath(task, &dylibs, &dirs)
+        } else {
+            None
+        };
+
+        let rustc = &compilers[job.compiler];
+        let fresh = job.build.rebuild_key.is_some()
+            && job.build.primary_artifacts.iter().all(|path| build_dir.join(path).is_file())
+            && task
+                .gather_deps()
+                .and_then(|inputs| {
+                    rebuild_key(config, rustc, job, rpath.as_deref(), &libs, &inputs)
+                })
+                .ok()
+                == job.build.rebuild_key;
+        if fresh {
+            continue;
+        }
+
+        println!("Compiling {}", job.name);
+        let inputs = task.run_steps(CompileTaskStep::Compile, CompileTaskStep::Link)?;
+        let key = rebuild_key(config, rustc, job, rpath.as_deref(), &libs, &inputs)?;
+        if job.build.local_rpath {
+            updates
+                .rpaths
+                .push((job.build.primary_artifacts[0].clone(), rpath));
+        }
+
+        // A script runs again each time it is rebuilt, and is rebuilt if it failed
+        if let Some(timing) = job.build.run {
+            println!("Running {}", job.name);
+            run_script(
+                config,
+                build_dir,
+                job,
+                task,
+                timing,
+                &mut transient,
+                &mut updates.cached,
+            )?;
+        }
+        updates.keys.push((job.name.clone(), key));
+    }
+
+    Ok(())
+}
+
+pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
+    let mut config_dir = PathBuf::new();
+    let mut names = Vec::new();
+    let mut use_cache = true;
+
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--cfg-dir" => {
+                config_dir = PathBuf::from(require_arg(Some("--cfg-dir"), &mut args, None)?);
+            }
+            "--no-artifact-cache" => use_cache = false,
+            "--verbose" => {
+                if current_level() < LogLevel::Exec {
+                    set_logging_level(LogLevel::Exec);
+                }
+            }
+            "--version" => {
+                super::print_version();
+                return Ok(());
+            }
+            "--help" => {
+                super::print_help(prg_name, "build", print_help);
+                return Ok(());
+            }
+            "--" => {
+                for arg in &mut args {
+                    names.push(TargetName::from_arg(&arg)?);
+                }
+                break;
+            }
+            x if x.starts_with('-') => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unrecognized option {}", x),
+                ))
+            }
+            _ => names.push(TargetName::from_arg(&arg)?),
+        }
+    }
+
+    let build_dir = fs::canonicalize(if config_dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        &config_dir
+    })?;
+    let mut config = Config::open(config_dir)?;
+    let cache = if use_cache {
+        ArtifactCache::open()?
+    } else {
+        None
+    };
+
+    let data = config.data();
+    let order = build_order(data, &names)?;
+    let mut updates = BuildUpdates::default();
+    let res = build_all(data, &build_dir, &order, cache.as_ref(), &mut updates);
+
+    // The targets that were built stay built, even if a later target failed
+    for (name, key) in updates.keys {
+        if let Some(BuildTargetInfo {
+            step: BuildTargetStep::Build(build),
+            ..
+        }) = config.data_mut().build_database.get_mut(&name)
+        {
+            build.rebuild_key = Some(key);
+        }
+    }
+    for (dir, name, val) in updates.cached {
+        config.data_mut().set_dir_var(&dir, name, val);
+    }
+    for (path, rpath) in updates.rpaths {
+        for art in &mut config.data_mut().artifacts {
+            if art.path == path {
+                art.local_rpath = rpath.clone();
+            }
+        }
+    }
+    config.cleanup()?;
+
+    res
+}
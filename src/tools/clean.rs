@@ -1,22 +1,69 @@
 use std::{
+    collections::HashSet,
     env::Args,
-    fs::{self, File},
+    fs,
     io,
-    path::PathBuf,
+    path::{Path, PathBuf},
 };
 
-use crate::config::ConfigData;
+use crate::config::{lock_config_dir, BuildTargetStep, ConfigData, TargetName};
+use crate::programs::artifacts::ArtifactCache;
+use crate::programs::cache;
 
-use super::require_arg;
+use super::config::load_config_data;
+use super::doc::DOC_DIR;
+use super::{require_arg, OptionArg, ToolOptions};
+
+pub const OPTIONS: ToolOptions = ToolOptions {
+    flags: &[
+        ("--cache-only", OptionArg::None),
+        ("--artifacts-only", OptionArg::None),
+        ("--cache", OptionArg::None),
+        ("--no-cache", OptionArg::None),
+        ("--artifacts", OptionArg::None),
+        ("--no-artifacts", OptionArg::None),
+        ("--probe-cache", OptionArg::None),
+        ("--no-probe-cache", OptionArg::None),
+        ("--artifact-cache", OptionArg::None),
+        ("--no-artifact-cache", OptionArg::None),
+        ("--all", OptionArg::None),
+        ("--none", OptionArg::None),
+        ("--verbose", OptionArg::None),
+        ("--terse", OptionArg::None),
+        ("--fail-slow", OptionArg::None),
+        ("--fail-fast", OptionArg::None),
+        ("--error-missing", OptionArg::None),
+        ("--ignore-missing", OptionArg::None),
+        ("--error-missing=cache", OptionArg::None),
+        ("--ignore-missing=cache", OptionArg::None),
+        ("--error-missing=files", OptionArg::None),
+        ("--ignore-missing=files", OptionArg::None),
+        ("--cfg-dir", OptionArg::Dir),
+        ("--stale", OptionArg::None),
+        ("--dry-run", OptionArg::None),
+        ("--clean", OptionArg::None),
+        ("--version", OptionArg::None),
+        ("--help", OptionArg::None),
+    ],
+    install_dirs: false,
+    optional_targets: false,
+    positional: OptionArg::Target,
+};
 
 fn print_help() {
+    println!(" [OPTIONS] [--] [target...]");
     println!("Cleans build directories after `autobuild config` and `autobuild build`");
+    println!("If targets (as `path:name`, or `name` for a target of the root manifest) are given, only the outputs of those targets and of every target that depends on them are removed, and the cache is kept");
     println!("\t--cache-only: Remove only the cache and ignore artifacts (same as --cache --no-artifacts)");
     println!("\t--artifacts-only: Remove only build artifacts and ignore the cache (same as --cache --no-artifacts)");
     println!("\t--cache: Remove the cache (default)");
     println!("\t--no-cache: Do not remove the cache");
     println!("\t--artifacts: Remove build artifacts (default)");
     println!("\t--no-artifacts: Do not remove build artifacts");
+    println!("\t--probe-cache: Also remove the compiler probe results shared by every build directory");
+    println!("\t--no-probe-cache: Do not remove the shared compiler probe results (default)");
+    println!("\t--artifact-cache: Also remove the compile outputs cached for every build directory (prints its statistics with --verbose)");
+    println!("\t--no-artifact-cache: Do not remove the shared compile outputs (default)");
     println!("\t--all: Remove all files (same as --cache --artifacts)");
     println!("\t--nothing: Remove nothing (same as --no-cache --no-artifacts");
     println!("\t--verbose: Print every file removed");
@@ -29,12 +76,186 @@ fn print_help() {
     println!("\t--ignore-missing=cache: When removing artifacts, silently ignore a missing or invalid cache");
     println!("\t--error-missing=files: When removing a file, error if it cannot be removed because it is missing");
     println!("\t--ignore-missing=files: When removing a file, ignore it if it cannot be removed because it is missing (default)");
+    println!("\t--stale: Also remove files in the build directory that no target of the configuration produces, such as outputs of targets removed from manifests");
     println!("\t--dry-run: Acknowledge files being removed, but do not try to remove them (only useful with --verbose)");
     println!("\t--clean: Remove targetted files (default)");
     println!("\t--version: Print version information and exit");
     println!("\t--help: Print this message and exit");
 }
 
+/// Removes files from the build directory, following the error handling options of the clean tool
+struct Remover {
+    verbose: bool,
+    remove_files: bool,
+    ignore_missing_files: bool,
+    fail_fast: bool,
+    res: io::Result<()>,
+    dirs: Vec<PathBuf>,
+}
+
+impl Remover {
+    fn fail(&mut self, e: io::Error) -> io::Result<()> {
+        if self.fail_fast {
+            return Err(e);
+        }
+        eprintln!("{}", e);
+        self.res = Err(io::Error::new(io::ErrorKind::Other, "failed"));
+        Ok(())
+    }
+
+    /// Removes `rel` in `base`. Directories are removed after every file, by [`Remover::remove_dirs`]
+    fn remove(&mut self, base: &Path, rel: &Path) -> io::Result<()> {
+        let path = base.join(rel);
+        if self.verbose {
+            println!("Deleting {}", rel.display());
+        }
+        if !self.remove_files {
+            return Ok(());
+        }
+
+        match fs::remove_file(&path) {
+            Ok(()) => Ok(()),
+            Err(_) if fs::metadata(&path).is_ok_and(|m| m.is_dir()) => {
+                self.dirs.push(path);
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound && self.ignore_missing_files => Ok(()),
+            Err(e) => self.fail(io::Error::new(
+                e.kind(),
+                format!("Could not delete {}: {}", rel.display(), e),
+            )),
+        }
+    }
+
+    fn remove_dirs(&mut self) {
+        for dir in core::mem::take(&mut self.dirs) {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// Resolves the targets named on the command line, and adds every target that (transitively) depends on one of them
+fn select_targets(config: &ConfigData, names: &[String]) -> io::Result<HashSet<TargetName>> {
+    let mut selected = HashSet::new();
+
+    for name in names {
+        let target = TargetName::from_arg(name)?;
+
+        let known = config.build_database.contains_key(&target)
+            || config.artifacts.iter().any(|art| art.target == target);
+        if !known {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No such target {}", target),
+            ));
+        }
+
+        selected.insert(target);
+    }
+
+    loop {
+        let mut changed = false;
+        for (name, info) in &config.build_database {
+            if !selected.contains(name) && info.deps.iter().any(|dep| selected.contains(dep)) {
+                selected.insert(name.clone());
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    Ok(selected)
+}
+
+/// The files produced by the `selected` targets (or every target), relative to the build directory
+fn target_outputs<'a>(
+    config: &'a ConfigData,
+    selected: Option<&HashSet<TargetName>>,
+) -> Vec<&'a Path> {
+    let is_selected = |name: &TargetName| selected.map_or(true, |selected| selected.contains(name));
+    let mut seen = HashSet::new();
+    let mut outputs = Vec::new();
+
+    for art in &config.artifacts {
+        if is_selected(&art.target) {
+            outputs.push(&*art.path);
+            outputs.extend(art.aliases.iter().map(|alias| &**alias));
+        }
+    }
+
+    for (name, info) in &config.build_database {
+        if let BuildTargetStep::Build(build) = &info.step {
+            if is_selected(name) {
+                outputs.extend(build.primary_artifacts.iter().map(|path| &**path));
+                outputs.extend(build.secondary_artifacts.iter().map(|path| &**path));
+            }
+        }
+    }
+
+    outputs.retain(|path| seen.insert(*path));
+    outputs
+}
+
+/// Removes the files under `rel` in the build directory `base` that aren't in `outputs`.
+///
+/// Files of autobuild itself (whose names start with `.` in the build directory), those of rustdoc in the documentation directory (such as its lock file),
+///  and the source directory are kept
+fn remove_stale(
+    remover: &mut Remover,
+    base: &Path,
+    rel: &Path,
+    outputs: &HashSet<&Path>,
+    src_dir: &Path,
+) -> io::Result<()> {
+    let dir = base.join(rel);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return remover.fail(io::Error::new(
+                e.kind(),
+                format!("Could not read {}: {}", dir.display(), e),
+            ))
+        }
+    };
+
+    for ent in entries {
+        let ent = ent?;
+        let name = ent.file_name();
+        if (rel.as_os_str().is_empty() || rel == Path::new(DOC_DIR))
+            && name.to_string_lossy().starts_with('.')
+        {
+            continue;
+        }
+
+        let child = rel.join(&name);
+        if outputs.contains(&*child) {
+            continue;
+        }
+
+        if ent.file_type()?.is_dir() {
+            if fs::canonicalize(ent.path())? == src_dir {
+                continue;
+            }
+            remove_stale(remover, base, &child, outputs, src_dir)?;
+
+            // A directory that held only stale files is stale too
+            if remover.remove_files
+                && fs::read_dir(ent.path())?.next().is_none()
+                && fs::remove_dir(ent.path()).is_ok()
+                && remover.verbose
+            {
+                println!("Deleting {}", child.display());
+            }
+        } else {
+            remover.remove(base, &child)?;
+        }
+    }
+
+    Ok(())
+}
+
 pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
     let mut clean_cache = true;
     let mut clean_artifacts = true;
@@ -44,6 +265,10 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
     let mut fail_fast = true;
     let mut config_dir = PathBuf::new();
     let mut remove_files = true;
+    let mut clean_probe_cache = false;
+    let mut clean_artifact_cache = false;
+    let mut clean_stale = false;
+    let mut targets = Vec::new();
 
     while let Some(arg) = args.next() {
         match &*arg {
@@ -67,6 +292,18 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
             "--no-artifacts" => {
                 clean_artifacts = false;
             }
+            "--probe-cache" => {
+                clean_probe_cache = true;
+            }
+            "--no-probe-cache" => {
+                clean_probe_cache = false;
+            }
+            "--artifact-cache" => {
+                clean_artifact_cache = true;
+            }
+            "--no-artifact-cache" => {
+                clean_artifact_cache = false;
+            }
             "--verbose" => verbose = true,
             "--terse" => verbose = false,
             "--fail-slow" => fail_fast = false,
@@ -103,6 +340,9 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
                 let dir = require_arg(Some("--cfg-dir"), &mut args, None)?;
                 config_dir = PathBuf::from(dir);
             }
+            "--stale" => {
+                clean_stale = true;
+            }
             "--dry-run" => {
                 remove_files = false;
             }
@@ -117,83 +357,99 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
                 super::print_help(prg_name, "clean", print_help);
                 return Ok(());
             }
-            _ => super::help_subcommands()?,
+            "--" => {
+                targets.extend(&mut args);
+                break;
+            }
+            x if x.starts_with('-') => super::help_subcommands()?,
+            _ => targets.push(arg),
         }
     }
 
-    let mut res = Ok(());
-    if clean_artifacts {
-        let mut config_path = config_dir.clone();
-        config_path.push(".config.toml");
-
-        let open_config_res: Result<ConfigData, io::Error> = (move || {
-            use io::Read as _;
-            let mut file = File::open(&config_path)?;
-            let mut st = String::new();
-            file.read_to_string(&mut st)?;
-            toml::from_str(&st).map_err(|e| {
-                io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    format!("Could not open {}: {}", config_path.display(), e),
-                )
-            })
-        })();
-
-        match open_config_res {
-            Ok(config) => {
-                let mut dirs = Vec::new();
-                for art in config.artifacts {
-                    let mut path: PathBuf = config_dir.clone();
-                    path.push(&art.path);
-                    if verbose {
-                        println!("Deleting {}", art.path.display());
-                    }
-                    if remove_files {
-                        match fs::remove_file(&path) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                match fs::metadata(&path) {
-                                    Ok(m) if m.is_dir() => {
-                                        dirs.push(path);
-                                        continue;
-                                    }
-                                    _ => {}
-                                }
-                                if !ignore_missing_files {
-                                    if fail_fast {
-                                        return Err(io::Error::new(
-                                            e.kind(),
-                                            format!(
-                                                "Could not delete {}: {}",
-                                                art.path.display(),
-                                                e
-                                            ),
-                                        ));
-                                    }
-                                    res = Err(io::Error::new(io::ErrorKind::Other, "failed"));
-                                    eprintln!("Could not delete {}: {}", art.path.display(), e)
-                                }
-                            }
-                        }
-                    }
-                }
+    // Removing the cache would forget the outputs of every other target
+    if !targets.is_empty() {
+        clean_cache = false;
+    }
 
-                for dir in dirs {
-                    if remove_files {
-                        let _ = fs::remove_dir_all(dir);
-                    }
-                }
-            }
+    // Hold the config lock so that we don't remove files out from under a concurrent `autobuild config`
+    let _lock = if remove_files && (clean_artifacts || clean_cache || clean_stale) {
+        match lock_config_dir(&config_dir) {
+            Ok(lock) => Some(lock),
+            Err(e) if e.kind() == io::ErrorKind::NotFound && ignore_missing_cache => None,
+            Err(e) => return Err(e),
+        }
+    } else {
+        None
+    };
+
+    let mut remover = Remover {
+        verbose,
+        remove_files,
+        ignore_missing_files,
+        fail_fast,
+        res: Ok(()),
+        dirs: Vec::new(),
+    };
+
+    let config = if clean_artifacts || clean_stale || !targets.is_empty() {
+        match load_config_data(&config_dir) {
+            Ok(config) => Some(config),
+            // Specific targets or stale files can't be found without the cache
+            Err(e) if clean_stale || !targets.is_empty() => return Err(e),
             Err(e) => {
                 if !ignore_missing_cache {
-                    if fail_fast {
-                        return res;
-                    }
-                    res = Err(io::Error::new(io::ErrorKind::Other, "failed"));
-                    eprintln!("{}", e)
+                    remover.fail(e)?;
                 }
+                None
             }
         }
+    } else {
+        None
+    };
+
+    if let Some(config) = &config {
+        // Checked first, so that nothing is removed if --stale can't be used
+        let stale_dirs = if clean_stale {
+            let build_dir = fs::canonicalize(if config_dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                &config_dir
+            })?;
+            let src_dir = fs::canonicalize(&config.src_dir)?;
+
+            if build_dir == src_dir {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "--stale cannot be used when building in the source directory",
+                ));
+            }
+            Some((build_dir, src_dir))
+        } else {
+            None
+        };
+
+        if clean_artifacts {
+            let selected = if targets.is_empty() {
+                None
+            } else {
+                Some(select_targets(config, &targets)?)
+            };
+
+            for output in target_outputs(config, selected.as_ref()) {
+                remover.remove(&config_dir, output)?;
+            }
+
+            remover.remove_dirs();
+        }
+
+        if let Some((build_dir, src_dir)) = stale_dirs {
+            // The outputs of `autobuild dist` are kept as well
+            let outputs = target_outputs(config, None)
+                .into_iter()
+                .chain(config.dist_outputs.iter().map(PathBuf::as_path))
+                .collect();
+            remove_stale(&mut remover, &build_dir, Path::new(""), &outputs, &src_dir)?;
+        }
     }
 
     if clean_cache {
@@ -207,19 +463,48 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
                 Ok(()) => {}
                 Err(e) => {
                     if !ignore_missing_files {
-                        if fail_fast {
-                            return Err(io::Error::new(
-                                e.kind(),
-                                format!("Could not delete {}: {}", file.display(), e),
-                            ));
-                        }
-                        res = Err(io::Error::new(io::ErrorKind::Other, "failed"));
-                        eprintln!("Could not delete {}: {}", file.display(), e)
+                        remover.fail(io::Error::new(
+                            e.kind(),
+                            format!("Could not delete {}: {}", file.display(), e),
+                        ))?;
                     }
                 }
             }
         }
     }
 
-    res
+    if clean_probe_cache {
+        if verbose {
+            if let Some(file) = cache::cache_file() {
+                println!("Removing {}", file.display());
+            }
+        }
+        if remove_files {
+            if let Err(e) = cache::clear() {
+                remover.fail(e)?;
+            }
+        }
+    }
+
+    if clean_artifact_cache {
+        if let Some(cache) = ArtifactCache::open()? {
+            if verbose {
+                let stats = cache.stats();
+                println!(
+                    "Removing {} ({} hits, {} misses, {} evicted)",
+                    cache.dir().display(),
+                    stats.hits,
+                    stats.misses,
+                    stats.evictions
+                );
+            }
+            if remove_files {
+                if let Err(e) = cache.clear() {
+                    remover.fail(e)?;
+                }
+            }
+        }
+    }
+
+    remover.res
 }
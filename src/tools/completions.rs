@@ -0,0 +1,271 @@
+use std::env::Args;
+use std::io;
+use std::path::Path;
+
+use crate::config::{read_manifest_tree, ConfigData, Manifest};
+use crate::install::InstallDirs;
+
+use super::config::{find_src_dir, read_config_data};
+use super::{OptionArg, ToolOptions};
+
+pub const OPTIONS: ToolOptions = ToolOptions {
+    flags: &[
+        ("--help", OptionArg::None),
+        ("--version", OptionArg::None),
+        ("--complete", OptionArg::None),
+    ],
+    install_dirs: false,
+    optional_targets: false,
+    positional: OptionArg::None,
+};
+
+fn help() {
+    println!(" <bash|zsh|fish>");
+    println!("Prints a completion script for the given shell");
+    println!("Options:");
+    println!("\t--help: Prints this message and exits");
+    println!("\t--version: Prints version information and exits");
+    println!("\t--complete [--] words...: Prints the completions for the last of words, one per line (used by the completion scripts)");
+}
+
+/// The project visible from the current directory, read lazily since most completions don't need it
+#[derive(Default)]
+struct Project {
+    config: Option<ConfigData>,
+    manifests: Vec<Manifest>,
+}
+
+impl Project {
+    fn find() -> Self {
+        let Ok(cur_dir) = std::env::current_dir() else {
+            return Self::default();
+        };
+
+        let config = read_config_data(&cur_dir);
+
+        let src_dir = match &config {
+            Some(config) => Some(config.src_dir.clone()),
+            None => find_src_dir(&cur_dir).ok(),
+        };
+
+        // A broken manifest shouldn't produce errors in the middle of the command line
+        let manifests = src_dir
+            .and_then(|src_dir| read_manifest_tree(&src_dir).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(_, manifest)| manifest)
+            .collect();
+
+        Self { config, manifests }
+    }
+}
+
+fn complete_arg(arg: OptionArg, cur: &str, out: &mut Vec<String>) {
+    match arg {
+        OptionArg::None | OptionArg::Text | OptionArg::Dir => {}
+        OptionArg::ConfigVar => {
+            let project = Project::find();
+            for manifest in &project.manifests {
+                for (name, _) in &manifest.options {
+                    out.push(name.clone());
+                }
+            }
+            if let Some(config) = &project.config {
+                for (name, _) in &config.config_vars {
+                    out.push(name.clone());
+                }
+            }
+        }
+        OptionArg::InstallDir => {
+            let project = Project::find();
+            for manifest in &project.manifests {
+                for (name, _) in &manifest.dirs.dirs {
+                    out.push(format!("{}=", name));
+                }
+            }
+            if let Some(config) = &project.config {
+                for (name, _) in &config.dirs.rest {
+                    out.push(format!("{}=", name));
+                }
+            }
+        }
+        OptionArg::Target => {
+            let project = Project::find();
+            if let Some(config) = &project.config {
+                for (name, _) in &config.build_database {
+                    out.push(name.to_string());
+                }
+            }
+        }
+    }
+
+    out.retain(|cand| cand.starts_with(cur));
+}
+
+fn complete_flag(opts: &ToolOptions, cur: &str, out: &mut Vec<String>) {
+    out.extend(opts.flags.iter().map(|(flag, _)| flag.to_string()));
+
+    if opts.install_dirs {
+        out.extend(InstallDirs::ARGS.iter().map(|(flag, _)| flag.to_string()));
+    }
+
+    if opts.optional_targets && (cur.starts_with("--e") || cur.starts_with("--d")) {
+        let project = Project::find();
+        for manifest in &project.manifests {
+            for (flag, _) in manifest.optional_target_flags() {
+                out.push(format!("--enable-{}", flag));
+                out.push(format!("--disable-{}", flag));
+            }
+        }
+    }
+
+    out.retain(|cand| cand.starts_with(cur));
+}
+
+/// Computes the completions of the last element of `words`, which are the arguments following the program name
+fn complete(words: &[String]) -> Vec<String> {
+    let mut out = Vec::new();
+
+    let Some((cur, prev)) = words.split_last() else {
+        return out;
+    };
+
+    let Some((tool, tool_args)) = prev.split_first() else {
+        out.extend(
+            super::TOOL_NAMES
+                .iter()
+                .filter(|name| name.starts_with(&**cur))
+                .map(|name| name.to_string()),
+        );
+        return out;
+    };
+
+    let Some(opts) = super::tool_options(tool) else {
+        return out;
+    };
+
+    // Options aren't recognized after `--` or the first positional argument
+    let mut expecting = None;
+    let mut positional = false;
+    for arg in tool_args {
+        if expecting.take().is_some() {
+            continue;
+        }
+        if arg == "--" || !arg.starts_with("--") {
+            positional = true;
+            break;
+        }
+        expecting = opts.flag_arg(arg).filter(|arg| *arg != OptionArg::None);
+    }
+
+    if let Some(arg) = expecting {
+        complete_arg(arg, cur, &mut out);
+    } else if cur.starts_with('-') && !positional {
+        complete_flag(opts, cur, &mut out);
+    } else {
+        complete_arg(opts.positional, cur, &mut out);
+    }
+
+    out.sort();
+    out.dedup();
+    out
+}
+
+fn print_script(shell: &str, prg: &str) -> io::Result<()> {
+    let func = format!("_{}", prg.replace(|c: char| !c.is_ascii_alphanumeric(), "_"));
+    match shell {
+        "bash" => {
+            println!("{}() {{", func);
+            println!("    local line=\"${{COMP_LINE:0:COMP_POINT}}\"");
+            println!("    local -a words");
+            println!("    read -ra words <<< \"$line\"");
+            println!("    [[ \"$line\" == *[[:space:]] ]] && words+=(\"\")");
+            println!("    local IFS=$'\\n'");
+            println!(
+                "    COMPREPLY=($({} completions --complete -- \"${{words[@]:1}}\" 2>/dev/null))",
+                prg
+            );
+            println!("    if [[ ${{#COMPREPLY[@]}} -eq 1 && \"${{COMPREPLY[0]}}\" == *= ]]; then");
+            println!("        compopt -o nospace");
+            println!("    fi");
+            println!("}}");
+            println!("complete -o default -F {} {}", func, prg);
+        }
+        "zsh" => {
+            println!("#compdef {}", prg);
+            println!("{}() {{", func);
+            println!("    local -a cands");
+            println!(
+                "    cands=(${{(f)\"$({} completions --complete -- \"${{(@)words[2,CURRENT]}}\" 2>/dev/null)\"}})",
+                prg
+            );
+            println!("    if (( ${{#cands}} )); then");
+            println!("        compadd -S '' -a -- ${{(M)cands:#*=}}");
+            println!("        compadd -a -- ${{cands:#*=}}");
+            println!("    else");
+            println!("        _files");
+            println!("    fi");
+            println!("}}");
+            println!("compdef {} {}", func, prg);
+        }
+        "fish" => {
+            println!("function _{}_complete", func);
+            println!("    set -l prev (commandline -opc)");
+            println!("    set -l cur (commandline -ct)");
+            println!(
+                "    set -l cands ({} completions --complete -- $prev[2..-1] \"$cur\" 2>/dev/null)",
+                prg
+            );
+            println!("    if test (count $cands) -eq 0");
+            println!("        __fish_complete_path \"$cur\"");
+            println!("    else");
+            println!("        printf '%s\\n' $cands");
+            println!("    end");
+            println!("end");
+            println!("complete -c {} -f -a '(_{}_complete)'", prg, func);
+        }
+        shell => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported shell {} (expected bash, zsh, or fish)", shell),
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
+    let prg = Path::new(prg_name)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("autobuild");
+
+    match args.next().as_deref() {
+        Some("--help") => {
+            super::print_help(prg_name, "completions", help);
+            Ok(())
+        }
+        Some("--version") => {
+            super::print_version();
+            Ok(())
+        }
+        Some("--complete") => {
+            let mut words = args.collect::<Vec<_>>();
+            if words.first().map(|s| &**s) == Some("--") {
+                words.remove(0);
+            }
+
+            for cand in complete(&words) {
+                println!("{}", cand);
+            }
+
+            Ok(())
+        }
+        Some(shell) => print_script(shell, prg),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "completions requires a shell (bash, zsh, or fish)",
+        )),
+    }
+}
@@ -1,16 +1,234 @@
+use std::collections::HashSet;
 use std::env::Args;
 use std::io;
 
 use std::path::{Path, PathBuf};
 
-use crate::config::{Config, ConfigData, ConfigInstallDirs, ConfigTargets, ConfigVarValue};
+use crate::config::site::{self, SiteConfig};
+use crate::config::{
+    enable_var_name, read_manifest_tree, Config, ConfigData, ConfigInstallDirs, ConfigTargets, ConfigVarValue,
+};
+use crate::hash::HashAlgorithm;
 use crate::helpers::SplitOnceOwned;
 use crate::install::InstallDirs;
 use crate::map::OrderedMap;
 use crate::rand::Rand;
+use target_tuples::Target;
+
+use super::{OptionArg, ToolOptions};
+
+pub const OPTIONS: ToolOptions = ToolOptions {
+    flags: &[
+        ("--help", OptionArg::None),
+        ("--version", OptionArg::None),
+        ("--set", OptionArg::ConfigVar),
+        ("--unset", OptionArg::ConfigVar),
+        ("--install", OptionArg::InstallDir),
+        ("--src-dir", OptionArg::Dir),
+        ("--config-dir", OptionArg::Dir),
+        ("--build", OptionArg::Text),
+        ("--host", OptionArg::Text),
+        ("--target", OptionArg::Text),
+        ("--target-alias", OptionArg::Text),
+        ("--rustc-target-spec", OptionArg::Text),
+        ("--no-probe-cache", OptionArg::None),
+        ("--hash-algorithm", OptionArg::Text),
+    ],
+    install_dirs: true,
+    optional_targets: true,
+    positional: OptionArg::None,
+};
 
 fn help() {
-    println!()
+    println!(" [OPTIONS] [--] [base-dir]");
+    println!("Configures a build directory for the project in src-dir");
+    println!("Options:");
+    println!("\t--help: Prints this message, and the options declared by the project, and exits");
+    println!("\t--version: Prints version information and exits");
+    println!("\t--set NAME[=VALUE]: Sets the config variable or project option NAME");
+    println!("\t--unset NAME: Unsets the config variable or project option NAME");
+    println!("\t--install DIR=PATH: Sets the installation directory DIR to PATH");
+    println!("\t--src-dir DIR: Uses the project in DIR (searched for from base-dir by default)");
+    println!("\t--config-dir DIR: Stores the configuration in DIR (the current directory by default)");
+    println!("\t--build TARGET: Sets the target the project is built on");
+    println!("\t--host TARGET: Sets the target the project runs on");
+    println!("\t--target TARGET: Sets the target the project produces output for");
+    println!("\t--target-alias NAME=TARGET: Sets the target named NAME declared by the project");
+    println!("\t--rustc-target-spec TARGET=PATH: Uses the target-spec JSON file PATH with rustc for TARGET (a tuple or target name)");
+    println!("\t--no-probe-cache: Probes compilers again, instead of using results cached by other build directories");
+    println!("\t--hash-algorithm ALG: Hashes files with ALG (blake2b-256 by default, or sha512-256)");
+    println!("\t--enable-FLAG[=yes|no], --disable-FLAG: Enables or disables an optional target");
+    println!("Installation Directories:");
+    for (flag, desc) in InstallDirs::ARGS {
+        println!("\t{} DIR: {}", flag, desc);
+    }
+}
+
+fn describe_default(val: &ConfigVarValue) -> &str {
+    match val {
+        ConfigVarValue::Set => "set",
+        ConfigVarValue::Unset => "unset",
+        ConfigVarValue::Value(v) => v,
+    }
+}
+
+/// Prints the options and optional targets declared by the manifests of the project in `src_dir`
+fn project_help(src_dir: &Path) -> io::Result<()> {
+    let manifests = read_manifest_tree(src_dir)?;
+
+    let mut printed_header = false;
+    for (_, manifest) in &manifests {
+        for (name, opt) in &manifest.options {
+            if !printed_header {
+                println!("Project Options:");
+                printed_header = true;
+            }
+            print!("\t--set {}={}", name, opt.ty);
+            if let Some(help) = &opt.help {
+                print!(": {}", help);
+            }
+            if let Some(default) = &opt.default {
+                print!(" (default: {})", describe_default(default));
+            }
+            println!();
+        }
+    }
+
+    let mut printed_header = false;
+    for (_, manifest) in &manifests {
+        for (name, spec) in &manifest.targets {
+            if !printed_header {
+                println!("Project Targets:");
+                printed_header = true;
+            }
+            print!("\t--target-alias {}=TARGET", name);
+            if let Some(help) = &spec.help {
+                print!(": {}", help);
+            }
+            if let Some(default) = &spec.default {
+                print!(" (default: {})", default);
+            }
+            println!();
+        }
+    }
+
+    let mut printed_header = false;
+    for (_, manifest) in &manifests {
+        for (flag, default) in manifest.optional_target_flags() {
+            if !printed_header {
+                println!("Optional Targets:");
+                printed_header = true;
+            }
+            if default {
+                println!("\t--disable-{}", flag);
+            } else {
+                println!("\t--enable-{}", flag);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads an existing configuration in `cfg_dir` without locking it, for informational purposes
+pub fn read_config_data(cfg_dir: &Path) -> Option<ConfigData> {
+    load_config_data(cfg_dir).ok()
+}
+
+/// Reads the configuration cache of the build directory `cfg_dir`, without locking it
+pub fn load_config_data(cfg_dir: &Path) -> io::Result<ConfigData> {
+    let mut cfg_path = cfg_dir.to_path_buf();
+    cfg_path.push(".config.toml");
+
+    let st = std::fs::read_to_string(&cfg_path)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", cfg_path.display(), e)))?;
+
+    toml::from_str(&st).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Could not open {}: {}", cfg_path.display(), e),
+        )
+    })
+}
+
+pub fn find_src_dir(base_dir: &Path) -> io::Result<PathBuf> {
+    let mut src_dir = base_dir.to_path_buf();
+
+    loop {
+        let mut autobuild_file = src_dir.clone();
+        autobuild_file.push("autobuild.toml");
+
+        if std::fs::metadata(autobuild_file).is_ok() {
+            break;
+        } else if &src_dir == Path::new("/") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Could not find source manifest (autobuild.toml)",
+            ));
+        } else {
+            src_dir.pop();
+        }
+    }
+
+    Ok(src_dir)
+}
+
+/// Stores the values from the site files in the configuration.
+///
+/// Only the values that still come from the previously applied site files are replaced, so values set on the command line since are kept.
+/// Called before the command line is applied, so that the command line takes precedence.
+fn apply_site(config: &mut Config, site: &SiteConfig) -> io::Result<()> {
+    let data = config.data_mut();
+    let old = core::mem::take(&mut data.site.values);
+
+    data.dirs
+        .install_dirs
+        .replace_from(&old.dirs.install_dirs, &site.dirs.install_dirs);
+
+    let keys = old
+        .dirs
+        .rest
+        .iter()
+        .chain(&site.dirs.rest)
+        .map(|(key, _)| key.clone())
+        .collect::<HashSet<_>>();
+    for key in keys {
+        if data.dirs.rest.get(&key) == old.dirs.rest.get(&key) {
+            match site.dirs.rest.get(&key) {
+                Some(dir) => data.dirs.rest.insert(key, dir.clone()),
+                None => data.dirs.rest.remove(&key),
+            };
+        }
+    }
+
+    let old_vars = old.config_vars()?;
+    let new_vars = site.config_vars()?;
+    let keys = old_vars
+        .iter()
+        .chain(&new_vars)
+        .map(|(key, _)| key.clone())
+        .collect::<HashSet<_>>();
+    for key in keys {
+        let prev = old_vars.get(&key);
+        let val = new_vars.get(&key);
+        if prev == val || data.config_vars.get(&key) != prev {
+            continue;
+        }
+
+        // Make sure we look up the program again if the site file moved it
+        if old.programs.contains_key(&key) || site.programs.contains_key(&key) {
+            data.programs.remove(&key);
+        }
+
+        match val {
+            Some(val) => data.config_vars.insert(key, val.clone()),
+            None => data.config_vars.remove(&key),
+        };
+    }
+
+    data.site.values = site.clone();
+
+    Ok(())
 }
 
 pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
@@ -20,6 +238,8 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
     let mut cfg_dir = None;
     let mut config_vars = OrderedMap::new();
     let mut extra_install_dirs = OrderedMap::new();
+    let mut target_choices = OrderedMap::new();
+    let mut show_help = false;
 
     let mut install_dirs = InstallDirs::default();
     let mut install_dirs_dirty = false;
@@ -27,6 +247,10 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
     let mut build_alias = None;
     let mut host_alias = None;
     let mut target_alias = None;
+    let mut named_targets = OrderedMap::new();
+    let mut rustc_target_specs = OrderedMap::new();
+    let mut probe_cache = true;
+    let mut hash_algorithm = None;
 
     install_dirs.read_env();
 
@@ -40,8 +264,7 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
                         format!("Unrecognized option --help={}", explicit_arg),
                     ));
                 }
-                super::print_help(prg_name, "config", help);
-                return Ok(());
+                show_help = true;
             }
             "--version" => {
                 if let Some(explicit_arg) = explicit_arg {
@@ -104,6 +327,69 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
                 target_alias = Some(val);
             }
 
+            "--target-alias" => {
+                let val = super::require_arg(Some("--target-alias"), &mut args, explicit_arg)?;
+
+                let (k, v) = val.split_once_owned("=").map_err(|val| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "--target-alias requires an argument of the form name=target, but got `{}` instead",
+                            val
+                        ),
+                    )
+                })?;
+
+                if ConfigTargets::RESERVED_NAMES.contains(&&*k) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Use --{} to set the {} target", k, k),
+                    ));
+                }
+
+                let targ: Target = v.parse().map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Target `{}` is not recognized or cannot be parsed", v),
+                    )
+                })?;
+
+                named_targets.insert(k, targ);
+            }
+
+            "--rustc-target-spec" => {
+                let val =
+                    super::require_arg(Some("--rustc-target-spec"), &mut args, explicit_arg)?;
+
+                let (k, v) = val.split_once_owned("=").map_err(|val| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "--rustc-target-spec requires an argument of the form target=path, but got `{}` instead",
+                            val
+                        ),
+                    )
+                })?;
+
+                rustc_target_specs.insert(k, std::env::current_dir()?.join(v));
+            }
+
+            "--hash-algorithm" => {
+                let val = super::require_arg(Some("--hash-algorithm"), &mut args, explicit_arg)?;
+
+                hash_algorithm = Some(val.parse::<HashAlgorithm>()?);
+            }
+
+            "--no-probe-cache" => {
+                if let Some(explicit_arg) = explicit_arg {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Unrecognized option --no-probe-cache={}", explicit_arg),
+                    ));
+                }
+                probe_cache = false;
+            }
+
             "--" => {
                 if let Some(explicit_arg) = explicit_arg {
                     return Err(io::Error::new(
@@ -114,6 +400,32 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
                 base_dir = args.next().map(PathBuf::from);
                 break;
             }
+            x if x.starts_with("--enable-") => {
+                let flag = &x["--enable-".len()..];
+                let enable = match explicit_arg.as_deref() {
+                    None | Some("yes") => true,
+                    Some("no") => false,
+                    Some(val) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("{} expects `yes` or `no`, but got `{}`", x, val),
+                        ))
+                    }
+                };
+
+                target_choices.insert(flag.to_string(), enable);
+            }
+            x if x.starts_with("--disable-") => {
+                if let Some(explicit_arg) = explicit_arg {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Unrecognized option {}={}", x, explicit_arg),
+                    ));
+                }
+                let flag = &x["--disable-".len()..];
+
+                target_choices.insert(flag.to_string(), false);
+            }
             x if x.starts_with("--") => {
                 let val = super::require_arg(Some(x), &mut args, explicit_arg)?;
 
@@ -152,8 +464,43 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
         }
     };
 
+    if show_help {
+        super::print_help(prg_name, "config", help);
+
+        // The project options are a courtesy - `--help` still works outside of a project
+        let src_dir = match src_dir {
+            Some(src_dir) => Some(src_dir),
+            None => read_config_data(&cfg_dir)
+                .map(|data| data.src_dir)
+                .or_else(|| find_src_dir(&base_dir).ok()),
+        };
+        if let Some(src_dir) = src_dir {
+            project_help(&src_dir)?;
+        }
+        return Ok(());
+    }
+
     let mut config = match Config::open(cfg_dir.clone()) {
         Ok(mut config) => {
+            if let Some(alg) = hash_algorithm.filter(|alg| *alg != config.data().hash_algorithm) {
+                // Hashes computed with the old algorithm can never match, so start from an empty file cache
+                let data = config.data_mut();
+                data.hash_algorithm = alg;
+                data.file_cache = OrderedMap::new();
+                config.force_reconfigure();
+            }
+
+            let mut dirs = config.data().dirs.install_dirs.clone();
+            dirs.set_from(&install_dirs);
+
+            let site_files = site::read_site_files(&dirs)?;
+
+            if config.check_site_files(&site_files)? {
+                println!("Site defaults changed, reconfiguring");
+                apply_site(&mut config, &site::merge_site_files(&site_files))?;
+                config.force_reconfigure();
+            }
+
             if install_dirs_dirty {
                 let data = config.data_mut();
                 data.dirs.install_dirs.set_from(&install_dirs);
@@ -165,30 +512,20 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
             }
             config
         }
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!(
+                    "Could not read the configuration in {}: {} (remove its .config.toml to configure from scratch)",
+                    cfg_dir.display(),
+                    e
+                ),
+            ))
+        }
         Err(_) => {
             let src_dir = match src_dir {
                 Some(src_dir) => src_dir.canonicalize()?,
-                None => {
-                    let mut src_dir = base_dir;
-
-                    loop {
-                        let mut autobuild_file = src_dir.clone();
-                        autobuild_file.push("autobuild.toml");
-
-                        if std::fs::metadata(autobuild_file).is_ok() {
-                            break;
-                        } else if &src_dir == Path::new("/") {
-                            return Err(io::Error::new(
-                                io::ErrorKind::InvalidInput,
-                                "Could not find source manifest (autobuild.toml)",
-                            ));
-                        } else {
-                            src_dir.pop();
-                        }
-                    }
-
-                    src_dir
-                }
+                None => find_src_dir(&base_dir)?,
             };
 
             let build = match build_alias.as_ref() {
@@ -198,7 +535,7 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
                         format!("Target `{}` is not recognized or cannot be parsed", alias),
                     )
                 })?,
-                None => crate::targ::guess::ident_target(),
+                None => crate::targ::guess::ident_target()?,
             };
 
             let host = match host_alias.as_ref() {
@@ -221,31 +558,120 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
                 None => host.clone(),
             };
 
-            let dirs = ConfigInstallDirs {
-                install_dirs,
-                rest: extra_install_dirs,
+            let site_files = site::read_site_files(&install_dirs)?;
+            let site = site::merge_site_files(&site_files);
+
+            let mut dirs = ConfigInstallDirs {
+                install_dirs: site.dirs.install_dirs.clone(),
+                rest: site.dirs.rest.clone(),
             };
 
+            dirs.install_dirs.set_from(&install_dirs);
+            dirs.rest.extend(extra_install_dirs);
+
             let targets = ConfigTargets {
                 build,
                 host,
                 target,
                 others: OrderedMap::new(),
             };
-            Config::new(
+            let mut config = Config::new(
                 cfg_dir.clone(),
                 Box::new(ConfigData::new(src_dir, dirs, targets, &mut rand)),
-            )
+            )?;
+
+            if let Some(alg) = hash_algorithm {
+                config.data_mut().hash_algorithm = alg;
+            }
+
+            config.check_site_files(&site_files)?;
+            apply_site(&mut config, &site)?;
+
+            config
         }
     };
 
+    // The documentation of each project is installed into a directory of its own, named after its source directory, unless
+    //  docdir is set
+    let data = config.data_mut();
+    if data.dirs.install_dirs.docdir.is_none() {
+        if let Some(name) = data.src_dir.file_name().and_then(|name| name.to_str()) {
+            let name = name.to_string();
+            data.dirs.install_dirs.set_project_name(&name);
+        }
+    }
+
+    for (flag, enable) in &target_choices {
+        let val = ConfigVarValue::Value(if *enable { "yes" } else { "no" }.to_string());
+        config_vars.insert(enable_var_name(flag), val);
+    }
+
+    let alias_names = named_targets
+        .iter()
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+
+    for (name, targ) in named_targets {
+        let data = config.data_mut();
+        data.target_aliases.insert(name.clone());
+        let prev = data.targets.others.insert(name, targ.clone());
+
+        if prev.as_ref().map(Target::get_name) != Some(targ.get_name()) {
+            // Programs may have been found for the old target
+            config.force_reconfigure();
+        }
+    }
+
+    if !probe_cache {
+        config.disable_probe_cache();
+    }
+
+    for (name, spec) in rustc_target_specs {
+        config.data_mut().rustc_target_specs.insert(name, spec);
+    }
+
     for (key, val) in config_vars {
+        if config.data().config_vars.get(&key) != Some(&val) {
+            // Manifests may depend on the value, so they need to be reprocessed
+            config.force_reconfigure();
+        }
+
         let data = config.data_mut();
 
         data.config_vars.insert(key, val);
     }
 
+    config.find_runners()?;
     config.read_manifest(None)?;
 
+    for name in alias_names {
+        if !config.target_names().contains(&name) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "--target-alias {}: No manifest declares a target named {}",
+                    name, name
+                ),
+            ));
+        }
+    }
+
+    for (flag, enable) in &target_choices {
+        if !config
+            .optional_targets()
+            .iter()
+            .any(|(_, opt)| opt.flag == *flag)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Unrecognized option --{}-{}",
+                    if *enable { "enable" } else { "disable" },
+                    flag
+                ),
+            ));
+        }
+    }
+
     config.cleanup()
 }
@@ -0,0 +1,476 @@
+use std::{
+    collections::BTreeSet,
+    env::Args,
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::config::{read_manifest_tree, Config, BuildScriptSrc, BuildTargetStep, ConfigData, Manifest, StepSpec};
+use crate::hash::{to_hex, DigestAlgorithm};
+use crate::programs::rustc::parse_dep_info;
+use crate::tar::{EntryType, TarWriter};
+
+use super::config::load_config_data;
+use super::{require_arg, OptionArg, ToolOptions};
+
+pub const OPTIONS: ToolOptions = ToolOptions {
+    flags: &[
+        ("--cfg-dir", OptionArg::Dir),
+        ("--output", OptionArg::Text),
+        ("--prefix", OptionArg::Text),
+        ("--mtime", OptionArg::Text),
+        ("--list", OptionArg::None),
+        ("--verbose", OptionArg::None),
+        ("--version", OptionArg::None),
+        ("--help", OptionArg::None),
+    ],
+    install_dirs: false,
+    optional_targets: false,
+    positional: OptionArg::None,
+};
+
+fn print_help() {
+    println!(" [OPTIONS]");
+    println!("Creates a reproducible tar archive of the source directory of a build directory, and a SHA-256 checksum file for it");
+    println!("The archive contains the manifests, the files they refer to (the inputs of the last build of each target), and the files matched by the `[dist]` tables of the manifests");
+    println!("Options:");
+    println!("\t--cfg-dir DIR: The build directory (default: the current directory)");
+    println!("\t--output FILE: Write the archive to FILE (default: PREFIX.tar in the build directory). The checksums are written to FILE.sha256");
+    println!("\t--prefix PREFIX: The directory that contains every file of the archive (default: the name of the source directory)");
+    println!("\t--mtime SECS: The modification time of every entry, in seconds since the epoch (default: SOURCE_DATE_EPOCH, or 0)");
+    println!("\t--list: Print the files that would be archived, and write nothing");
+    println!("\t--verbose: Print every file archived");
+    println!("\t--version: Print version information and exit");
+    println!("\t--help: Print this message and exit");
+}
+
+/// Matches a single path component against a component of a pattern, which may contain `*` and `?`
+fn component_matches(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| component_matches(rest, &name[i..])),
+        Some((b'?', rest)) => !name.is_empty() && component_matches(rest, &name[1..]),
+        Some((c, rest)) => name.first() == Some(c) && component_matches(rest, &name[1..]),
+    }
+}
+
+/// Matches the components of a path against the components of a pattern.
+/// A pattern that matches a directory matches everything in it
+fn pattern_matches(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.split_first(), path.split_first()) {
+        (None, _) => true,
+        (Some((&"**", rest)), _) => (0..=path.len()).any(|i| pattern_matches(rest, &path[i..])),
+        (Some(_), None) => false,
+        (Some((pat, pat_rest)), Some((name, path_rest))) => {
+            component_matches(pat.as_bytes(), name.as_bytes())
+                && pattern_matches(pat_rest, path_rest)
+        }
+    }
+}
+
+/// Converts `path` (relative to `base`) to a `/`-separated path relative to the source directory
+fn relative_name(base: &str, path: &Path) -> io::Result<String> {
+    let mut components: Vec<&str> = base.split('/').filter(|c| !c.is_empty()).collect();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => components.push(name.to_str().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: path is not valid UTF-8", path.display()),
+                )
+            })?),
+            Component::CurDir => {}
+            Component::ParentDir if components.pop().is_some() => {}
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{} is outside of the source directory", path.display()),
+                ))
+            }
+        }
+    }
+
+    Ok(components.join("/"))
+}
+
+/// Lists every file under `dir` as a `/`-separated path relative to the source directory, skipping `skip` (the build directory)
+/// and the metadata directories of version control systems
+fn walk(src_dir: &Path, rel: &str, skip: &Path, out: &mut Vec<String>) -> io::Result<()> {
+    let dir = src_dir.join(rel);
+    let entries = fs::read_dir(&dir)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", dir.display(), e)))?;
+
+    for ent in entries {
+        let ent = ent?;
+        let name = ent.file_name();
+        let name = name.to_str().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: path is not valid UTF-8", ent.path().display()),
+            )
+        })?;
+
+        if matches!(name, ".git" | ".hg" | ".svn") {
+            continue;
+        }
+
+        let child = if rel.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", rel, name)
+        };
+
+        if ent.file_type()?.is_dir() {
+            if fs::canonicalize(ent.path())? == skip {
+                continue;
+            }
+            walk(src_dir, &child, skip, out)?;
+        } else {
+            out.push(child);
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds the inputs inside the source directory listed in the dep-info of the last build of each compiled target,
+/// and returns the sources of the targets that have one
+fn dep_info_files(
+    config: &ConfigData,
+    src_dir: &Path,
+    build_dir: &Path,
+    files: &mut BTreeSet<String>,
+) -> io::Result<BTreeSet<String>> {
+    let mut built = BTreeSet::new();
+
+    for (name, info) in &config.build_database {
+        let BuildTargetStep::Build(build) = &info.step else {
+            continue;
+        };
+
+        for dep_info in &build.secondary_artifacts {
+            let st = match fs::read_to_string(build_dir.join(dep_info)) {
+                Ok(st) => st,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        format!("{}: {}", dep_info.display(), e),
+                    ))
+                }
+            };
+
+            let base = name.base_path.to_str().unwrap_or_default();
+            built.insert(relative_name(base, &build.src)?);
+
+            for input in parse_dep_info(&st) {
+                // Inputs outside of the source directory (such as generated files) aren't part of the archive
+                let Ok(input) = fs::canonicalize(build_dir.join(input)) else {
+                    continue;
+                };
+                if let Ok(input) = input.strip_prefix(src_dir) {
+                    files.insert(relative_name("", input)?);
+                }
+            }
+        }
+    }
+
+    Ok(built)
+}
+
+/// Adds the files `manifest` (in the directory `rel`) refers to.
+///
+/// The sources of a target in `built` were added from its dep-info. Otherwise every file in the directory of its crate root is added,
+/// since modules and `include_str!` inputs can be anywhere below it
+fn manifest_files(
+    rel: &str,
+    manifest: &Manifest,
+    all_files: &[String],
+    built: &BTreeSet<String>,
+    files: &mut BTreeSet<String>,
+) -> io::Result<()> {
+    let prefix = if rel.is_empty() {
+        String::new()
+    } else {
+        format!("{}/", rel)
+    };
+
+    files.insert(format!("{}autobuild.toml", prefix));
+
+    for (_, spec) in &manifest.target.targets {
+        match spec.step() {
+            StepSpec::Build(build) => {
+                let src = relative_name(rel, &build.src)?;
+                files.insert(src.clone());
+
+                if built.contains(&src) {
+                    continue;
+                }
+
+                let src_dir = src.rsplit_once('/').map_or("", |(dir, _)| dir);
+                let dir_prefix = if src_dir.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}/", src_dir)
+                };
+                files.extend(
+                    all_files
+                        .iter()
+                        .filter(|file| file.starts_with(&*dir_prefix))
+                        .cloned(),
+                );
+            }
+            StepSpec::Script(script) => {
+                let (BuildScriptSrc::Build(src) | BuildScriptSrc::Configure(src)) = &script.src;
+                files.insert(relative_name(rel, src)?);
+            }
+            StepSpec::Subdir(_) => {}
+        }
+    }
+
+    for (_, spec) in &manifest.rustc_target_specs {
+        // Target specs outside of the source directory are provided by the system
+        if let Ok(spec) = relative_name(rel, spec) {
+            files.insert(spec);
+        }
+    }
+
+    let split = |pat: &str| -> Vec<String> {
+        pat.split('/')
+            .filter(|c| !c.is_empty() && *c != ".")
+            .map(str::to_string)
+            .collect()
+    };
+
+    let in_dir = |file: &str| -> Option<Vec<String>> {
+        file.strip_prefix(&*prefix)
+            .map(|rest| rest.split('/').map(str::to_string).collect())
+    };
+
+    for pattern in &manifest.dist.include {
+        let pattern = split(pattern);
+        let pattern: Vec<&str> = pattern.iter().map(|c| &**c).collect();
+        for file in all_files {
+            if let Some(path) = in_dir(file) {
+                let path: Vec<&str> = path.iter().map(|c| &**c).collect();
+                if pattern_matches(&pattern, &path) {
+                    files.insert(file.clone());
+                }
+            }
+        }
+    }
+
+    for pattern in &manifest.dist.exclude {
+        let pattern = split(pattern);
+        let pattern: Vec<&str> = pattern.iter().map(|c| &**c).collect();
+        files.retain(|file| {
+            // Manifests can't be excluded, since the archive couldn't be configured without them
+            if file.rsplit('/').next() == Some("autobuild.toml") {
+                return true;
+            }
+            match in_dir(file) {
+                Some(path) => {
+                    let path: Vec<&str> = path.iter().map(|c| &**c).collect();
+                    !pattern_matches(&pattern, &path)
+                }
+                None => true,
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    if metadata.permissions().mode() & 0o111 != 0 {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+#[cfg(not(unix))]
+fn file_mode(_: &fs::Metadata) -> u32 {
+    0o644
+}
+
+pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
+    let mut config_dir = PathBuf::new();
+    let mut output = None;
+    let mut prefix = None;
+    let mut mtime = None;
+    let mut list = false;
+    let mut verbose = false;
+
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--cfg-dir" => {
+                config_dir = PathBuf::from(require_arg(Some("--cfg-dir"), &mut args, None)?);
+            }
+            "--output" => {
+                output = Some(PathBuf::from(require_arg(
+                    Some("--output"),
+                    &mut args,
+                    None,
+                )?));
+            }
+            "--prefix" => prefix = Some(require_arg(Some("--prefix"), &mut args, None)?),
+            "--mtime" => mtime = Some(require_arg(Some("--mtime"), &mut args, None)?),
+            "--list" => list = true,
+            "--verbose" => verbose = true,
+            "--version" => {
+                super::print_version();
+                return Ok(());
+            }
+            "--help" => {
+                super::print_help(prg_name, "dist", print_help);
+                return Ok(());
+            }
+            _ => super::help_subcommands()?,
+        }
+    }
+
+    let mtime = match mtime.or_else(|| std::env::var("SOURCE_DATE_EPOCH").ok()) {
+        Some(mtime) => mtime.trim().parse::<u64>().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("`{}` is not a time in seconds since the epoch", mtime),
+            )
+        })?,
+        None => 0,
+    };
+
+    let config = load_config_data(&config_dir)?;
+    let src_dir = fs::canonicalize(&config.src_dir)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", config.src_dir.display(), e)))?;
+    let build_dir = fs::canonicalize(if config_dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        &config_dir
+    })?;
+
+    let prefix = match prefix {
+        Some(prefix) => prefix,
+        None => src_dir
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("dist")
+            .to_string(),
+    };
+
+    let mut all_files = Vec::new();
+    walk(&src_dir, "", &build_dir, &mut all_files)?;
+    all_files.sort();
+
+    let mut files = BTreeSet::new();
+    let built = dep_info_files(&config, &src_dir, &build_dir, &mut files)?;
+    for (dir, manifest) in read_manifest_tree(&config.src_dir)? {
+        let rel = dir.strip_prefix(&config.src_dir).unwrap_or(&dir);
+        let rel = relative_name("", rel)?;
+        manifest_files(&rel, &manifest, &all_files, &built, &mut files)?;
+    }
+
+    for file in &files {
+        if fs::symlink_metadata(src_dir.join(file)).is_err() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} is referenced by a manifest, but does not exist", file),
+            ));
+        }
+    }
+
+    if list {
+        for file in &files {
+            println!("{}", file);
+        }
+        return Ok(());
+    }
+
+    // Every directory gets its own entry, so that extracting the archive doesn't depend on the umask
+    let mut dirs = BTreeSet::new();
+    for file in &files {
+        let mut path = &**file;
+        while let Some((parent, _)) = path.rsplit_once('/') {
+            dirs.insert(parent.to_string());
+            path = parent;
+        }
+    }
+
+    let mut entries: Vec<(String, bool)> = files
+        .iter()
+        .map(|file| (file.clone(), false))
+        .chain(dirs.into_iter().map(|dir| (dir, true)))
+        .collect();
+    entries.sort();
+
+    let mut tar = TarWriter::new(Vec::new(), mtime);
+    tar.append(&format!("{}/", prefix), EntryType::Dir, 0o755, &[], "")?;
+
+    for (entry, is_dir) in &entries {
+        let name = format!("{}/{}", prefix, entry);
+        if *is_dir {
+            tar.append(&format!("{}/", name), EntryType::Dir, 0o755, &[], "")?;
+            continue;
+        }
+
+        let path = src_dir.join(entry);
+        let metadata = fs::symlink_metadata(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+
+        if verbose {
+            println!("{}", name);
+        }
+
+        if metadata.file_type().is_symlink() {
+            let link = fs::read_link(&path)?;
+            let link = link.to_str().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: link is not valid UTF-8", path.display()),
+                )
+            })?;
+            tar.append(&name, EntryType::Symlink, 0o777, &[], link)?;
+        } else {
+            let data = fs::read(&path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            tar.append(&name, EntryType::File, file_mode(&metadata), &data, "")?;
+        }
+    }
+
+    let archive = tar.finish()?;
+
+    let output = output.unwrap_or_else(|| config_dir.join(format!("{}.tar", prefix)));
+    fs::write(&output, &archive)
+        .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", output.display(), e)))?;
+
+    let digest = DigestAlgorithm::Sha256.digest_reader(&*archive)?;
+    let file_name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut checksum_file = output.clone().into_os_string();
+    checksum_file.push(".sha256");
+    fs::write(
+        &checksum_file,
+        format!("{}  {}\n", to_hex(&digest), file_name),
+    )?;
+
+    // Recorded so that `autobuild clean --stale` keeps them
+    let mut dist_outputs = Vec::new();
+    for file in [output.as_path(), Path::new(&checksum_file)] {
+        if let Ok(file) = fs::canonicalize(file)?.strip_prefix(&build_dir) {
+            dist_outputs.push(file.to_path_buf());
+        }
+    }
+    let mut config = Config::open(config_dir)?;
+    config.data_mut().dist_outputs = dist_outputs;
+    config.cleanup()?;
+
+    println!("Wrote {}", output.display());
+
+    Ok(())
+}
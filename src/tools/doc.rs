@@ -0,0 +1,492 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env::Args,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::config::{
+    read_manifest_tree, Artifact, BuildInfo, BuildTargetInfo, BuildTargetStep, Config,
+    ConfigData, ConfigFoundProgram, ConfigProgramInfo, ProgramSpec, StepSpec, TargetName,
+};
+use crate::helpers::{FormatArg, FormatSpec, FormatString};
+use crate::log::trace;
+use crate::programs::rustc::RustcVersion;
+
+use super::{require_arg, OptionArg, ToolOptions};
+
+pub const OPTIONS: ToolOptions = ToolOptions {
+    flags: &[
+        ("--cfg-dir", OptionArg::Dir),
+        ("--document-private-items", OptionArg::None),
+        ("--verbose", OptionArg::None),
+        ("--version", OptionArg::None),
+        ("--help", OptionArg::None),
+    ],
+    install_dirs: false,
+    optional_targets: false,
+    positional: OptionArg::Target,
+};
+
+/// The output directory of rustdoc, relative to the build directory
+pub const DOC_DIR: &str = "doc";
+
+fn print_help() {
+    println!(" [OPTIONS] [--] [target...]");
+    println!("Documents the Rust targets built by `autobuild build` with rustdoc, into `doc` in the build directory");
+    println!("If targets (as `path:name`, or `name` for a target of the root manifest) are given, only those are documented");
+    println!("The documentation is installed into docdir by `autobuild install`");
+    println!("Options:");
+    println!("\t--cfg-dir DIR: The build directory (default: the current directory)");
+    println!("\t--document-private-items: Also document items that are not public");
+    println!("\t--verbose: Print each rustdoc command");
+    println!("\t--version: Print version information and exit");
+    println!("\t--help: Print this message and exit");
+}
+
+/// A target that rustdoc can document
+struct RustTarget<'a> {
+    build: &'a BuildInfo,
+    rustc: &'a RustcVersion,
+    /// The crate root, relative to the source directory
+    src: PathBuf,
+    crate_name: String,
+    /// The directory the documentation is written to, relative to `doc`, which is separate for each instance of a target
+    out_dir: PathBuf,
+    /// The Rust targets the target links to directly, passed to rustdoc with `--extern`
+    deps: Vec<&'a TargetName>,
+}
+
+/// The name of the crate built by the target `name`, which is the same for every instance of the target
+fn crate_name(name: &TargetName) -> String {
+    let base = name.name.split('@').next().unwrap_or(&name.name);
+    base.replace('-', "_")
+}
+
+/// The compiler of `info`, if it is rustc
+fn target_rustc<'a>(config: &'a ConfigData, info: &'a BuildTargetInfo) -> Option<&'a RustcVersion> {
+    let BuildTargetStep::Build(build) = &info.step else {
+        return None;
+    };
+    let key = info
+        .instance
+        .as_ref()
+        .and_then(|instance| instance.programs.get(&build.compiler_name))
+        .unwrap_or(&build.compiler_name);
+    match config.programs.get(key)?.info.as_ref()? {
+        ConfigProgramInfo::Rustc(rustc) => Some(rustc),
+    }
+}
+
+/// Finds the Rust targets that `name` links to, looking through the targets (such as groups) that build nothing themselves
+fn rust_deps<'a>(
+    config: &'a ConfigData,
+    name: &'a TargetName,
+    rust: &HashSet<&TargetName>,
+    seen: &mut HashSet<&'a TargetName>,
+    deps: &mut Vec<&'a TargetName>,
+) {
+    let Some(info) = config.build_database.get(name) else {
+        return;
+    };
+    for dep in &info.deps {
+        if !seen.insert(dep) {
+            continue;
+        }
+        if rust.contains(dep) {
+            deps.push(dep);
+        } else if !matches!(
+            config.build_database.get(dep).map(|info| &info.step),
+            Some(BuildTargetStep::Build(_))
+        ) {
+            rust_deps(config, dep, rust, seen, deps);
+        }
+    }
+}
+
+/// Finds the Rust targets of the configuration, and the crate root of each from its manifest
+fn rust_targets(config: &ConfigData) -> io::Result<HashMap<&TargetName, RustTarget<'_>>> {
+    trace!(rust_targets);
+    let mut roots = HashMap::new();
+    for (dir, manifest) in read_manifest_tree(&config.src_dir)? {
+        let rel = dir.strip_prefix(&config.src_dir).unwrap_or(&dir).to_path_buf();
+        for (name, spec) in &manifest.target.targets {
+            if let StepSpec::Build(build) = spec.step() {
+                roots.insert((rel.clone(), name.clone()), rel.join(&build.src));
+            }
+        }
+    }
+
+    let mut targets = HashMap::new();
+    for (name, info) in &config.build_database {
+        let BuildTargetStep::Build(build) = &info.step else {
+            continue;
+        };
+        // Test harnesses document the same items as the crate they test
+        if build.test.is_some() {
+            continue;
+        }
+        let Some(rustc) = target_rustc(config, info) else {
+            continue;
+        };
+        let base = name.name.split('@').next().unwrap_or(&name.name);
+        let Some(src) = roots.get(&(name.base_path.clone(), base.to_string())) else {
+            continue;
+        };
+
+        targets.insert(
+            name,
+            RustTarget {
+                build,
+                rustc,
+                src: src.clone(),
+                crate_name: crate_name(name),
+                out_dir: info
+                    .instance
+                    .as_ref()
+                    .map(|instance| instance.output_dir.clone())
+                    .unwrap_or_default(),
+                deps: Vec::new(),
+            },
+        );
+    }
+
+    let rust = targets.keys().copied().collect::<HashSet<_>>();
+    for (name, target) in &mut targets {
+        rust_deps(config, name, &rust, &mut HashSet::new(), &mut target.deps);
+    }
+
+    Ok(targets)
+}
+
+/// Orders `names` and their dependencies so that every target comes after the targets it depends on
+fn dependency_order<'a>(
+    targets: &HashMap<&'a TargetName, RustTarget<'a>>,
+    names: &[&'a TargetName],
+) -> Vec<&'a TargetName> {
+    fn visit<'a>(
+        targets: &HashMap<&'a TargetName, RustTarget<'a>>,
+        name: &'a TargetName,
+        visited: &mut HashSet<&'a TargetName>,
+        order: &mut Vec<&'a TargetName>,
+    ) {
+        if !visited.insert(name) {
+            return;
+        }
+        for dep in &targets[name].deps {
+            visit(targets, dep, visited, order);
+        }
+        order.push(name);
+    }
+
+    let mut visited = HashSet::new();
+    let mut order = Vec::new();
+    for name in names {
+        visit(targets, name, &mut visited, &mut order);
+    }
+    order
+}
+
+/// The program `RUSTDOC` of the configuration.
+///
+/// If no manifest declares it, it is found as for a program declared with the names `rustdoc` (so it can be set with
+///  `autobuild config RUSTDOC=...`), preferring the rustdoc next to `RUSTC`, and kept for later runs
+fn find_rustdoc(config: &mut Config) -> io::Result<ConfigFoundProgram> {
+    trace!(find_rustdoc);
+    if let Some(rustdoc) = config.data().programs.get("RUSTDOC") {
+        return Ok(rustdoc.clone());
+    }
+
+    let mut names = Vec::new();
+    if let Some(rustc) = config.data().programs.get("RUSTC") {
+        let sibling = rustc
+            .location
+            .with_file_name(format!("rustdoc{}", std::env::consts::EXE_SUFFIX));
+        if let Some(sibling) = sibling.to_str() {
+            names.push(sibling.to_string());
+        }
+    }
+    names.push("rustdoc".to_string());
+
+    let spec = ProgramSpec {
+        names,
+        ..Default::default()
+    };
+    config.find_program("RUSTDOC", &spec)?;
+
+    Ok(config.data().programs["RUSTDOC"].clone())
+}
+
+/// The library that `target` links dependents against
+fn link_artifact<'a>(target: &RustTarget<'a>) -> Option<&'a Path> {
+    let rustc = &target.rustc.target;
+    let is_lib = |path: &&PathBuf| {
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        name.ends_with(&rustc.rlib_suffix)
+            || name.ends_with(".rmeta")
+            || name.ends_with(&rustc.dylib_suffix)
+    };
+    target
+        .build
+        .primary_artifacts
+        .iter()
+        .find(is_lib)
+        .map(PathBuf::as_path)
+}
+
+/// Registers the documentation as artifacts installed into docdir, replacing those of earlier runs.
+///
+/// `crates` maps the output directory (relative to `doc`) and name of each crate to the target it documents
+fn register_docs(
+    config: &mut ConfigData,
+    build_dir: &Path,
+    crates: &HashMap<(PathBuf, String), TargetName>,
+) -> io::Result<()> {
+    config
+        .artifacts
+        .retain(|art| !art.path.starts_with(DOC_DIR));
+
+    let out_dirs = crates
+        .keys()
+        .map(|(out_dir, _)| out_dir)
+        .filter(|out_dir| !out_dir.as_os_str().is_empty())
+        .collect::<HashSet<_>>();
+
+    register_doc_dir(config, build_dir, Path::new(""), crates)?;
+    for out_dir in out_dirs {
+        if build_dir.join(DOC_DIR).join(out_dir).is_dir() {
+            register_doc_dir(config, build_dir, out_dir, crates)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Registers the documentation written to `out_dir` (relative to `doc`), which is installed into the same directory of
+///  docdir
+fn register_doc_dir(
+    config: &mut ConfigData,
+    build_dir: &Path,
+    out_dir: &Path,
+    crates: &HashMap<(PathBuf, String), TargetName>,
+) -> io::Result<()> {
+    let doc_dir = Path::new(DOC_DIR).join(out_dir);
+    let install_dir = if out_dir.as_os_str().is_empty() {
+        String::new()
+    } else {
+        format!("/{}", out_dir.display())
+    };
+
+    // `{docdir}` followed by `rest`
+    let artifact = |path: PathBuf, target: TargetName, rest: &str| Artifact {
+        path,
+        deps: Vec::new(),
+        target,
+        tuple: None,
+        aliases: Vec::new(),
+        install_base: Some(FormatString {
+            args: vec![FormatArg {
+                leading_text: String::new(),
+                fmt: FormatSpec::Keyed("docdir".to_string()),
+            }],
+            rest: format!("{}{}", install_dir, rest),
+        }),
+        local_rpath: None,
+    };
+    let target_of = |krate: &str| {
+        crates
+            .get(&(out_dir.to_path_buf(), krate.to_string()))
+            .cloned()
+            .unwrap_or_else(shared_target)
+    };
+
+    let mut entries = fs::read_dir(build_dir.join(&doc_dir))?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|ent| ent.file_name());
+    for ent in entries {
+        let name = ent.file_name().to_string_lossy().into_owned();
+        // rustdoc's own files, such as its lock file
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = doc_dir.join(&name);
+
+        // The documentation of instances is registered separately
+        if out_dir.as_os_str().is_empty()
+            && crates
+                .keys()
+                .any(|(dir, _)| dir.as_os_str() == ent.file_name())
+        {
+            continue;
+        }
+
+        if name == "src" {
+            let mut sources = fs::read_dir(ent.path())?.collect::<io::Result<Vec<_>>>()?;
+            sources.sort_by_key(|ent| ent.file_name());
+            for src in sources {
+                let krate = src.file_name().to_string_lossy().into_owned();
+                config
+                    .artifacts
+                    .push(artifact(path.join(&krate), target_of(&krate), "/src"));
+            }
+            continue;
+        }
+
+        // The files shared by every crate, such as the search index, belong to the `doc` pseudo-target
+        config.artifacts.push(artifact(path, target_of(&name), ""));
+    }
+
+    Ok(())
+}
+
+fn shared_target() -> TargetName {
+    TargetName {
+        base_path: PathBuf::new(),
+        name: DOC_DIR.to_string(),
+    }
+}
+
+pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
+    let mut config_dir = PathBuf::new();
+    let mut private = false;
+    let mut verbose = false;
+    let mut names = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--cfg-dir" => {
+                config_dir = PathBuf::from(require_arg(Some("--cfg-dir"), &mut args, None)?);
+            }
+            "--document-private-items" => private = true,
+            "--verbose" => verbose = true,
+            "--version" => {
+                super::print_version();
+                return Ok(());
+            }
+            "--help" => {
+                super::print_help(prg_name, "doc", print_help);
+                return Ok(());
+            }
+            "--" => {
+                for arg in &mut args {
+                    names.push(TargetName::from_arg(&arg)?);
+                }
+                break;
+            }
+            x if x.starts_with('-') => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unrecognized option {}", x),
+                ))
+            }
+            _ => names.push(TargetName::from_arg(&arg)?),
+        }
+    }
+
+    let build_dir = fs::canonicalize(if config_dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        &config_dir
+    })?;
+    let mut config = Config::open(config_dir)?;
+    let rustdoc = find_rustdoc(&mut config)?;
+
+    let data = config.data();
+    let targets = rust_targets(data)?;
+
+    let mut selected = Vec::new();
+    for name in &names {
+        match targets.get_key_value(name) {
+            Some((name, _)) => selected.push(*name),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No such Rust target {}", name),
+                ))
+            }
+        }
+    }
+    if names.is_empty() {
+        selected.extend(data.build_database.iter().filter_map(|(name, _)| {
+            targets.get_key_value(name).map(|(name, _)| *name)
+        }));
+    }
+
+    let doc_dir = build_dir.join(DOC_DIR);
+    let mut crates = HashMap::new();
+
+    for name in dependency_order(&targets, &selected) {
+        let target = &targets[name];
+
+        let mut cmd = rustdoc.command();
+        cmd.arg(data.src_dir.join(&target.src))
+            .arg("--crate-name")
+            .arg(&target.crate_name)
+            .arg("-o")
+            .arg(doc_dir.join(&target.out_dir));
+
+        let rustc_target = &target.rustc.target;
+        match &rustc_target.target_spec {
+            Some(spec) => cmd.arg("--target").arg(spec),
+            None => cmd.arg("--target").arg(&rustc_target.rustc_target),
+        };
+
+        if private {
+            cmd.arg("--document-private-items");
+        }
+
+        let mut lib_dirs = Vec::new();
+        for dep in &target.deps {
+            let dep = &targets[*dep];
+            let Some(lib) = link_artifact(dep) else {
+                continue;
+            };
+            let lib = build_dir.join(lib);
+            let mut extern_arg = OsString::from(format!("{}=", dep.crate_name));
+            extern_arg.push(&lib);
+            cmd.arg("--extern").arg(extern_arg);
+            if let Some(dir) = lib.parent() {
+                if !lib_dirs.iter().any(|d| d == dir) {
+                    lib_dirs.push(dir.to_path_buf());
+                }
+            }
+        }
+        // Dependencies of dependencies are found through the directories of the direct dependencies
+        for dir in &lib_dirs {
+            let mut arg = OsString::from("dependency=");
+            arg.push(dir);
+            cmd.arg("-L").arg(arg);
+        }
+
+        if verbose {
+            println!("Running {:?}", cmd);
+        } else {
+            println!("Documenting {}", name);
+        }
+
+        let status = cmd.status().map_err(|e| {
+            io::Error::new(e.kind(), format!("{}: {}", rustdoc.location.display(), e))
+        })?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "rustdoc failed to document {}: {}",
+                name, status
+            )));
+        }
+
+        crates.insert(
+            (target.out_dir.clone(), target.crate_name.clone()),
+            (*name).clone(),
+        );
+    }
+
+    // Crates documented by earlier runs keep their targets
+    for (name, target) in &targets {
+        crates
+            .entry((target.out_dir.clone(), target.crate_name.clone()))
+            .or_insert_with(|| (*name).clone());
+    }
+
+    register_docs(config.data_mut(), &build_dir, &crates)?;
+    config.cleanup()
+}
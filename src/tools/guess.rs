@@ -1,17 +1,93 @@
 use std::env::Args;
 use std::io;
 
+use target_tuples::{Environment, Target, OS};
+
+use crate::helpers::which;
+use crate::programs::rustc::rustc_detect_target;
+
+use super::{OptionArg, ToolOptions};
+
+pub const OPTIONS: ToolOptions = ToolOptions {
+    flags: &[
+        ("--help", OptionArg::None),
+        ("--version", OptionArg::None),
+        ("--canonicalize", OptionArg::None),
+        ("--rustc", OptionArg::None),
+    ],
+    install_dirs: false,
+    optional_targets: false,
+    positional: OptionArg::None,
+};
+
+fn help() {
+    println!(" [OPTIONS] [--canonicalize target...]");
+    println!("Prints the target tuple of the host, or canonicalizes the given target tuples");
+    println!("Options:");
+    println!("\t--help: Prints this message and exits");
+    println!("\t--version: Prints version information and exits");
+    println!("\t--canonicalize: Prints the canonical form of each target given, like config.sub");
+    println!("\t--rustc: Prints the name rustc uses for the target instead (uses RUSTC if set, or rustc from PATH)");
+}
+
+/// Parses a target alias in the style of config.sub, which accepts shorthand such as `x86_64-linux`
+fn canonicalize(alias: &str) -> io::Result<Target> {
+    let unrecognized = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Target `{}` is not recognized or cannot be parsed", alias),
+        )
+    };
+
+    let mut components = Vec::new();
+    for (i, comp) in alias.split('-').enumerate() {
+        match comp {
+            "" => return Err(unrecognized()),
+            // MinGW-w64 spells out the vendor and environment
+            "w64" if i == 1 => components.push("pc"),
+            "mingw32" | "mingw64" if i != 0 => components.extend(["windows", "gnu"]),
+            comp => components.push(comp),
+        }
+    }
+
+    let targ: Target = components
+        .join("-")
+        .parse()
+        .map_err(|_| unrecognized())?;
+
+    // config.sub assumes glibc for a bare Linux system
+    let env = match (targ.operating_system(), targ.environment()) {
+        (Some(OS::Linux), None) if targ.object_format().is_none() => Some(Environment::GNU),
+        (_, env) => env,
+    };
+
+    Ok(Target::from_components(
+        targ.arch(),
+        targ.vendor(),
+        targ.operating_system(),
+        env,
+        targ.object_format(),
+    ))
+}
+
 pub fn main(prg_name: &str, args: Args) -> io::Result<()> {
+    let mut canonicalize_mode = false;
+    let mut rustc_name = false;
+    let mut aliases = Vec::new();
+
     for opt in args {
         match &*opt {
             "--help" => {
-                super::print_help(prg_name, "guess", || println!());
+                super::print_help(prg_name, "guess", help);
                 return Ok(());
             }
             "--version" => {
                 super::print_version();
                 return Ok(());
             }
+            "--canonicalize" => canonicalize_mode = true,
+            "--rustc" => rustc_name = true,
+            x if canonicalize_mode && !x.starts_with("--") => aliases.push(opt),
             x => {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidInput,
@@ -21,7 +97,36 @@ pub fn main(prg_name: &str, args: Args) -> io::Result<()> {
         }
     }
 
-    let targ = crate::targ::guess::ident_target();
-    println!("{}", targ);
+    let targets = if canonicalize_mode {
+        if aliases.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--canonicalize requires at least one target",
+            ));
+        }
+        aliases
+            .iter()
+            .map(|alias| canonicalize(alias))
+            .collect::<io::Result<Vec<_>>>()?
+    } else {
+        vec![crate::targ::guess::ident_target()?]
+    };
+
+    if rustc_name {
+        let rustc = match std::env::var_os("RUSTC") {
+            Some(rustc) => rustc.into(),
+            None => which("rustc")?,
+        };
+
+        for targ in targets {
+            let rustc_targ = rustc_detect_target(&rustc, targ.to_string())?;
+            println!("{}", rustc_targ.rustc_target);
+        }
+    } else {
+        for targ in targets {
+            println!("{}", targ);
+        }
+    }
+
     Ok(())
 }
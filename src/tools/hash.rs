@@ -0,0 +1,279 @@
+use std::{
+    env::Args,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::PathBuf,
+};
+
+use crate::helpers::SplitOnceOwned;
+use crate::hash::{to_hex, DigestAlgorithm, FileHash, HashAlgorithm};
+
+use super::config::load_config_data;
+use super::{require_arg, OptionArg, ToolOptions};
+
+pub const OPTIONS: ToolOptions = ToolOptions {
+    flags: &[
+        ("--algorithm", OptionArg::Text),
+        ("--check", OptionArg::Text),
+        ("--keyed", OptionArg::None),
+        ("--cfg-dir", OptionArg::Dir),
+        ("--quiet", OptionArg::None),
+        ("--version", OptionArg::None),
+        ("--help", OptionArg::None),
+    ],
+    install_dirs: false,
+    optional_targets: false,
+    positional: OptionArg::None,
+};
+
+fn print_help() {
+    println!(" [OPTIONS] [--] [file...]");
+    println!("Prints the checksum of each file (or standard input, given `-` or no files) in the format of `sha256sum`");
+    println!("Options:");
+    println!("\t--algorithm ALG: Use ALG, one of sha1, sha224, sha256 (default), sha384, sha512, sha512-256, blake2b (as `b2sum`), or blake2b-256");
+    println!("\t--check FILE: Read checksums from FILE (`-` for standard input) and verify them, instead of printing checksums");
+    println!("\t--keyed: Hash with the key and algorithm of the build directory, reproducing the entries of its file cache");
+    println!("\t--cfg-dir DIR: The build directory used by --keyed (default: the current directory)");
+    println!("\t--quiet: With --check, do not print a line for every file that matches");
+    println!("\t--version: Print version information and exit");
+    println!("\t--help: Print this message and exit");
+}
+
+/// How each file is hashed
+enum Hasher {
+    Plain(DigestAlgorithm),
+    Keyed(HashAlgorithm, FileHash),
+}
+
+impl Hasher {
+    fn hash<R: Read>(&self, r: R) -> io::Result<String> {
+        match self {
+            Self::Plain(alg) => Ok(to_hex(&alg.digest_reader(r)?)),
+            Self::Keyed(alg, key) => Ok(alg.hash_reader(r, *key)?.to_string()),
+        }
+    }
+
+    fn hash_file(&self, name: &str) -> io::Result<String> {
+        if name == "-" {
+            self.hash(io::stdin().lock())
+        } else {
+            self.hash(File::open(name)?)
+        }
+    }
+}
+
+/// Escapes a file name as coreutils does, returning whether the line needs a leading `\`
+fn escape_name(name: &str) -> (bool, String) {
+    if !name.contains(['\\', '\n', '\r']) {
+        return (false, name.to_string());
+    }
+
+    let mut escaped = String::with_capacity(name.len() + 2);
+    for c in name.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    (true, escaped)
+}
+
+fn unescape_name(name: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                '\\' => unescaped.push('\\'),
+                'n' => unescaped.push('\n'),
+                'r' => unescaped.push('\r'),
+                _ => return None,
+            },
+            c => unescaped.push(c),
+        }
+    }
+    Some(unescaped)
+}
+
+/// Parses a line of a checksum file, `<hex>  <name>` or `<hex> *<name>`, into the checksum and the file name
+fn parse_line(line: &str) -> Option<(&str, String)> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+
+    let (sum, name) = line.split_once(' ')?;
+    let name = name.strip_prefix([' ', '*'])?;
+
+    if sum.is_empty() || !sum.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let name = if escaped {
+        unescape_name(name)?
+    } else {
+        name.to_string()
+    };
+
+    Some((sum, name))
+}
+
+fn check(hasher: &Hasher, list: &str, quiet: bool) -> io::Result<()> {
+    let reader: Box<dyn BufRead> = if list == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(list).map_err(|e| {
+            io::Error::new(e.kind(), format!("{}: {}", list, e))
+        })?))
+    };
+
+    let mut mismatched = 0;
+    let mut unreadable = 0;
+    let mut malformed = 0;
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.strip_suffix('\r').unwrap_or(&line);
+        if line.is_empty() {
+            continue;
+        }
+
+        let Some((expected, name)) = parse_line(line) else {
+            malformed += 1;
+            continue;
+        };
+
+        match hasher.hash_file(&name) {
+            Ok(sum) if sum.eq_ignore_ascii_case(expected) => {
+                if !quiet {
+                    println!("{}: OK", name);
+                }
+            }
+            Ok(_) => {
+                mismatched += 1;
+                println!("{}: FAILED", name);
+            }
+            Err(e) => {
+                unreadable += 1;
+                eprintln!("{}: {}", name, e);
+                println!("{}: FAILED open or read", name);
+            }
+        }
+    }
+
+    if malformed != 0 {
+        eprintln!(
+            "WARNING: {} line(s) of {} are improperly formatted",
+            malformed, list
+        );
+    }
+    if unreadable != 0 {
+        eprintln!("WARNING: {} listed file(s) could not be read", unreadable);
+    }
+    if mismatched != 0 {
+        eprintln!(
+            "WARNING: {} computed checksum(s) did NOT match",
+            mismatched
+        );
+    }
+
+    if mismatched + unreadable + malformed != 0 {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: verification failed", list),
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
+    let mut algorithm = DigestAlgorithm::Sha256;
+    let mut check_file = None;
+    let mut keyed = false;
+    let mut config_dir = PathBuf::new();
+    let mut quiet = false;
+    let mut files = Vec::new();
+
+    while let Some(mut arg) = args.next() {
+        // File names may contain `=`, so only options are split
+        let explicit = if arg.starts_with("--") {
+            arg.split_once_take("=")
+        } else {
+            None
+        };
+
+        match &*arg {
+            "--algorithm" => {
+                algorithm = require_arg(Some("--algorithm"), &mut args, explicit)?.parse()?;
+            }
+            "--check" => {
+                check_file = Some(require_arg(Some("--check"), &mut args, explicit)?);
+            }
+            "--keyed" => keyed = true,
+            "--cfg-dir" => {
+                config_dir = PathBuf::from(require_arg(Some("--cfg-dir"), &mut args, explicit)?);
+            }
+            "--quiet" => quiet = true,
+            "--version" => {
+                super::print_version();
+                return Ok(());
+            }
+            "--help" => {
+                super::print_help(prg_name, "hash", print_help);
+                return Ok(());
+            }
+            "--" => {
+                files.extend(&mut args);
+                break;
+            }
+            "-" => files.push(arg),
+            x if x.starts_with('-') => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unrecognized option {}", x),
+                ))
+            }
+            _ => files.push(arg),
+        }
+    }
+
+    let hasher = if keyed {
+        let data = load_config_data(&config_dir)?;
+        Hasher::Keyed(data.hash_algorithm, data.global_key)
+    } else {
+        Hasher::Plain(algorithm)
+    };
+
+    if let Some(list) = check_file {
+        if !files.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "--check does not accept files to hash",
+            ));
+        }
+        return check(&hasher, &list, quiet);
+    }
+
+    if files.is_empty() {
+        files.push("-".to_string());
+    }
+
+    let mut res = Ok(());
+    for name in files {
+        match hasher.hash_file(&name) {
+            Ok(sum) => {
+                let (escaped, printed) = escape_name(&name);
+                println!("{}{}  {}", if escaped { "\\" } else { "" }, sum, printed);
+            }
+            Err(e) => {
+                eprintln!("{}: {}", name, e);
+                res = Err(io::Error::other("failed"));
+            }
+        }
+    }
+
+    res
+}
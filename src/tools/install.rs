@@ -1,13 +1,156 @@
+use std::collections::{HashMap, HashSet};
 use std::env::Args;
+use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 
+use crate::config::{Artifact, ConfigData, TargetName};
 use crate::install::InstallDirs;
+use crate::log::{log, LogLevel};
 use crate::map::OrderedMap;
+use crate::programs::rpath::set_install_rpath;
 
 use crate::helpers::SplitOnceOwned;
 
-#[allow(unused_variables, unused_assignments)]
+use super::config::load_config_data;
+use super::{OptionArg, ToolOptions};
+
+pub const OPTIONS: ToolOptions = ToolOptions {
+    flags: &[
+        ("--install-dir", OptionArg::InstallDir),
+        ("--user-prefix", OptionArg::None),
+        ("--strip", OptionArg::None),
+        ("--strip-command", OptionArg::Text),
+        ("--install-command", OptionArg::Text),
+        ("--sysroot", OptionArg::Dir),
+        ("--dry-run", OptionArg::None),
+        ("--config-dir", OptionArg::Dir),
+        ("--version", OptionArg::None),
+        ("--help", OptionArg::None),
+    ],
+    install_dirs: true,
+    optional_targets: false,
+    positional: OptionArg::Target,
+};
+
+fn print_help() {
+    println!(" [OPTIONS] [--] [target...]");
+    println!("Installs the artifacts of a build directory (or of the given targets) into the installation directories");
+    println!("Binaries that find libraries of the build directory through their rpath are changed to find them in libdir");
+    println!("Options:");
+    println!("\t--config-dir DIR: The build directory (default: the current directory)");
+    println!("\t--install-dir KEY=PATH: Sets the extra installation directory KEY to PATH");
+    println!("\t--user-prefix: Install into ~/.local");
+    println!("\t--strip: Strip debug information from installed ELF files");
+    println!("\t--strip-command CMD: Strip with CMD (default: strip)");
+    println!("\t--install-command CMD: Copy each file with CMD, which is given the source and destination, instead of copying it directly");
+    println!("\t--sysroot DIR: Install into DIR, as though it were the root directory");
+    println!("\t--dry-run: Print the files that would be installed, and install nothing");
+    println!("\t--version: Print version information and exit");
+    println!("\t--help: Print this message and exit");
+    println!("Installation Directories (default: those given to `autobuild config`):");
+    for (flag, desc) in InstallDirs::ARGS {
+        println!("\t{} DIR: {}", flag, desc);
+    }
+}
+
+struct Installer<'a> {
+    dry_run: bool,
+    install_command: Option<&'a str>,
+    strip_command: Option<&'a str>,
+}
+
+impl Installer<'_> {
+    /// Installs the file or directory tree `src` as `dest`
+    fn install(&self, src: &Path, dest: &Path) -> io::Result<()> {
+        if fs::metadata(src)?.is_dir() {
+            if !self.dry_run {
+                fs::create_dir_all(dest)?;
+            }
+            let mut entries = fs::read_dir(src)?.collect::<io::Result<Vec<_>>>()?;
+            entries.sort_by_key(|ent| ent.file_name());
+            for ent in entries {
+                self.install(&ent.path(), &dest.join(ent.file_name()))?;
+            }
+            return Ok(());
+        }
+
+        println!("Installing {} to {}", src.display(), dest.display());
+        if self.dry_run {
+            return Ok(());
+        }
+
+        match self.install_command {
+            Some(cmd) => run(Command::new(cmd).arg(src).arg(dest))?,
+            None => {
+                // The old file may be running, so it is replaced rather than overwritten
+                let _ = fs::remove_file(dest);
+                fs::copy(src, dest)?;
+            }
+        }
+
+        if let Some(strip) = self.strip_command {
+            if is_elf(dest)? {
+                run(Command::new(strip).arg("--strip-debug").arg(dest))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn install_alias(&self, target: &Path, alias: &Path) -> io::Result<()> {
+        println!("Linking {} to {}", alias.display(), target.display());
+        if self.dry_run {
+            return Ok(());
+        }
+
+        let _ = fs::remove_file(alias);
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(target.file_name().unwrap(), alias)
+        }
+        #[cfg(not(unix))]
+        {
+            fs::copy(target, alias).map(drop)
+        }
+    }
+}
+
+fn run(cmd: &mut Command) -> io::Result<()> {
+    let status = cmd.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{} failed: {}",
+            cmd.get_program().to_string_lossy(),
+            status
+        )))
+    }
+}
+
+fn is_elf(path: &Path) -> io::Result<bool> {
+    use io::Read;
+    let mut magic = [0u8; 4];
+    let mut file = fs::File::open(path)?;
+    Ok(file.read_exact(&mut magic).is_ok() && magic == *b"\x7fELF")
+}
+
+/// The installation directories of `art`: those of the configuration, overridden by `overrides`, and for the target `art` was built for
+fn artifact_dirs(config: &ConfigData, art: &Artifact, overrides: &InstallDirs) -> InstallDirs {
+    let mut dirs = config.dirs.install_dirs.clone();
+    dirs.set_from(overrides);
+    if let Some(tuple) = &art.tuple {
+        let name = config
+            .targets
+            .get(tuple)
+            .map_or(&**tuple, |targ| targ.get_name());
+        dirs.set_target(name);
+    }
+    dirs
+}
+
 pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
     let mut config_dir = PathBuf::new();
     let mut install_dirs = InstallDirs::default();
@@ -89,6 +232,14 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
                     explicit,
                 )?);
             }
+            "--version" => {
+                super::print_version();
+                return Ok(());
+            }
+            "--help" => {
+                super::print_help(prg_name, "install", print_help);
+                return Ok(());
+            }
             "--" => break,
             x if x.starts_with("--") => {
                 install_dirs
@@ -109,5 +260,87 @@ pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
 
     targets.extend(args);
 
+    let config = load_config_data(&config_dir)?;
+    let selected = targets
+        .iter()
+        .map(|name| TargetName::from_arg(name))
+        .collect::<io::Result<HashSet<_>>>()?;
+
+    let installer = Installer {
+        dry_run,
+        install_command: install_command.as_deref(),
+        strip_command: strip_debug.then(|| strip_command.as_deref().unwrap_or("strip")),
+    };
+
+    for art in &config.artifacts {
+        if !selected.is_empty() && !selected.contains(&art.target) {
+            continue;
+        }
+        let Some(base) = &art.install_base else {
+            continue;
+        };
+
+        let dirs = artifact_dirs(&config, art, &install_dirs);
+        let mut keys = HashMap::new();
+        for (key, dir) in dirs.as_canonical_env() {
+            keys.insert(key.to_string(), dir.display().to_string());
+        }
+        for (key, dir) in config.dirs.rest.iter() {
+            keys.insert(key.clone(), dirs.prefix().join(dir).display().to_string());
+        }
+        for (key, dir) in extra_install_dirs.iter() {
+            keys.insert(key.clone(), dirs.prefix().join(dir).display().to_string());
+        }
+
+        let mut dest_dir = String::new();
+        base.eval(&dirs.prefix().display().to_string(), &keys, &mut dest_dir)?;
+        let dest_dir = match &sysroot {
+            Some(sysroot) => {
+                let dest_dir = Path::new(&dest_dir);
+                sysroot.join(dest_dir.strip_prefix("/").unwrap_or(dest_dir))
+            }
+            None => PathBuf::from(dest_dir),
+        };
+
+        let src = config_dir.join(&art.path);
+        let dest = dest_dir.join(art.path.file_name().unwrap_or(art.path.as_os_str()));
+
+        if !dry_run {
+            fs::create_dir_all(&dest_dir)?;
+        }
+        installer.install(&src, &dest)?;
+
+        if art.local_rpath.is_some() && !dry_run {
+            let targ = art
+                .tuple
+                .as_deref()
+                .and_then(|tuple| config.targets.get(tuple))
+                .unwrap_or(&config.targets.host);
+            // The build rpath is only padded for the directories given to `autobuild config`
+            let rewritten = set_install_rpath(&dest, targ, &dirs).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "{} (configure with the installation directories used to install, and rebuild)",
+                        e
+                    ),
+                )
+            })?;
+            if !rewritten {
+                log!(
+                    LogLevel::Warning,
+                    "Cannot rewrite the rpath of {} for {}; it still refers to the build directory",
+                    dest.display(),
+                    targ.get_name()
+                );
+            }
+        }
+
+        for alias in &art.aliases {
+            let alias = dest_dir.join(alias.file_name().unwrap_or(alias.as_os_str()));
+            installer.install_alias(&dest, &alias)?;
+        }
+    }
+
     Ok(())
 }
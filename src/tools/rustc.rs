@@ -1,6 +1,13 @@
 use std::{env::Args, io, path::PathBuf};
 
-use super::require_arg;
+use super::{require_arg, OptionArg, ToolOptions};
+
+pub const OPTIONS: ToolOptions = ToolOptions {
+    flags: &[],
+    install_dirs: false,
+    optional_targets: false,
+    positional: OptionArg::None,
+};
 
 #[allow(unused_variables)]
 pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
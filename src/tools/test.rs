@@ -0,0 +1,334 @@
+use std::{
+    collections::VecDeque,
+    env::Args,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use target_tuples::Target;
+
+use crate::config::{BuildTargetStep, BuildTestInfo, ConfigData, TargetName};
+use crate::jobserv::JobServer;
+use crate::log::{log, trace, LogLevel};
+
+use super::config::load_config_data;
+use super::{require_arg, OptionArg, ToolOptions};
+
+pub const OPTIONS: ToolOptions = ToolOptions {
+    flags: &[
+        ("--cfg-dir", OptionArg::Dir),
+        ("--jobs", OptionArg::Text),
+        ("--verbose", OptionArg::None),
+        ("--version", OptionArg::None),
+        ("--help", OptionArg::None),
+    ],
+    install_dirs: false,
+    optional_targets: false,
+    positional: OptionArg::Target,
+};
+
+fn print_help() {
+    println!(" [OPTIONS] [target...] [-- harness-args...]");
+    println!("Runs the test harnesses built by `autobuild build`, and summarizes their results");
+    println!("If targets (as `path:name`, or `name` for a target of the root manifest) are given, only those tests are run");
+    println!("Arguments after `--`, such as test name filters, are passed to every harness");
+    println!("Options:");
+    println!("\t--cfg-dir DIR: The build directory (default: the current directory)");
+    println!("\t--jobs N: Run up to N harnesses at once (default: the jobserver of the parent `make`, or the number of CPUs)");
+    println!("\t--verbose: Print the output of every harness, not only those that fail");
+    println!("\t--version: Print version information and exit");
+    println!("\t--help: Print this message and exit");
+}
+
+struct TestJob<'a> {
+    target: &'a TargetName,
+    harness: PathBuf,
+    /// The target the harness was built for, which decides the runner it runs through
+    tuple: &'a Target,
+    cwd: PathBuf,
+    info: &'a BuildTestInfo,
+}
+
+#[derive(Default)]
+struct TestResult {
+    passed: u64,
+    failed: u64,
+    ignored: u64,
+    /// Whether the harness exited successfully
+    success: bool,
+    output: String,
+}
+
+/// Adds the counts of a libtest summary line, `test result: ok. 3 passed; 0 failed; 1 ignored; ...`
+fn parse_summary(line: &str, res: &mut TestResult) {
+    let Some(rest) = line.trim().strip_prefix("test result: ") else {
+        return;
+    };
+    let Some((_, counts)) = rest.split_once(". ") else {
+        return;
+    };
+
+    for count in counts.split(';') {
+        let Some((n, kind)) = count.trim().split_once(' ') else {
+            continue;
+        };
+        let Ok(n) = n.parse::<u64>() else {
+            continue;
+        };
+        match kind {
+            "passed" => res.passed += n,
+            "failed" => res.failed += n,
+            "ignored" => res.ignored += n,
+            _ => {}
+        }
+    }
+}
+
+fn run_test(config: &ConfigData, job: &TestJob, harness_args: &[String]) -> TestResult {
+    trace!(run_test);
+    let mut cmd = config.run_command(job.tuple, &job.harness);
+    cmd.args(job.info.args.iter().flatten())
+        .args(harness_args)
+        .current_dir(&job.cwd);
+
+    let mut res = TestResult::default();
+    match cmd.output() {
+        Ok(output) => {
+            res.success = output.status.success();
+            let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+            text.push_str(&String::from_utf8_lossy(&output.stderr));
+            for line in text.lines() {
+                parse_summary(line, &mut res);
+            }
+            res.output = text;
+            if !res.success && res.failed == 0 {
+                res.output
+                    .push_str(&format!("{}: {}\n", job.harness.display(), output.status));
+            }
+        }
+        Err(e) => res.output = format!("{}: {}\n", job.harness.display(), e),
+    }
+
+    res
+}
+
+/// Runs every job, with as many at once as `jobserver` allows. The caller's implicit token runs jobs too
+fn run_all(
+    config: &ConfigData,
+    jobs: &[TestJob],
+    harness_args: &[String],
+    jobserver: &JobServer,
+) -> io::Result<Vec<TestResult>> {
+    let queue = Mutex::new((0..jobs.len()).collect::<VecDeque<_>>());
+    let results = Mutex::new((0..jobs.len()).map(|_| None).collect::<Vec<_>>());
+    let acquire_err = Mutex::new(None);
+    // The number of workers still waiting for a token, or `None` once the jobs are done and those workers were woken up
+    let waiting = Mutex::new(Some(jobs.len().saturating_sub(1)));
+
+    let work = || {
+        while let Some(idx) = queue.lock().unwrap().pop_front() {
+            let res = run_test(config, &jobs[idx], harness_args);
+            results.lock().unwrap()[idx] = Some(res);
+        }
+    };
+
+    std::thread::scope(|s| {
+        for _ in 1..jobs.len() {
+            s.spawn(|| {
+                let token = match jobserver.acquire() {
+                    Ok(token) => token,
+                    Err(e) => {
+                        *acquire_err.lock().unwrap() = Some(e);
+                        return;
+                    }
+                };
+                {
+                    let mut waiting = waiting.lock().unwrap();
+                    match &mut *waiting {
+                        Some(n) => *n -= 1,
+                        // The token was (or stands for) one of those added to wake this worker
+                        None => return token.forget(),
+                    }
+                }
+                work();
+            });
+        }
+        work();
+
+        // Every job has started, so workers that are still waiting would never get anything to do
+        let stranded = waiting.lock().unwrap().take().unwrap_or(0);
+        if let Err(e) = jobserver.wake_waiters(stranded) {
+            *acquire_err.lock().unwrap() = Some(e);
+        }
+    });
+
+    if let Some(e) = acquire_err.into_inner().unwrap() {
+        return Err(e);
+    }
+
+    Ok(results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(Option::unwrap)
+        .collect())
+}
+
+pub fn main(prg_name: &str, mut args: Args) -> io::Result<()> {
+    let mut config_dir = PathBuf::new();
+    let mut jobs = None;
+    let mut verbose = false;
+    let mut targets = Vec::new();
+    let mut harness_args = Vec::new();
+
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--cfg-dir" => {
+                config_dir = PathBuf::from(require_arg(Some("--cfg-dir"), &mut args, None)?);
+            }
+            "--jobs" => {
+                let n = require_arg(Some("--jobs"), &mut args, None)?;
+                jobs = Some(n.parse::<usize>().ok().filter(|n| *n != 0).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("--jobs expects a positive integer, got `{}`", n),
+                    )
+                })?);
+            }
+            "--verbose" => verbose = true,
+            "--version" => {
+                super::print_version();
+                return Ok(());
+            }
+            "--help" => {
+                super::print_help(prg_name, "test", print_help);
+                return Ok(());
+            }
+            "--" => {
+                harness_args.extend(&mut args);
+                break;
+            }
+            x if x.starts_with('-') => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unrecognized option {}", x),
+                ))
+            }
+            _ => targets.push(TargetName::from_arg(&arg)?),
+        }
+    }
+
+    let config = load_config_data(&config_dir)?;
+    let build_dir = fs::canonicalize(if config_dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        &config_dir
+    })?;
+
+    for target in &targets {
+        let is_test = matches!(
+            config.build_database.get(target).map(|info| &info.step),
+            Some(BuildTargetStep::Build(build)) if build.test.is_some()
+        );
+        if !is_test {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No such test target {}", target),
+            ));
+        }
+    }
+
+    let mut test_jobs = Vec::new();
+    for (name, info) in &config.build_database {
+        let BuildTargetStep::Build(build) = &info.step else {
+            continue;
+        };
+        let Some(test) = &build.test else {
+            continue;
+        };
+        if !targets.is_empty() && !targets.contains(name) {
+            continue;
+        }
+        let Some(harness) = build.primary_artifacts.first() else {
+            continue;
+        };
+
+        test_jobs.push(TestJob {
+            target: name,
+            harness: build_dir.join(harness),
+            tuple: config.output_target(info),
+            cwd: config.src_dir.join(&name.base_path),
+            info: test,
+        });
+    }
+
+    let default_jobs = || std::thread::available_parallelism().map_or(1, |n| n.get());
+    let jobserver = match jobs {
+        Some(jobs) => JobServer::new(jobs)?,
+        None => match JobServer::from_env() {
+            Ok(Some(jobserver)) => jobserver,
+            Ok(None) => JobServer::new(default_jobs())?,
+            Err(e) => {
+                log!(
+                    LogLevel::Warning,
+                    "Ignoring the jobserver of the parent make: {}",
+                    e
+                );
+                JobServer::new(default_jobs())?
+            }
+        },
+    };
+
+    let results = run_all(&config, &test_jobs, &harness_args, &jobserver)?;
+
+    let mut total = TestResult {
+        success: true,
+        ..Default::default()
+    };
+    let mut failed_targets = 0;
+
+    for (job, res) in test_jobs.iter().zip(&results) {
+        if verbose || !res.success {
+            println!("---- {} ----", job.target);
+            print!("{}", res.output);
+        }
+    }
+
+    println!("Test summary:");
+    for (job, res) in test_jobs.iter().zip(&results) {
+        println!(
+            "\t{}: {}. {} passed; {} failed; {} ignored",
+            job.target,
+            if res.success { "ok" } else { "FAILED" },
+            res.passed,
+            res.failed,
+            res.ignored
+        );
+        total.passed += res.passed;
+        total.failed += res.failed;
+        total.ignored += res.ignored;
+        if !res.success {
+            total.success = false;
+            failed_targets += 1;
+        }
+    }
+    println!(
+        "Total: {}. {} passed; {} failed; {} ignored ({} of {} targets failed)",
+        if total.success { "ok" } else { "FAILED" },
+        total.passed,
+        total.failed,
+        total.ignored,
+        failed_targets,
+        test_jobs.len()
+    );
+
+    if total.success {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{} test target(s) failed",
+            failed_targets
+        )))
+    }
+}
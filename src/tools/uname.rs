@@ -2,6 +2,8 @@ use crate::targ::guess::uname::uname;
 
 use std::{collections::HashSet, env::Args, io};
 
+use super::{OptionArg, ToolOptions};
+
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 enum PrintType {
     Kernel,
@@ -12,6 +14,23 @@ enum PrintType {
     Sys,
 }
 
+pub const OPTIONS: ToolOptions = ToolOptions {
+    flags: &[
+        ("--help", OptionArg::None),
+        ("--version", OptionArg::None),
+        ("--all", OptionArg::None),
+        ("--kernel-name", OptionArg::None),
+        ("--nodename", OptionArg::None),
+        ("--kernel-release", OptionArg::None),
+        ("--kernel-version", OptionArg::None),
+        ("--machine", OptionArg::None),
+        ("--operating-system", OptionArg::None),
+    ],
+    install_dirs: false,
+    optional_targets: false,
+    positional: OptionArg::None,
+};
+
 fn help() {}
 
 pub fn main(prg_name: &str, args: Args) -> io::Result<()> {
@@ -3,6 +3,15 @@ use std::io;
 
 use crate::helpers;
 
+use super::{OptionArg, ToolOptions};
+
+pub const OPTIONS: ToolOptions = ToolOptions {
+    flags: &[("--help", OptionArg::None), ("--version", OptionArg::None)],
+    install_dirs: false,
+    optional_targets: false,
+    positional: OptionArg::None,
+};
+
 fn help_subcommands() {
     println!("which [OPTIONS] filename...");
     println!("Finds programs on PATH which can be executed on shell");